@@ -165,6 +165,11 @@ class FrameInterpolator(AppletBase):
 
         rendered_imgs.append(im_end)    
 
+        # full resolution frames for callers that assemble the video themselves
+        if params.get('frames_dir'):
+            for i, frame in enumerate(rendered_imgs):
+                frame.save(os.path.join(params['frames_dir'], "frame_%04d.png" % i))
+
         fn = "/tmp/" + str(random.randint(0,10000)) + ".gif"
         rendered_imgs[0].save(fn, format='GIF', append_images=rendered_imgs[1:] , save_all=True , loop=0)
         self.update_state("outputs" , [get_output_img(fn , save_ext='.gif' , is_save=True)] )
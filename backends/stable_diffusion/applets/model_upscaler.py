@@ -0,0 +1,85 @@
+from .applets import AppletBase
+from .form_utils import get_file_textbox, get_output_text, get_output_img
+import numpy as np
+import os
+from PIL import Image
+
+
+class ModelUpscaler(AppletBase):
+    """Upscales an image with a TensorFlow SavedModel such as ESRGAN.
+
+    The model takes a float32 [1, H, W, 3] batch in 0-255 and returns the
+    upscaled batch. The app resamples the result to the scale it asked for.
+    """
+
+    applet_name = "model_upscale"
+    applet_title = "Upscaler"
+    applet_description = "Upscale an image with a super-resolution model"
+
+    # Images are upscaled in tiles of this size to keep memory bounded
+    tile_size = 256
+    tile_overlap = 16
+
+    loaded_model = None
+    loaded_model_path = None
+
+    def get_input_form(self):
+        return [
+            get_file_textbox("input_image_path", path_type="", title="Image", description="The image to upscale."),
+            get_file_textbox("model_path", path_type="folder", title="Model", description="SavedModel directory of the upscaler."),
+            get_file_textbox("output_path", path_type="", title="Output", description="Where the upscaled PNG is written."),
+        ]
+
+    def load_model(self, model_path):
+        import tensorflow as tf
+
+        if self.loaded_model_path != model_path:
+            self.loaded_model = tf.saved_model.load(model_path)
+            self.loaded_model_path = model_path
+        return self.loaded_model
+
+    def run_model(self, model, tile):
+        import tensorflow as tf
+
+        batch = tf.convert_to_tensor(tile[None].astype(np.float32))
+        out = model(batch)
+        return np.clip(np.asarray(out)[0], 0, 255)
+
+    def run(self, params):
+        input_path = params.get("input_image_path") or ""
+        model_path = params.get("model_path") or ""
+        output_path = params.get("output_path") or ""
+
+        if not os.path.isfile(input_path):
+            raise ValueError("Input image not found: " + input_path)
+        if not os.path.exists(model_path):
+            raise ValueError("Upscaler model not found, set upscaler_model_path: " + model_path)
+        if output_path == "":
+            raise ValueError("No output path given")
+
+        self.update_state("outputs", [get_output_text("Loading upscaler...")])
+        model = self.load_model(model_path)
+
+        img = np.array(Image.open(input_path).convert("RGB"))
+        h, w = img.shape[:2]
+        step = self.tile_size - 2 * self.tile_overlap
+        out = None
+        scale = None
+
+        for y in range(0, h, step):
+            for x in range(0, w, step):
+                y0, x0 = max(y - self.tile_overlap, 0), max(x - self.tile_overlap, 0)
+                y1, x1 = min(y + step + self.tile_overlap, h), min(x + step + self.tile_overlap, w)
+                up = self.run_model(model, img[y0:y1, x0:x1])
+
+                if out is None:
+                    scale = up.shape[0] // (y1 - y0)
+                    out = np.zeros((h * scale, w * scale, 3), dtype=np.float32)
+
+                # keep only the tile's own area, the overlap is just context
+                ty, tx = (y - y0) * scale, (x - x0) * scale
+                th, tw = (min(y + step, h) - y) * scale, (min(x + step, w) - x) * scale
+                out[y * scale:y * scale + th, x * scale:x * scale + tw] = up[ty:ty + th, tx:tx + tw]
+
+        Image.fromarray(out.round().astype(np.uint8)).save(output_path)
+        self.update_state("outputs", [get_output_text("Upscaled %dx" % scale), get_output_img(output_path)])
@@ -33,6 +33,7 @@ from stable_diffusion.utils.utils import get_sd_run_from_dict
 
 from applets.applets import register_applet , run_applet
 from applets.frame_interpolator import FrameInterpolator
+from applets.model_upscaler import ModelUpscaler
 # get the model interface form the environ
 USE_DUMMY_INTERFACE = False
 if  USE_DUMMY_INTERFACE :
@@ -136,6 +137,7 @@ def diffusion_bee_main():
 
     time.sleep(2)
     register_applet(model_container , FrameInterpolator)
+    register_applet(model_container , ModelUpscaler)
 
     print("sdbk mltl Loading Model")
 
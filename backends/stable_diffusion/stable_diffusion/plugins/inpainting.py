@@ -72,7 +72,7 @@ class MaskInpainting(BasePlugin):
 
             latent_proper = np.copy(sd_run.encoded_img_orig)
 
-            noise = self.parent.get_modded_noise(sd_run.seed , latent_proper.shape , seed_type=sd_run.seed_type, small_mod_seed=sd_run.small_mod_seed  )
+            noise = self.parent.get_modded_noise(sd_run.seed , latent_proper.shape , seed_type=sd_run.seed_type, small_mod_seed=sd_run.small_mod_seed, small_mod_strength=sd_run.small_mod_strength  )
             latent_proper = self.parent.scheduler.add_noise(latent_proper, noise, np.array([self.parent.t_to_i(sd_run.current_t)] * sd_run.batch_size, dtype=np.int64 ) )
 
             sd_run.next_latents = (latent_proper  * sd_run.processed_mask_downscaled) + (sd_run.next_latents * (1 - sd_run.processed_mask_downscaled))
@@ -25,6 +25,7 @@ class SDRun():
     seed:int=None
     seed_type:str="np"
     small_mod_seed:int=None
+    small_mod_strength:float=None  # share of the noise taken from small_mod_seed, 0.01 when not given
     img_id:int = 0
     scheduler:str = "__default_for_model__"
 
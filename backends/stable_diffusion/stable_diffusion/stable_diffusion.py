@@ -245,10 +245,12 @@ class StableDiffusion(StableDiffusionPluginMixin):
         else:
             raise ValueError("Invalid seed type")
         
-    def get_modded_noise(self, seed, shape ,seed_type , small_mod_seed):
+    def get_modded_noise(self, seed, shape ,seed_type , small_mod_seed, small_mod_strength=None):
         noise = self.get_noise(seed, shape , seed_type)
         if small_mod_seed is not None and small_mod_seed >= 0:
-            nmask = (np.random.RandomState(small_mod_seed).rand(*noise.shape ) > 0.99)
+            if small_mod_strength is None:
+                small_mod_strength = 0.01
+            nmask = (np.random.RandomState(small_mod_seed).rand(*noise.shape ) > 1 - small_mod_strength)
             noise = noise*(1-nmask) + nmask*self.get_noise(small_mod_seed, noise.shape , seed_type=seed_type )
 
         return noise
@@ -272,7 +274,7 @@ class StableDiffusion(StableDiffusionPluginMixin):
         n_w = sd_run.img_width // 8
   
         if not sd_run.starting_img_given:
-            latent_np = self.get_modded_noise(sd_run.seed ,(sd_run.batch_size, n_h, n_w, 4) ,  seed_type=sd_run.seed_type, small_mod_seed=sd_run.small_mod_seed  )
+            latent_np = self.get_modded_noise(sd_run.seed ,(sd_run.batch_size, n_h, n_w, 4) ,  seed_type=sd_run.seed_type, small_mod_seed=sd_run.small_mod_seed, small_mod_strength=sd_run.small_mod_strength  )
 
             if self.debug_output_path is not None:
                 log_object(latent_np , self.debug_output_path  , key="latent_np")
@@ -292,7 +294,7 @@ class StableDiffusion(StableDiffusionPluginMixin):
 
             start_timestep = np.array([self.t_to_i(sd_run.start_timestep)] * sd_run.batch_size, dtype=np.int64 )
            
-            noise = self.get_modded_noise(sd_run.seed , latent.shape , seed_type=sd_run.seed_type, small_mod_seed=sd_run.small_mod_seed  )
+            noise = self.get_modded_noise(sd_run.seed , latent.shape , seed_type=sd_run.seed_type, small_mod_seed=sd_run.small_mod_seed, small_mod_strength=sd_run.small_mod_strength  )
 
             if self.debug_output_path is not None:
                 log_object(noise , self.debug_output_path  , key="noise_e")
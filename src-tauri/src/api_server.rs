@@ -0,0 +1,442 @@
+// Opt-in local HTTP API compatible with AUTOMATIC1111's `/sdapi/v1` endpoints
+//
+// Lets existing scripts and tools that talk to an SD web UI use this app as their
+// backend. Payloads are translated into `ImageGenerationRequest`s and run through
+// the normal generation path; images come back base64 encoded. Every request
+// must carry the configured token, either as `Authorization: Bearer <token>` or
+// as the password of HTTP basic auth (what A1111 clients send for `--api-auth`).
+
+use crate::{load_settings, run_generation, AppSettings, DiffusionError, ImageGenerationRequest};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+struct RunningServer {
+    address: SocketAddr,
+    token: String,
+    shutdown: oneshot::Sender<()>,
+}
+
+static API_SERVER: Mutex<Option<RunningServer>> = Mutex::new(None);
+
+// Images one request may ask for (batch_size * n_iter); each is a backend job
+const MAX_IMAGES_PER_REQUEST: u32 = 100;
+
+#[derive(Clone)]
+struct ServerState {
+    token: Arc<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiServerStatus {
+    pub running: bool,
+    pub address: Option<String>,
+}
+
+// Subset of the A1111 txt2img / img2img payload that maps onto our backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct SdApiRequest {
+    prompt: String,
+    negative_prompt: String,
+    width: u32,
+    height: u32,
+    steps: u32,
+    cfg_scale: f32,
+    seed: i64,
+    batch_size: u32,
+    n_iter: u32,
+    init_images: Vec<String>,
+    denoising_strength: f32,
+}
+
+impl Default for SdApiRequest {
+    fn default() -> Self {
+        Self {
+            prompt: String::new(),
+            negative_prompt: String::new(),
+            width: 512,
+            height: 512,
+            steps: 20,
+            cfg_scale: 7.0,
+            seed: -1,
+            batch_size: 1,
+            n_iter: 1,
+            init_images: Vec::new(),
+            denoising_strength: 0.75,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SdApiResponse {
+    images: Vec<String>,
+    parameters: SdApiRequest,
+    /// A1111 returns generation info as a JSON encoded string
+    info: String,
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    fn unprocessable(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            message: message.into(),
+        }
+    }
+
+    fn internal(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<DiffusionError> for ApiError {
+    fn from(e: DiffusionError) -> Self {
+        match e {
+            DiffusionError::Validation { .. } => Self::bad_request(e.message()),
+            _ => Self::internal(e.message()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = json!({
+            "error": self.status.canonical_reason().unwrap_or("Error"),
+            "detail": self.message,
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+pub fn validate_settings(settings: &AppSettings) -> Result<(), DiffusionError> {
+    if !settings.api_server_enabled {
+        return Ok(());
+    }
+    parse_bind_address(settings)?;
+    if settings.api_server_token.trim().len() < 16 {
+        return Err(DiffusionError::validation(
+            "api_server_token",
+            "API server token must be at least 16 characters",
+        ));
+    }
+    Ok(())
+}
+
+fn parse_bind_address(settings: &AppSettings) -> Result<SocketAddr, DiffusionError> {
+    settings.api_server_bind_address.parse().map_err(|_| {
+        DiffusionError::validation(
+            "api_server_bind_address",
+            format!("Invalid API server bind address: {}", settings.api_server_bind_address),
+        )
+    })
+}
+
+pub fn status() -> ApiServerStatus {
+    let server = API_SERVER.lock().ok();
+    let address = server
+        .as_ref()
+        .and_then(|s| s.as_ref())
+        .map(|s| s.address.to_string());
+    ApiServerStatus {
+        running: address.is_some(),
+        address,
+    }
+}
+
+/// Starts, stops or restarts the server so it matches `settings`.
+pub async fn apply_settings(settings: &AppSettings) -> Result<(), DiffusionError> {
+    validate_settings(settings)?;
+
+    let wanted = if settings.api_server_enabled {
+        let address = parse_bind_address(settings)?;
+        Some((address, settings.api_server_token.trim().to_string()))
+    } else {
+        None
+    };
+
+    {
+        let mut server = API_SERVER.lock().map_err(|_| DiffusionError::lock("API server"))?;
+        let unchanged = match (server.as_ref(), wanted.as_ref()) {
+            (Some(running), Some((address, token))) => running.address == *address && running.token == *token,
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            return Ok(());
+        }
+        if let Some(running) = server.take() {
+            let _ = running.shutdown.send(());
+            log::info!("API server on {} stopped", running.address);
+        }
+    }
+
+    let Some((address, token)) = wanted else {
+        return Ok(());
+    };
+
+    if !address.ip().is_loopback() {
+        log::warn!("API server is listening on non-loopback address {}", address);
+    }
+
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .map_err(|e| DiffusionError::ProcessError(format!("Failed to bind API server to {}: {}", address, e)))?;
+
+    let state = ServerState {
+        token: Arc::new(token.clone()),
+    };
+    let app = Router::new()
+        .route("/sdapi/v1/txt2img", post(txt2img))
+        .route("/sdapi/v1/img2img", post(img2img))
+        .route("/sdapi/v1/sd-models", get(sd_models))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state);
+
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let server = axum::serve(listener, app).with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        });
+        if let Err(e) = server.await {
+            log::error!("API server error: {}", e);
+        }
+    });
+
+    log::info!("API server listening on http://{}", address);
+    let mut server = API_SERVER.lock().map_err(|_| DiffusionError::lock("API server"))?;
+    *server = Some(RunningServer {
+        address,
+        token,
+        shutdown,
+    });
+    Ok(())
+}
+
+async fn require_token(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(presented_token)
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), state.token.as_bytes()));
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            Json(json!({ "error": "Unauthorized", "detail": "Missing or invalid API token" })),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+fn presented_token(authorization: &str) -> Option<String> {
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        return Some(token.trim().to_string());
+    }
+    // Basic auth: any user name, the token as password
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64.decode(encoded.trim()).ok()?).ok()?;
+    decoded.split_once(':').map(|(_, password)| password.to_string())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn txt2img(Json(payload): Json<SdApiRequest>) -> Result<Json<SdApiResponse>, ApiError> {
+    generate(payload, None).await
+}
+
+async fn img2img(Json(payload): Json<SdApiRequest>) -> Result<Json<SdApiResponse>, ApiError> {
+    let encoded = payload
+        .init_images
+        .first()
+        .ok_or_else(|| ApiError::bad_request("img2img requires at least one image in init_images"))?;
+    let init_image = write_init_image(encoded)?;
+    let result = generate(payload, Some(init_image.clone())).await;
+    let _ = std::fs::remove_file(&init_image);
+    result
+}
+
+async fn sd_models() -> Result<Json<Value>, ApiError> {
+    let models = crate::get_models().await?;
+    let list: Vec<Value> = models
+        .iter()
+        .map(|name| json!({ "title": name, "model_name": name, "filename": name }))
+        .collect();
+    Ok(Json(Value::Array(list)))
+}
+
+async fn generate(
+    payload: SdApiRequest,
+    init_image: Option<PathBuf>,
+) -> Result<Json<SdApiResponse>, ApiError> {
+    let count = image_count(&payload)?;
+    let settings = load_settings();
+    let first_seed = u32::try_from(payload.seed).ok().filter(|s| *s > 0);
+
+    let mut images = Vec::new();
+    let mut seeds = Vec::new();
+    let mut prompts = Vec::new();
+    // One backend job per image; like A1111, batch images use consecutive seeds
+    for i in 0..count {
+        let request = ImageGenerationRequest {
+            prompt: payload.prompt.clone(),
+            img_width: payload.width,
+            img_height: payload.height,
+            num_imgs: 1,
+            num_inference_steps: payload.steps,
+            guidance_scale: payload.cfg_scale,
+            seed: first_seed.or(seeds.first().copied()).map(|s: u32| s.wrapping_add(i)),
+            negative_prompt: payload.negative_prompt.clone(),
+            input_image: init_image.as_ref().map(|p| p.to_string_lossy().to_string()),
+            input_image_strength: init_image.as_ref().map(|_| 1.0 - payload.denoising_strength.clamp(0.0, 1.0)),
+            ..ImageGenerationRequest::new(String::new())
+        };
+
+        let response = run_generation(request, &settings).await?;
+        let bytes = std::fs::read(&response.generated_img_path)
+            .map_err(|e| ApiError::internal(format!("Failed to read generated image: {}", e)))?;
+        images.push(BASE64.encode(bytes));
+        seeds.extend(response.seed);
+        prompts.push(response.prompt);
+    }
+
+    let info = json!({
+        "prompt": prompts.first().cloned().unwrap_or_default(),
+        "all_prompts": prompts,
+        "negative_prompt": payload.negative_prompt,
+        "seed": seeds.first(),
+        "all_seeds": seeds,
+        "width": payload.width,
+        "height": payload.height,
+        "steps": payload.steps,
+        "cfg_scale": payload.cfg_scale,
+    });
+
+    Ok(Json(SdApiResponse {
+        images,
+        parameters: payload,
+        info: info.to_string(),
+    }))
+}
+
+fn image_count(payload: &SdApiRequest) -> Result<u32, ApiError> {
+    payload
+        .batch_size
+        .max(1)
+        .checked_mul(payload.n_iter.max(1))
+        .filter(|count| *count <= MAX_IMAGES_PER_REQUEST)
+        .ok_or_else(|| {
+            ApiError::unprocessable(format!(
+                "batch_size * n_iter must be at most {} (got batch_size {} and n_iter {})",
+                MAX_IMAGES_PER_REQUEST, payload.batch_size, payload.n_iter
+            ))
+        })
+}
+
+// Init images arrive base64 encoded, optionally as a data URL
+fn write_init_image(encoded: &str) -> Result<PathBuf, ApiError> {
+    let data = encoded
+        .split_once("base64,")
+        .map(|(_, data)| data)
+        .unwrap_or(encoded);
+    let bytes = BASE64
+        .decode(data.trim())
+        .map_err(|e| ApiError::bad_request(format!("init_images[0] is not valid base64: {}", e)))?;
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let path = std::env::temp_dir().join(format!("diffusionbee_api_init_{}.png", nanos));
+    std::fs::write(&path, bytes)
+        .map_err(|e| ApiError::internal(format!("Failed to write init image: {}", e)))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(batch_size: u32, n_iter: u32) -> SdApiRequest {
+        SdApiRequest {
+            batch_size,
+            n_iter,
+            ..SdApiRequest::default()
+        }
+    }
+
+    // API clients key on these, like the frontend does on the error kinds
+    #[test]
+    fn error_status_codes() {
+        let statuses: Vec<(&str, StatusCode)> = crate::error::tests::every_variant()
+            .into_iter()
+            .map(|error| (error.kind(), ApiError::from(error).status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("python_backend", StatusCode::INTERNAL_SERVER_ERROR),
+                ("file_system", StatusCode::INTERNAL_SERVER_ERROR),
+                ("json", StatusCode::INTERNAL_SERVER_ERROR),
+                ("process", StatusCode::INTERNAL_SERVER_ERROR),
+                ("validation", StatusCode::BAD_REQUEST),
+                ("model_not_found", StatusCode::INTERNAL_SERVER_ERROR),
+                ("backend_not_found", StatusCode::INTERNAL_SERVER_ERROR),
+                ("cancelled", StatusCode::INTERNAL_SERVER_ERROR),
+                ("internal", StatusCode::INTERNAL_SERVER_ERROR),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn error_body() {
+        let response = ApiError::from(DiffusionError::validation("img_width", "Width must be a multiple of 8"))
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "Bad Request", "detail": "Width must be a multiple of 8" })
+        );
+    }
+
+    #[test]
+    fn image_count_is_capped() {
+        assert_eq!(image_count(&payload(0, 0)).ok(), Some(1));
+        assert_eq!(image_count(&payload(4, 25)).ok(), Some(100));
+        for (batch_size, n_iter) in [(101, 1), (1, 101), (11, 10), (u32::MAX, u32::MAX), (65_536, 65_536)] {
+            let err = image_count(&payload(batch_size, n_iter)).err().unwrap();
+            assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// Bridge to the backend's applets (applets/applets.py)
+//
+// Applets report everything through `utds <key>___U_P_D_A_T_E___<json>` lines:
+// at startup each registered applet publishes its metadata and input form under
+// `registered_ext_applets.<name>`, and while running it publishes state and
+// outputs under the same prefix. Discovery replays those startup updates, so a
+// new Python applet shows up here without Rust changes.
+//
+// Running an applet (`b2py rapp <name> <json>`) can't just wait for the process
+// to exit: the caller needs the updates as they arrive and may ask the applet to
+// stop, which the backend checks for as `__stop__` on stdin.
+
+use crate::logging::{self, BackendStream};
+use crate::DiffusionError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+const STATE_UPDATE_PREFIX: &str = "utds ";
+const STATE_UPDATE_SEPARATOR: &str = "___U_P_D_A_T_E___";
+pub const APPLETS_STATE_KEY: &str = "registered_ext_applets";
+
+// How often the cancel flag is checked while waiting for backend output
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Discovery has to wait for the backend's imports (TensorFlow is slow to load)
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(180);
+
+// Form components that hold a value; everything else is layout or output
+const INPUT_COMPONENTS: [&str; 7] = [
+    "Textbox",
+    "Textarea",
+    "FilePathTextBox",
+    "Dropdown",
+    "Slider",
+    "Checkbox",
+    "ImageInput",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppletInfo {
+    pub id: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub img_icon: Option<String>,
+    #[serde(default)]
+    pub home_category: Option<String>,
+    #[serde(default)]
+    pub is_stop_avail: bool,
+    /// The applet's input form, as built by applets/form_utils.py
+    #[serde(default)]
+    pub inputs: Value,
+}
+
+/// One value-bearing element of an applet form
+#[derive(Debug, Clone, Serialize)]
+pub struct FormField {
+    pub id: String,
+    pub component: String,
+    pub output_type: String,
+    pub default_value: Option<Value>,
+    pub options: Option<Vec<Value>>,
+    pub min_val: Option<f64>,
+    pub max_val: Option<f64>,
+}
+
+/// Splits a `utds` line into its state key and JSON value
+pub fn parse_state_update(line: &str) -> Option<(String, Value)> {
+    let rest = line.strip_prefix(STATE_UPDATE_PREFIX)?;
+    let (key, json) = rest.split_once(STATE_UPDATE_SEPARATOR)?;
+    let value = serde_json::from_str(json).ok()?;
+    Some((key.to_string(), value))
+}
+
+/// Applies a dotted-key state update (`a.b.c`) to a JSON state tree
+pub fn apply_state_update(state: &mut Value, key: &str, value: Value) {
+    let mut node = state;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        let Value::Object(map) = node else {
+            return;
+        };
+        if parts.peek().is_none() {
+            map.insert(part.to_string(), value);
+            return;
+        }
+        node = map.entry(part.to_string()).or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+/// Starts the backend just long enough to collect the applets it registers.
+pub async fn discover_applets(python: &Path, backend_script: &Path) -> Result<Vec<AppletInfo>, DiffusionError> {
+    let mut child = Command::new(python)
+        .arg(backend_script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| DiffusionError::ProcessError(format!("Failed to start Python backend: {}", e)))?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| DiffusionError::ProcessError("Failed to open backend stdout".to_string()))?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut state = Value::Object(Map::new());
+
+    let read_registrations = async {
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|e| DiffusionError::ProcessError(format!("Failed to read from Python backend: {}", e)))?
+        {
+            if let Some((key, value)) = parse_state_update(&line) {
+                apply_state_update(&mut state, &key, value);
+            } else if line.starts_with("sdbk mltl") || line.starts_with("sdbk inrd") {
+                // Applets are registered before the model starts loading
+                return Ok(());
+            }
+        }
+        Err(DiffusionError::ProcessError(
+            "Python backend exited before registering its applets".to_string(),
+        ))
+    };
+
+    let result = tokio::time::timeout(DISCOVERY_TIMEOUT, read_registrations)
+        .await
+        .map_err(|_| {
+            DiffusionError::ProcessError("Timed out waiting for the Python backend to register applets".to_string())
+        })
+        .and_then(|r| r);
+    let _ = child.kill().await;
+    result?;
+
+    let mut applets: Vec<AppletInfo> = state[APPLETS_STATE_KEY]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, info)| {
+            let mut info = info.clone();
+            if info.get("id").is_none() {
+                info["id"] = Value::String(name.clone());
+            }
+            serde_json::from_value(info).ok()
+        })
+        .collect();
+    applets.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(applets)
+}
+
+/// Flattens an applet form into its value-bearing fields, first occurrence wins
+pub fn form_fields(form: &Value) -> Vec<FormField> {
+    let mut fields = Vec::new();
+    let mut seen = HashSet::new();
+    collect_fields(form, &mut fields, &mut seen);
+    fields
+}
+
+fn collect_fields(node: &Value, fields: &mut Vec<FormField>, seen: &mut HashSet<String>) {
+    if let Some(elements) = node.as_array() {
+        for element in elements {
+            collect_fields(element, fields, seen);
+        }
+        return;
+    }
+
+    let component = node["component"].as_str().unwrap_or_default();
+    if INPUT_COMPONENTS.contains(&component) {
+        if let Some(id) = node["id"].as_str() {
+            if seen.insert(id.to_string()) {
+                fields.push(FormField {
+                    id: id.to_string(),
+                    component: component.to_string(),
+                    output_type: node["output_type"].as_str().unwrap_or(match component {
+                        "Checkbox" => "bool",
+                        _ => "str",
+                    }).to_string(),
+                    default_value: node.get("default_value").cloned(),
+                    options: node["options"].as_array().cloned(),
+                    min_val: node["min_val"].as_f64(),
+                    max_val: node["max_val"].as_f64(),
+                });
+            }
+        }
+    }
+
+    if let Some(children) = node.get("children") {
+        collect_fields(children, fields, seen);
+    }
+}
+
+/// Checks `params` against the applet's form: unknown fields are rejected, values
+/// are coerced to the field's output type and missing fields get their defaults.
+pub fn validate_params(form: &Value, params: &Value) -> Result<Value, DiffusionError> {
+    let empty = Map::new();
+    let given = match params {
+        Value::Object(map) => map,
+        Value::Null => &empty,
+        _ => return Err(DiffusionError::invalid("Applet parameters must be a JSON object")),
+    };
+
+    let fields = form_fields(form);
+    if let Some(unknown) = given.keys().find(|k| !fields.iter().any(|f| &f.id == *k)) {
+        return Err(DiffusionError::validation(unknown, format!("Unknown applet parameter: {}", unknown)));
+    }
+
+    let mut validated = Map::new();
+    for field in &fields {
+        let value = match given.get(&field.id) {
+            Some(value) => coerce_value(field, value)?,
+            // Empty defaults (e.g. seed boxes) mean "required", which the applet checks itself
+            None => match &field.default_value {
+                Some(Value::String(s)) if s.is_empty() && field.output_type != "str" => continue,
+                Some(default) => coerce_value(field, default)?,
+                None => continue,
+            },
+        };
+        validated.insert(field.id.clone(), value);
+    }
+    Ok(Value::Object(validated))
+}
+
+fn coerce_value(field: &FormField, value: &Value) -> Result<Value, DiffusionError> {
+    let invalid = || DiffusionError::validation(&field.id, format!("Invalid value for '{}': {}", field.id, value));
+
+    let coerced = match field.output_type.as_str() {
+        "int" => match value {
+            Value::Number(n) => n.as_i64().map(Value::from),
+            Value::String(s) => s.trim().parse::<i64>().ok().map(Value::from),
+            _ => None,
+        },
+        "float" => match value {
+            Value::Number(n) => n.as_f64().map(Value::from),
+            Value::String(s) => s.trim().parse::<f64>().ok().map(Value::from),
+            _ => None,
+        },
+        "bool" => match value {
+            Value::Bool(b) => Some(Value::Bool(*b)),
+            Value::String(s) => match s.as_str() {
+                "Yes" | "true" => Some(Value::Bool(true)),
+                "No" | "false" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => match value {
+            Value::String(_) => Some(value.clone()),
+            Value::Number(n) => Some(Value::String(n.to_string())),
+            _ => None,
+        },
+    }
+    .ok_or_else(invalid)?;
+
+    if let Some(n) = coerced.as_f64() {
+        if field.min_val.is_some_and(|min| n < min) || field.max_val.is_some_and(|max| n > max) {
+            return Err(DiffusionError::validation(
+                &field.id,
+                format!(
+                    "'{}' must be between {} and {}",
+                    field.id,
+                    field.min_val.unwrap_or(f64::MIN),
+                    field.max_val.unwrap_or(f64::MAX)
+                ),
+            ));
+        }
+    }
+
+    // Dropdowns only accept one of their options (compared loosely, the UI sends strings)
+    if let Some(options) = &field.options {
+        let matches = |option: &Value| option == value || option_str(option) == option_str(value);
+        if !options.iter().any(matches) {
+            return Err(DiffusionError::validation(
+                &field.id,
+                format!("'{}' must be one of the listed options", field.id),
+            ));
+        }
+    }
+
+    Ok(coerced)
+}
+
+fn option_str(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Runs `applet_name` with `params` in a fresh backend process started with `python`.
+///
+/// `on_update` gets every state update the applet publishes. `is_cancelled` is
+/// polled while the applet runs; once it returns true `__stop__` is sent and the
+/// applet is left to wind down on its own. Returns when the backend is ready for
+/// input again, i.e. the applet finished, stopped or raised.
+pub async fn run_applet(
+    python: &Path,
+    backend_script: &Path,
+    applet_name: &str,
+    params: &Value,
+    mut on_update: impl FnMut(&str, &Value),
+    is_cancelled: impl Fn() -> bool,
+) -> Result<(), DiffusionError> {
+    let process_error = DiffusionError::ProcessError;
+
+    let mut child = Command::new(python)
+        .arg(backend_script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| process_error(format!("Failed to start Python backend: {}", e)))?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| process_error("Failed to open backend stdin".to_string()))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| process_error("Failed to open backend stdout".to_string()))?;
+    let mut lines = BufReader::new(stdout).lines();
+
+    // Logged and collected in the background so a traceback can go with `sdbk errr`
+    let stderr = child.stderr.take();
+    let collect_stderr = async move {
+        let mut text = String::new();
+        if let Some(stderr) = stderr {
+            let mut stderr_lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = stderr_lines.next_line().await {
+                logging::backend_output(BackendStream::Stderr, &line);
+                text.push_str(&line);
+                text.push('\n');
+            }
+        }
+        text
+    };
+    // Spawned tasks don't inherit the job, so pass it on
+    let stderr_task = match logging::current_job() {
+        Some(job_id) => tokio::spawn(logging::with_job(job_id, collect_stderr)),
+        None => tokio::spawn(collect_stderr),
+    };
+
+    let command = format!("b2py rapp {} {}\n", applet_name, params);
+    stdin
+        .write_all(command.as_bytes())
+        .await
+        .map_err(|e| process_error(format!("Failed to write to Python backend: {}", e)))?;
+
+    let mut started = false;
+    let mut stop_sent = false;
+    let mut error: Option<String> = None;
+
+    let outcome = loop {
+        if !stop_sent && is_cancelled() {
+            // The backend may already be gone; the read loop below reports that
+            let _ = stdin.write_all(b"__stop__\n").await;
+            stop_sent = true;
+        }
+
+        let line = match tokio::time::timeout(CANCEL_POLL_INTERVAL, lines.next_line()).await {
+            Err(_) => continue,
+            Ok(Ok(Some(line))) => line,
+            Ok(Ok(None)) => {
+                break Err(error.take().unwrap_or_else(|| {
+                    format!("Python backend exited while running applet '{}'", applet_name)
+                }))
+            }
+            Ok(Err(e)) => break Err(format!("Failed to read from Python backend: {}", e)),
+        };
+        logging::backend_output(BackendStream::Stdout, &line);
+
+        if let Some((key, value)) = parse_state_update(&line) {
+            on_update(&key, &value);
+        } else if line.starts_with("sdbk inwk") {
+            started = true;
+        } else if let Some(message) = line.strip_prefix("sdbk errr ") {
+            error = Some(message.trim().to_string());
+        } else if line.starts_with("sdbk inrd") && started {
+            break error.take().map_or(Ok(()), Err);
+        }
+    };
+
+    let _ = child.kill().await;
+    let stderr = stderr_task.await.unwrap_or_default();
+    outcome.map_err(|message| DiffusionError::backend(message, &stderr))
+}
@@ -0,0 +1,112 @@
+// Line-level connection to a backend speaking the `sdbk` protocol
+//
+// Generation reads the backend's output as it arrives so progress and errors can
+// be tracked while the job runs. The connection looks the same whether the other
+// end is the Python process or the in-process mock (see mock_backend.rs): lines
+// go in through `send`, stdout and stderr lines come out of `recv`.
+
+use crate::DiffusionError;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::error::Elapsed;
+
+#[derive(Debug, Clone)]
+pub enum BackendLine {
+    Stdout(String),
+    Stderr(String),
+}
+
+pub struct BackendConnection {
+    lines: UnboundedReceiver<BackendLine>,
+    input: Box<dyn FnMut(&str) -> bool + Send>,
+    child: Option<Child>,
+}
+
+impl BackendConnection {
+    /// Starts `<python> <backend_script>` and forwards its output line by line
+    pub fn spawn_python(python: &Path, backend_script: &Path) -> Result<Self, DiffusionError> {
+        let mut child = Command::new(python)
+            .arg(backend_script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| DiffusionError::ProcessError(format!("Failed to start Python backend: {}", e)))?;
+
+        let (sender, lines) = unbounded_channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward_lines(stdout, sender.clone(), BackendLine::Stdout);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward_lines(stderr, sender, BackendLine::Stderr);
+        }
+
+        let mut stdin = child.stdin.take();
+        let input = move |line: &str| match stdin.as_mut() {
+            Some(stdin) => writeln!(stdin, "{}", line).and_then(|_| stdin.flush()).is_ok(),
+            None => false,
+        };
+
+        Ok(Self {
+            lines,
+            input: Box::new(input),
+            child: Some(child),
+        })
+    }
+
+    /// A connection to an in-process backend. `input` returns false once the
+    /// backend is gone.
+    pub fn from_channel(
+        lines: UnboundedReceiver<BackendLine>,
+        input: impl FnMut(&str) -> bool + Send + 'static,
+    ) -> Self {
+        Self {
+            lines,
+            input: Box::new(input),
+            child: None,
+        }
+    }
+
+    /// Sends one line of input, e.g. `b2py t2im {...}` or `__stop__`
+    pub fn send(&mut self, line: &str) -> Result<(), DiffusionError> {
+        if (self.input)(line) {
+            Ok(())
+        } else {
+            Err(DiffusionError::ProcessError("Failed to write to Python backend".to_string()))
+        }
+    }
+
+    /// Waits up to `timeout` for the next line. `Ok(None)` means the backend has
+    /// closed its output.
+    pub async fn recv(&mut self, timeout: Duration) -> Result<Option<BackendLine>, Elapsed> {
+        tokio::time::timeout(timeout, self.lines.recv()).await
+    }
+}
+
+impl Drop for BackendConnection {
+    fn drop(&mut self) {
+        // The backend waits for input forever, so it has to be stopped explicitly
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn forward_lines<R: Read + Send + 'static>(
+    reader: R,
+    sender: UnboundedSender<BackendLine>,
+    wrap: fn(String) -> BackendLine,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else { break };
+            if sender.send(wrap(line)).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,87 @@
+// Finds diffusionbee_backend.py for bundled apps, dev checkouts and overrides
+//
+// An override from the `DIFFUSIONBEE_BACKEND_SCRIPT` environment variable or the
+// `backend_script_path` setting is used as is. Otherwise the Tauri resource
+// directory (where bundles ship `backends/`, see tauri.conf.json), the workspace
+// this crate was built from, and the ancestors of the executable and the working
+// directory are searched. Nothing here panics on shallow paths; when nothing is
+// found the error lists every path that was checked.
+
+use crate::DiffusionError;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const BACKEND_SCRIPT_ENV: &str = "DIFFUSIONBEE_BACKEND_SCRIPT";
+pub const BACKEND_SCRIPT_NAME: &str = "diffusionbee_backend.py";
+
+// Set once the Tauri app is up; the CLI has no resource directory
+static RESOURCE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn set_resource_dir(dir: PathBuf) {
+    if let Ok(mut resource_dir) = RESOURCE_DIR.lock() {
+        *resource_dir = Some(dir);
+    }
+}
+
+// <root>/backends/stable_diffusion/diffusionbee_backend.py
+fn script_under(root: &Path) -> PathBuf {
+    root.join("backends").join("stable_diffusion").join(BACKEND_SCRIPT_NAME)
+}
+
+// An override may name the script itself or the directory holding it
+fn script_at(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.join(BACKEND_SCRIPT_NAME)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+// The environment variable wins over the setting
+fn override_path(configured: &str) -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(BACKEND_SCRIPT_ENV).filter(|p| !p.is_empty()) {
+        return Some(script_at(Path::new(&path)));
+    }
+    (!configured.trim().is_empty()).then(|| script_at(Path::new(configured.trim())))
+}
+
+/// Every path checked for the backend script, in order. An override is the only
+/// candidate, so a wrong override is reported instead of silently bypassed.
+pub fn candidate_paths(configured: &str) -> Vec<PathBuf> {
+    if let Some(path) = override_path(configured) {
+        return vec![path];
+    }
+
+    let mut candidates = Vec::new();
+
+    if let Some(resource_dir) = RESOURCE_DIR.lock().ok().and_then(|dir| dir.clone()) {
+        candidates.push(script_under(&resource_dir));
+    }
+
+    // src-tauri/.. is the workspace root in a dev checkout
+    if let Some(workspace_root) = Path::new(env!("CARGO_MANIFEST_DIR")).parent() {
+        candidates.push(script_under(workspace_root));
+    }
+
+    let exe_dir = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+    for start in exe_dir.into_iter().chain(std::env::current_dir().ok()) {
+        candidates.extend(start.ancestors().map(script_under));
+    }
+
+    let mut seen = Vec::new();
+    candidates.retain(|path| {
+        let new = !seen.contains(path);
+        seen.push(path.clone());
+        new
+    });
+    candidates
+}
+
+/// The backend script to run, or an error naming every place that was checked
+pub fn resolve_backend_script(configured: &str) -> Result<PathBuf, DiffusionError> {
+    let candidates = candidate_paths(configured);
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(DiffusionError::BackendNotFound(candidates)),
+    }
+}
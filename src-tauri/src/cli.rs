@@ -0,0 +1,421 @@
+// Headless command-line front end for batch generation
+//
+// Uses the same request types, validation and backend call as the Tauri app, but
+// takes jobs from arguments, a JSONL file or stdin and reports progress as one
+// JSON object per line on stdout. Diagnostics go to stderr.
+
+use clap::Parser;
+use ported_diffusion_app_lib::post_processing::{PostProcessStage, UpscaleMethod, UpscaleOptions};
+use ported_diffusion_app_lib::presets::Preset;
+use ported_diffusion_app_lib::{
+    generation_progress, load_settings, logging, output_files, post_processing, presets, run_generation, AppSettings,
+    ImageGenerationRequest, ImageGenerationResponse,
+};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Parser, Debug)]
+#[command(name = "diffusion-cli", version, about = "Generate images without the GUI")]
+struct Args {
+    /// Prompts to generate, one job per prompt
+    prompts: Vec<String>,
+
+    /// JSONL file of ImageGenerationRequest objects (use `-` for stdin)
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+
+    /// Directory the generated images are written to
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// File name template, e.g. `{date}_{seed}_{prompt:30}` (defaults to the app setting)
+    #[arg(long)]
+    name_template: Option<String>,
+
+    /// Model (.tdict) to use instead of the default one
+    #[arg(short, long)]
+    model: Option<PathBuf>,
+
+    /// Directory containing `__wildcard__` files
+    #[arg(long)]
+    wildcards: Option<PathBuf>,
+
+    // No clap defaults for the values a preset can set, so a preset only loses
+    // to flags that were actually given
+
+    /// Image width [default: 512]
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// Image height [default: 512]
+    #[arg(long)]
+    height: Option<u32>,
+
+    #[arg(long, default_value_t = 1)]
+    num_imgs: u32,
+
+    /// Inference steps [default: 20]
+    #[arg(long)]
+    steps: Option<u32>,
+
+    /// Guidance scale [default: 7.5]
+    #[arg(long)]
+    guidance: Option<f32>,
+
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Saved preset applied to every job, can be repeated; later presets win,
+    /// flags and JSONL fields win over presets
+    #[arg(long = "preset")]
+    presets: Vec<String>,
+
+    /// Also save a Lanczos upscale of every image, e.g. `--upscale 2`
+    #[arg(long)]
+    upscale: Option<f32>,
+
+    /// Use the in-process mock backend instead of Python
+    #[arg(long)]
+    mock: bool,
+}
+
+impl Args {
+    // Defaults for every job; JSONL lines only need to set what differs. Presets
+    // fill in parameters before the flags, see the precedence in `presets`.
+    fn base_request(&self, presets: &[Preset]) -> ImageGenerationRequest {
+        let mut request = ImageGenerationRequest {
+            num_imgs: self.num_imgs,
+            seed: self.seed,
+            ..ImageGenerationRequest::new(String::new())
+        };
+        for preset in presets {
+            preset.parameters.apply_to(&mut request);
+        }
+        request.img_width = self.width.unwrap_or(request.img_width);
+        request.img_height = self.height.unwrap_or(request.img_height);
+        request.num_inference_steps = self.steps.unwrap_or(request.num_inference_steps);
+        request.guidance_scale = self.guidance.unwrap_or(request.guidance_scale);
+        request
+    }
+}
+
+// One line of machine-readable output
+fn emit(event: Value) {
+    println!("{}", event);
+}
+
+fn read_jobs(args: &Args, presets: &[Preset]) -> Result<Vec<Result<ImageGenerationRequest, String>>, String> {
+    let base_request = args.base_request(presets);
+    let mut jobs: Vec<Result<ImageGenerationRequest, String>> = args
+        .prompts
+        .iter()
+        .map(|prompt| {
+            Ok(ImageGenerationRequest {
+                prompt: prompt.clone(),
+                ..base_request.clone()
+            })
+        })
+        .collect();
+
+    if let Some(file) = &args.file {
+        let reader: Box<dyn BufRead> = if file == Path::new("-") {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            let handle = fs::File::open(file)
+                .map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+            Box::new(BufReader::new(handle))
+        };
+        jobs.extend(parse_jobs(&base_request, reader)?);
+    }
+
+    Ok(jobs)
+}
+
+// One job per non-blank JSONL line, each line's fields over `base_request`
+fn parse_jobs(
+    base_request: &ImageGenerationRequest,
+    reader: impl BufRead,
+) -> Result<Vec<Result<ImageGenerationRequest, String>>, String> {
+    let base = serde_json::to_value(base_request).map_err(|e| e.to_string())?;
+    let mut jobs = Vec::new();
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read jobs: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        jobs.push(parse_job(&base, &line).map_err(|e| format!("Line {}: {}", line_no + 1, e)));
+    }
+    Ok(jobs)
+}
+
+fn parse_job(base: &Value, line: &str) -> Result<ImageGenerationRequest, String> {
+    let overrides: Value = serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let Value::Object(overrides) = overrides else {
+        return Err("Expected a JSON object".to_string());
+    };
+
+    let mut merged = base.clone();
+    if let Value::Object(fields) = &mut merged {
+        fields.extend(overrides);
+    }
+    serde_json::from_value(merged).map_err(|e| format!("Invalid request: {}", e))
+}
+
+// Runs one job, emitting a `progress` line whenever the backend reports a new step
+async fn generate_with_progress(
+    job: usize,
+    request: ImageGenerationRequest,
+    settings: &AppSettings,
+) -> Result<ImageGenerationResponse, String> {
+    let generation = run_generation(request, settings);
+    tokio::pin!(generation);
+    let mut poll = tokio::time::interval(PROGRESS_POLL_INTERVAL);
+    let mut last_step = None;
+    loop {
+        tokio::select! {
+            result = &mut generation => return result.map_err(|e| e.to_string()),
+            _ = poll.tick() => {
+                // The previous job's progress stays complete until this one resets it
+                let Ok(Some(progress)) = generation_progress() else { continue };
+                if progress.is_complete || progress.current_step == 0 || last_step == Some(progress.current_step) {
+                    continue;
+                }
+                last_step = Some(progress.current_step);
+                emit(json!({
+                    "event": "progress",
+                    "job": job,
+                    "step": progress.current_step,
+                    "total_steps": progress.total_steps,
+                }));
+            }
+        }
+    }
+}
+
+// Images are already in the output directory, named by the template
+fn outputs(response: &ImageGenerationResponse) -> Vec<String> {
+    let mut outputs = response.images.clone();
+    if outputs.is_empty() {
+        outputs.push(response.generated_img_path.clone());
+    }
+    outputs.extend(response.aux_output_image_path.clone());
+    outputs
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    logging::init();
+    // Read while still single-threaded so {date} and {time} use local time
+    output_files::local_offset();
+
+    match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime.block_on(run(args)),
+        Err(e) => {
+            emit(json!({ "event": "error", "error": format!("Failed to start the async runtime: {}", e) }));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> ExitCode {
+    let presets = match args.presets.iter().map(|name| presets::get(name)).collect::<Result<Vec<_>, _>>() {
+        Ok(presets) => presets,
+        Err(e) => {
+            emit(json!({ "event": "error", "error": e.to_string() }));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let jobs = match read_jobs(&args, &presets) {
+        Ok(jobs) if jobs.is_empty() => {
+            eprintln!("No prompts given. Pass prompts as arguments or use --file.");
+            return ExitCode::from(2);
+        }
+        Ok(jobs) => jobs,
+        Err(e) => {
+            emit(json!({ "event": "error", "error": e }));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(e) = fs::create_dir_all(&args.output) {
+        emit(json!({ "event": "error", "error": format!("Failed to create output directory: {}", e) }));
+        return ExitCode::FAILURE;
+    }
+
+    // Saved app settings, with the command line taking precedence
+    let mut settings = load_settings();
+    settings.output_directory = args.output.to_string_lossy().to_string();
+    if let Some(model) = &args.model {
+        settings.model_path = model.to_string_lossy().to_string();
+    }
+    if let Some(wildcards) = &args.wildcards {
+        settings.wildcards_directory = wildcards.to_string_lossy().to_string();
+    }
+    if let Some(template) = &args.name_template {
+        settings.filename_template = template.clone();
+    }
+    if let Err(e) = output_files::validate_template(&settings.filename_template) {
+        emit(json!({ "event": "error", "error": e.to_string() }));
+        return ExitCode::FAILURE;
+    }
+    settings.mock_backend |= args.mock;
+    if let Some(scale) = args.upscale {
+        settings.post_processing.push(PostProcessStage::Upscale(UpscaleOptions {
+            scale,
+            method: UpscaleMethod::Lanczos,
+        }));
+    }
+    if let Err(e) = post_processing::validate_settings(&settings) {
+        emit(json!({ "event": "error", "error": e.to_string() }));
+        return ExitCode::FAILURE;
+    }
+
+    let total = jobs.len();
+    let mut failed = 0;
+    emit(json!({ "event": "batch_started", "total": total }));
+
+    for (job, request) in jobs.into_iter().enumerate() {
+        let request = request.map(|mut request| {
+            for preset in &presets {
+                preset.apply_prompts_to(&mut request);
+            }
+            request
+        });
+        let result = match request {
+            Ok(request) => {
+                emit(json!({ "event": "job_started", "job": job, "total": total, "prompt": request.prompt }));
+                generate_with_progress(job, request, &settings).await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(response) => emit(json!({
+                "event": "job_completed",
+                "job": job,
+                "total": total,
+                "prompt": response.prompt,
+                "seed": response.seed,
+                "outputs": outputs(&response),
+                "warnings": response.warnings,
+                "job_id": response.job_id,
+                "history_id": response.history_id,
+                "derived_images": response.derived_images,
+            })),
+            Err(e) => {
+                failed += 1;
+                emit(json!({ "event": "job_failed", "job": job, "total": total, "error": e }));
+            }
+        }
+    }
+
+    emit(json!({ "event": "batch_finished", "total": total, "completed": total - failed, "failed": failed }));
+
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ported_diffusion_app_lib::presets::PresetParameters;
+
+    fn args(extra: &[&str]) -> Args {
+        Args::parse_from(["diffusion-cli", "-o", "out"].iter().chain(extra))
+    }
+
+    fn jobs(base: &ImageGenerationRequest, input: &str) -> Vec<Result<ImageGenerationRequest, String>> {
+        parse_jobs(base, input.as_bytes()).unwrap()
+    }
+
+    fn preset(parameters: PresetParameters) -> Preset {
+        Preset {
+            name: "style".to_string(),
+            description: String::new(),
+            prompt_template: String::new(),
+            negative_prompt: String::new(),
+            parameters,
+        }
+    }
+
+    #[test]
+    fn line_fields_override_the_base_request() {
+        let base = args(&["--width", "640", "--steps", "30", "--seed", "7"]).base_request(&[]);
+        let jobs = jobs(&base, r#"{"prompt": "a fox", "img_width": 768, "seed": null}"#);
+        let request = jobs[0].as_ref().unwrap();
+        assert_eq!(request.prompt, "a fox");
+        assert_eq!(request.img_width, 768);
+        assert_eq!(request.seed, None);
+        // Fields the line leaves out come from the flags, then the defaults
+        assert_eq!(request.num_inference_steps, 30);
+        assert_eq!(request.img_height, 512);
+        assert_eq!(request.guidance_scale, 7.5);
+    }
+
+    #[test]
+    fn flags_win_over_presets() {
+        let presets = [
+            preset(PresetParameters {
+                img_width: Some(768),
+                num_inference_steps: Some(12),
+                ..PresetParameters::default()
+            }),
+            preset(PresetParameters {
+                num_inference_steps: Some(25),
+                guidance_scale: Some(5.0),
+                ..PresetParameters::default()
+            }),
+        ];
+        let base = args(&["--steps", "40"]).base_request(&presets);
+        assert_eq!(base.img_width, 768);
+        assert_eq!(base.num_inference_steps, 40);
+        assert_eq!(base.guidance_scale, 5.0);
+
+        let base = args(&[]).base_request(&presets);
+        assert_eq!(base.num_inference_steps, 25);
+        let jobs = jobs(&base, r#"{"prompt": "a fox", "img_width": 512}"#);
+        assert_eq!(jobs[0].as_ref().unwrap().img_width, 512);
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        let base = args(&[]).base_request(&[]);
+        let jobs = jobs(&base, "\n{\"prompt\": \"one\"}\n   \n\n{\"prompt\": \"two\"}\n");
+        let prompts: Vec<&str> = jobs.iter().map(|job| job.as_ref().unwrap().prompt.as_str()).collect();
+        assert_eq!(prompts, ["one", "two"]);
+    }
+
+    #[test]
+    fn invalid_lines_fail_only_their_job() {
+        let base = args(&[]).base_request(&[]);
+        let input = "{\"prompt\": \"ok\"}\n\n{\"prompt\": \n[\"a list\"]\n\"text\"\n{\"img_width\": \"wide\"}\n";
+        let jobs = jobs(&base, input);
+        assert_eq!(jobs.len(), 5);
+        assert!(jobs[0].is_ok());
+        let errors: Vec<&str> = jobs[1..].iter().map(|job| job.as_ref().unwrap_err().as_str()).collect();
+        assert!(errors[0].starts_with("Line 3: Invalid JSON: "), "{}", errors[0]);
+        assert_eq!(errors[1], "Line 4: Expected a JSON object");
+        assert_eq!(errors[2], "Line 5: Expected a JSON object");
+        assert!(errors[3].starts_with("Line 6: Invalid request: invalid type"), "{}", errors[3]);
+    }
+
+    #[test]
+    fn prompt_arguments_use_the_base_request() {
+        let args = args(&["--height", "768", "a fox", "a cat"]);
+        let jobs = read_jobs(&args, &[]).unwrap();
+        let requests: Vec<&ImageGenerationRequest> = jobs.iter().map(|job| job.as_ref().unwrap()).collect();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].prompt, "a cat");
+        assert!(requests.iter().all(|r| r.img_height == 768));
+    }
+}
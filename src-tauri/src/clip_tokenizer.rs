@@ -0,0 +1,343 @@
+// Rust port of the backend's CLIP BPE tokenizer (stable_diffusion/clip_tokenizer)
+//
+// Only used to count tokens so we can warn about prompts the text encoder will
+// truncate. It loads the same `bpe_simple_vocab_16e6.txt.gz` merges file so the
+// counts match what the Python side actually feeds the model.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Tokens available to the prompt; the encoder adds start and end tokens to make 77
+pub const MAX_PROMPT_TOKENS: usize = 75;
+
+const START_OF_TEXT: &str = "<|startoftext|>";
+const END_OF_TEXT: &str = "<|endoftext|>";
+const CONTRACTIONS: [&str; 7] = ["'s", "'t", "'re", "'ve", "'m", "'ll", "'d"];
+
+pub struct ClipTokenizer {
+    byte_encoder: Vec<char>,
+    encoder: HashMap<String, u32>,
+    bpe_ranks: HashMap<(String, String), usize>,
+    cache: Mutex<HashMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptTokenCount {
+    pub token_count: usize,
+    pub max_tokens: usize,
+    /// Words (in prompt order) that start past the limit or straddle it
+    pub truncated_words: Vec<String>,
+}
+
+impl PromptTokenCount {
+    pub fn warning(&self) -> Option<String> {
+        if self.truncated_words.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "Prompt is {} tokens long but only {} are used. These words will be ignored: {}",
+            self.token_count,
+            self.max_tokens,
+            self.truncated_words.join(" ")
+        ))
+    }
+}
+
+impl ClipTokenizer {
+    pub fn load(vocab_path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(vocab_path)
+            .with_context(|| format!("Failed to open CLIP vocab at {}", vocab_path.display()))?;
+        let mut content = String::new();
+        GzDecoder::new(file)
+            .read_to_string(&mut content)
+            .context("Failed to decompress CLIP vocab")?;
+        Ok(Self::from_merges(&content))
+    }
+
+    fn from_merges(content: &str) -> Self {
+        // Same slice as the Python tokenizer: skip the header, keep 49152 - 256 - 2 merges
+        let merges: Vec<(String, String)> = content
+            .split('\n')
+            .skip(1)
+            .take(49152 - 256 - 2)
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect();
+
+        let byte_encoder = bytes_to_unicode();
+        // The Python vocab lists printable bytes first, which is code point order here
+        let mut base_chars = byte_encoder.clone();
+        base_chars.sort_unstable();
+        let mut vocab: Vec<String> = base_chars.iter().map(|c| c.to_string()).collect();
+        vocab.extend(base_chars.iter().map(|c| format!("{}</w>", c)));
+        vocab.extend(merges.iter().map(|(a, b)| format!("{}{}", a, b)));
+        vocab.push(START_OF_TEXT.to_string());
+        vocab.push(END_OF_TEXT.to_string());
+
+        let encoder = vocab
+            .into_iter()
+            .enumerate()
+            .map(|(i, token)| (token, i as u32))
+            .collect();
+        let bpe_ranks = merges
+            .into_iter()
+            .enumerate()
+            .map(|(i, pair)| (pair, i))
+            .collect();
+
+        Self {
+            byte_encoder,
+            encoder,
+            bpe_ranks,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Token ids for `text`, without the start/end tokens and without truncation.
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        clean_text(text)
+            .split(' ')
+            .flat_map(|word| self.encode_word(word))
+            .collect()
+    }
+
+    pub fn count_prompt_tokens(&self, prompt: &str) -> PromptTokenCount {
+        let mut token_count = 0;
+        let mut truncated_words = Vec::new();
+
+        // Pre-tokenization never crosses whitespace, so words can be encoded one at a time
+        for word in clean_text(prompt).split(' ').filter(|w| !w.is_empty()) {
+            let tokens = self.encode_word(word).len();
+            if token_count + tokens > MAX_PROMPT_TOKENS {
+                truncated_words.push(word.to_string());
+            }
+            token_count += tokens;
+        }
+
+        PromptTokenCount {
+            token_count,
+            max_tokens: MAX_PROMPT_TOKENS,
+            truncated_words,
+        }
+    }
+
+    fn encode_word(&self, word: &str) -> Vec<u32> {
+        let mut ids = Vec::new();
+        for token in pre_tokenize(word) {
+            let unicode: String = token
+                .bytes()
+                .map(|b| self.byte_encoder[b as usize])
+                .collect();
+            for piece in self.bpe(&unicode) {
+                if let Some(&id) = self.encoder.get(&piece) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+
+    fn bpe(&self, token: &str) -> Vec<String> {
+        if token == START_OF_TEXT || token == END_OF_TEXT {
+            return vec![token.to_string()];
+        }
+        if let Some(cached) = self.cache.lock().ok().and_then(|c| c.get(token).cloned()) {
+            return cached;
+        }
+
+        let mut word: Vec<String> = token.chars().map(|c| c.to_string()).collect();
+        if let Some(last) = word.last_mut() {
+            last.push_str("</w>");
+        }
+
+        while word.len() > 1 {
+            let best = word
+                .windows(2)
+                .filter_map(|pair| {
+                    self.bpe_ranks
+                        .get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|&rank| (rank, pair[0].clone(), pair[1].clone()))
+                })
+                .min_by_key(|(rank, _, _)| *rank);
+
+            let Some((_, first, second)) = best else {
+                break;
+            };
+
+            let mut merged = Vec::with_capacity(word.len());
+            let mut i = 0;
+            while i < word.len() {
+                if i + 1 < word.len() && word[i] == first && word[i + 1] == second {
+                    merged.push(format!("{}{}", first, second));
+                    i += 2;
+                } else {
+                    merged.push(word[i].clone());
+                    i += 1;
+                }
+            }
+            word = merged;
+        }
+
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(token.to_string(), word.clone());
+        }
+        word
+    }
+}
+
+// GPT-2 style reversible byte to printable unicode mapping
+fn bytes_to_unicode() -> Vec<char> {
+    let mut printable: Vec<u32> = (b'!' as u32..=b'~' as u32)
+        .chain(0xA1..=0xAC)
+        .chain(0xAE..=0xFF)
+        .collect();
+    let mut mapping = vec!['\0'; 256];
+    for &b in &printable {
+        mapping[b as usize] = char::from_u32(b).unwrap_or('\0');
+    }
+    let mut n = 0;
+    for b in 0..256u32 {
+        if !printable.contains(&b) {
+            printable.push(b);
+            mapping[b as usize] = char::from_u32(256 + n).unwrap_or('\0');
+            n += 1;
+        }
+    }
+    mapping
+}
+
+// Mirrors basic_clean + whitespace_clean + lower() from the Python tokenizer
+// (minus ftfy, which only repairs mojibake)
+fn clean_text(text: &str) -> String {
+    let unescaped = html_unescape(&html_unescape(text));
+    unescaped
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn html_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+// Equivalent of the tokenizer regex:
+// <|startoftext|>|<|endoftext|>|'s|'t|'re|'ve|'m|'ll|'d|[\p{L}]+|[\p{N}]|[^\s\p{L}\p{N}]+
+fn pre_tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let len = if let Some(special) = [START_OF_TEXT, END_OF_TEXT]
+            .iter()
+            .find(|s| rest.starts_with(*s))
+        {
+            special.len()
+        } else if let Some(contraction) = CONTRACTIONS.iter().find(|s| rest.starts_with(*s)) {
+            contraction.len()
+        } else if c.is_alphabetic() {
+            run_length(rest, |c| c.is_alphabetic())
+        } else if c.is_numeric() {
+            c.len_utf8()
+        } else if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        } else {
+            run_length(rest, |c| !c.is_whitespace() && !c.is_alphabetic() && !c.is_numeric())
+        };
+
+        tokens.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+fn run_length(text: &str, pred: impl Fn(char) -> bool) -> usize {
+    text.char_indices()
+        .find(|(_, c)| !pred(*c))
+        .map(|(i, _)| i)
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Expected ids come from the backend's `SimpleTokenizer.encode` (without the
+    // start/end tokens), so any drift from the Python side fails here.
+
+    fn tokenizer() -> ClipTokenizer {
+        let vocab_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../backends/stable_diffusion/stable_diffusion/clip_tokenizer/bpe_simple_vocab_16e6.txt.gz");
+        ClipTokenizer::load(&vocab_path).unwrap()
+    }
+
+    fn long_prompt() -> String {
+        // 18 * 4 - 1 = 71 tokens, then "a cat on" takes it to 74 and "photorealistic"
+        // (2 tokens) straddles the limit
+        format!("{} a cat on photorealistic mars", vec!["masterpiece best quality"; 18].join(", "))
+    }
+
+    #[test]
+    fn encode_matches_simple_tokenizer() {
+        let tokenizer = tokenizer();
+        let cases: [(&str, &[u32]); 3] = [
+            (
+                "a photo of an astronaut riding a horse on mars",
+                &[320, 1125, 539, 550, 18376, 6765, 320, 4558, 525, 7496],
+            ),
+            (
+                "Portrait of a cat, highly detailed, 8k, trending on ArtStation",
+                &[5352, 539, 320, 2368, 267, 5302, 12609, 267, 279, 330, 267, 6087, 525, 1486, 2631],
+            ),
+            (
+                "don't   stop &amp; it's café-style!!",
+                &[847, 713, 1691, 261, 585, 568, 15304, 268, 1844, 748],
+            ),
+        ];
+        for (prompt, expected) in cases {
+            assert_eq!(tokenizer.encode(prompt), expected, "{:?}", prompt);
+            assert_eq!(tokenizer.count_prompt_tokens(prompt).token_count, expected.len());
+        }
+    }
+
+    #[test]
+    fn prompt_straddling_the_limit() {
+        let tokenizer = tokenizer();
+        let prompt = long_prompt();
+
+        let ids = tokenizer.encode(&prompt);
+        assert_eq!(ids.len(), 77);
+        // Python keeps the first 75, cutting "photorealistic" (1153 16157) in half
+        assert_eq!(ids[70..75], [3027, 320, 2368, 525, 1153]);
+        assert_eq!(ids[75..], [16157, 7496]);
+
+        let count = tokenizer.count_prompt_tokens(&prompt);
+        assert_eq!(count.token_count, 77);
+        assert_eq!(count.max_tokens, MAX_PROMPT_TOKENS);
+        assert_eq!(count.truncated_words, ["photorealistic", "mars"]);
+        assert!(count.warning().unwrap().contains("photorealistic mars"));
+    }
+
+    #[test]
+    fn short_prompt_has_no_warning() {
+        let count = tokenizer().count_prompt_tokens("a photo of an astronaut riding a horse on mars");
+        assert!(count.truncated_words.is_empty());
+        assert!(count.warning().is_none());
+    }
+}
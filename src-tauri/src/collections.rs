@@ -0,0 +1,407 @@
+// Tags, ratings, favorites and collections of history entries
+//
+// Tags, the star rating and the favorite mark are stored on the history entries
+// themselves. Tags are lowercase and kept sorted, so `Portrait` and `portrait`
+// are one tag.
+//
+// A collection (an album) is a named list of history entries, kept in
+// ~/.diffusionbee/collections.json in the order the entries were added. An
+// entry can be in any number of collections, and removing it from one leaves
+// the entry and its images alone.
+//
+// `export_collection` copies the images of a collection into a new folder or
+// zip together with manifest.json, which holds each entry with its prompt and
+// parameters and the names its images were given in `images/`:
+//
+//   {"format": "diffusionbee-collection", "version": 1, "name": ...,
+//    "entries": [{"entry": {...}, "files": ["images/..."]}], "missing": [...]}
+
+use crate::history::{self, HistoryEntry};
+use crate::json_store::Store;
+use crate::{output_files, DiffusionError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const COLLECTIONS_FILE_NAME: &str = "collections.json";
+const MANIFEST_FORMAT: &str = "diffusionbee-collection";
+const MANIFEST_VERSION: u32 = 1;
+const MAX_NAME_LEN: usize = 64;
+const MAX_TAG_LEN: usize = 64;
+pub const MAX_RATING: u8 = 5;
+
+static COLLECTIONS: Store<Collection> = Store::pretty(COLLECTIONS_FILE_NAME, "collections");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    /// Unique, ignoring case
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// RFC 3339, UTC
+    pub created: String,
+    /// History entries in the order they were added
+    #[serde(default)]
+    pub entry_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    /// Entries having the tag
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Folder,
+    Zip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectionExport {
+    /// The folder or zip written
+    pub path: PathBuf,
+    pub entries: usize,
+    pub files: usize,
+    /// Images that no longer exist, and entries no longer in the history
+    pub missing: Vec<String>,
+}
+
+// Tags, favorites and ratings
+
+fn normalize_tag(tag: &str) -> Result<String, DiffusionError> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err(DiffusionError::validation("tags", "Tags cannot be empty"));
+    }
+    if tag.chars().count() > MAX_TAG_LEN {
+        return Err(DiffusionError::validation(
+            "tags",
+            format!("Tags must be at most {} characters", MAX_TAG_LEN),
+        ));
+    }
+    if tag.contains(',') {
+        return Err(DiffusionError::validation("tags", format!("Tag '{}' cannot contain a comma", tag)));
+    }
+    Ok(tag)
+}
+
+fn normalize_tags(tags: &[String]) -> Result<BTreeSet<String>, DiffusionError> {
+    tags.iter().map(|tag| normalize_tag(tag)).collect()
+}
+
+/// Adds `tags` to the entries `ids`
+pub fn add_tags(ids: &[String], tags: &[String]) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    let tags = normalize_tags(tags)?;
+    history::update(ids, |entry| {
+        let mut all: BTreeSet<String> = entry.tags.drain(..).collect();
+        all.extend(tags.iter().cloned());
+        entry.tags = all.into_iter().collect();
+    })
+}
+
+/// Removes `tags` from the entries `ids`; tags they don't have are ignored
+pub fn remove_tags(ids: &[String], tags: &[String]) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    let tags = normalize_tags(tags)?;
+    history::update(ids, |entry| entry.tags.retain(|tag| !tags.contains(tag)))
+}
+
+/// Every tag in use, the most used first
+pub fn list_tags() -> Result<Vec<TagCount>, DiffusionError> {
+    let counts = history::read(|entries| {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for tag in entries.iter().flat_map(|e| &e.tags) {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+        counts
+    })?;
+    let mut tags: Vec<TagCount> = counts.into_iter().map(|(tag, count)| TagCount { tag, count }).collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    Ok(tags)
+}
+
+pub fn set_favorite(ids: &[String], favorite: bool) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    history::update(ids, |entry| entry.favorite = favorite)
+}
+
+/// Sets the star rating of the entries `ids`, or clears it with `None`
+pub fn set_rating(ids: &[String], rating: Option<u8>) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    if rating.is_some_and(|stars| !(1..=MAX_RATING).contains(&stars)) {
+        return Err(DiffusionError::validation(
+            "rating",
+            format!("Rating must be between 1 and {} stars", MAX_RATING),
+        ));
+    }
+    history::update(ids, |entry| entry.rating = rating)
+}
+
+// Collections
+
+/// ~/.diffusionbee/collections.json
+pub fn collections_path() -> Option<PathBuf> {
+    COLLECTIONS.path()
+}
+
+/// Every collection, sorted by name
+pub fn list() -> Result<Vec<Collection>, DiffusionError> {
+    COLLECTIONS.with(|collections| collections.clone())
+}
+
+pub fn get(name: &str) -> Result<Collection, DiffusionError> {
+    COLLECTIONS.with(|collections| collections.iter().find(|c| same_name(&c.name, name)).cloned())?
+        .ok_or_else(|| unknown(name))
+}
+
+pub fn create(name: &str, description: &str) -> Result<Collection, DiffusionError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(DiffusionError::validation("name", "Collection name cannot be empty"));
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(DiffusionError::validation(
+            "name",
+            format!("Collection name must be at most {} characters", MAX_NAME_LEN),
+        ));
+    }
+    COLLECTIONS.with(|collections| {
+        if collections.iter().any(|c| same_name(&c.name, name)) {
+            return Err(DiffusionError::validation(
+                "name",
+                format!("A collection named {} already exists", name),
+            ));
+        }
+        let collection = Collection {
+            name: name.to_string(),
+            description: description.trim().to_string(),
+            created: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            entry_ids: Vec::new(),
+        };
+        let index = collections.partition_point(|c| c.name.to_lowercase() < name.to_lowercase());
+        collections.insert(index, collection.clone());
+        COLLECTIONS.save(collections)?;
+        Ok(collection)
+    })?
+}
+
+/// Deletes the collection; its entries stay in the history
+pub fn delete(name: &str) -> Result<(), DiffusionError> {
+    COLLECTIONS.with(|collections| {
+        let before = collections.len();
+        collections.retain(|c| !same_name(&c.name, name));
+        if collections.len() == before {
+            return Err(unknown(name));
+        }
+        COLLECTIONS.save(collections)
+    })?
+}
+
+/// Appends the entries `ids` that aren't in the collection yet
+pub fn add_entries(name: &str, ids: &[String]) -> Result<Collection, DiffusionError> {
+    for id in ids {
+        history::get(id)?;
+    }
+    update(name, |collection| {
+        for id in ids {
+            if !collection.entry_ids.contains(id) {
+                collection.entry_ids.push(id.clone());
+            }
+        }
+    })
+}
+
+pub fn remove_entries(name: &str, ids: &[String]) -> Result<Collection, DiffusionError> {
+    update(name, |collection| collection.entry_ids.retain(|id| !ids.contains(id)))
+}
+
+fn update(name: &str, f: impl FnOnce(&mut Collection)) -> Result<Collection, DiffusionError> {
+    COLLECTIONS.with(|collections| {
+        let collection = collections
+            .iter_mut()
+            .find(|c| same_name(&c.name, name))
+            .ok_or_else(|| unknown(name))?;
+        f(collection);
+        let collection = collection.clone();
+        COLLECTIONS.save(collections)?;
+        Ok(collection)
+    })?
+}
+
+fn unknown(name: &str) -> DiffusionError {
+    DiffusionError::validation("name", format!("Unknown collection: {}", name))
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+// Export
+
+/// Writes the collection `name` as a folder or zip named after it inside
+/// `destination`. An existing folder or zip of that name is never overwritten.
+pub fn export(name: &str, destination: &Path, format: ExportFormat) -> Result<CollectionExport, DiffusionError> {
+    let collection = get(name)?;
+    let stem = match output_files::sanitize(&collection.name).trim_matches('_') {
+        "" => "collection".to_string(),
+        stem => stem.to_string(),
+    };
+    let path = match format {
+        ExportFormat::Folder => destination.join(&stem),
+        ExportFormat::Zip => destination.join(format!("{}.zip", stem)),
+    };
+    if path.exists() {
+        return Err(DiffusionError::FileSystem(format!("{} already exists", path.display())));
+    }
+    fs::create_dir_all(destination)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", destination.display(), e)))?;
+    log::info!("Exporting collection {} to {}", collection.name, path.display());
+
+    let mut target = match format {
+        ExportFormat::Folder => {
+            fs::create_dir(&path)
+                .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", path.display(), e)))?;
+            Target::Folder(path.clone())
+        }
+        ExportFormat::Zip => {
+            let file = File::create(&path)
+                .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", path.display(), e)))?;
+            Target::Zip(Box::new(ZipWriter::new(file)))
+        }
+    };
+
+    let written = write_collection(&collection, &mut target).and_then(|summary| target.finish().map(|_| summary));
+    let (entries, files, missing) = match written {
+        Ok(summary) => summary,
+        Err(e) => {
+            // Don't leave half an export behind
+            let _ = match format {
+                ExportFormat::Folder => fs::remove_dir_all(&path),
+                ExportFormat::Zip => fs::remove_file(&path),
+            };
+            return Err(e);
+        }
+    };
+    for item in &missing {
+        log::warn!("Not in the export of {}: {}", collection.name, item);
+    }
+    Ok(CollectionExport {
+        path,
+        entries,
+        files,
+        missing,
+    })
+}
+
+// Copies the images and writes the manifest, returning the number of entries
+// and files and what was missing
+fn write_collection(collection: &Collection, target: &mut Target) -> Result<(usize, usize, Vec<String>), DiffusionError> {
+    let mut names = BTreeSet::new();
+    let mut manifest_entries = Vec::new();
+    let mut missing = Vec::new();
+    for id in &collection.entry_ids {
+        let entry = match history::get(id) {
+            Ok(entry) => entry,
+            Err(_) => {
+                missing.push(format!("history entry {}", id));
+                continue;
+            }
+        };
+        let mut files = Vec::new();
+        for image in &entry.images {
+            let source = Path::new(image);
+            if !source.is_file() {
+                missing.push(image.clone());
+                continue;
+            }
+            let name = unique_name(&mut names, source);
+            target.add_file(&name, source)?;
+            files.push(name);
+        }
+        manifest_entries.push(json!({ "entry": entry, "files": files }));
+    }
+
+    let file_count = names.len();
+    let manifest = json!({
+        "format": MANIFEST_FORMAT,
+        "version": MANIFEST_VERSION,
+        "name": collection.name,
+        "description": collection.description,
+        "exported": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+        "entries": manifest_entries,
+        "missing": missing,
+    });
+    target.add_bytes("manifest.json", serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    Ok((manifest_entries.len(), file_count, missing))
+}
+
+// `images/<file name>`, with `_1`, `_2`, ... before the extension when taken
+fn unique_name(names: &mut BTreeSet<String>, source: &Path) -> String {
+    let stem = source.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = source.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut name = format!("images/{}{}", stem, extension);
+    let mut attempt = 1;
+    while names.contains(&name) {
+        name = format!("images/{}_{}{}", stem, attempt, extension);
+        attempt += 1;
+    }
+    names.insert(name.clone());
+    name
+}
+
+enum Target {
+    Folder(PathBuf),
+    Zip(Box<ZipWriter<File>>),
+}
+
+impl Target {
+    fn add_file(&mut self, name: &str, source: &Path) -> Result<(), DiffusionError> {
+        let content = fs::read(source)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", source.display(), e)))?;
+        self.add_bytes(name, &content)
+    }
+
+    fn add_bytes(&mut self, name: &str, content: &[u8]) -> Result<(), DiffusionError> {
+        match self {
+            Target::Folder(folder) => {
+                let path = folder.join(name);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, content)
+                    .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", path.display(), e)))
+            }
+            Target::Zip(writer) => {
+                // Images are compressed already
+                let method = if name.ends_with(".json") {
+                    zip::CompressionMethod::Deflated
+                } else {
+                    zip::CompressionMethod::Stored
+                };
+                writer
+                    .start_file(name, SimpleFileOptions::default().compression_method(method))
+                    .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", name, e)))?;
+                writer.write_all(content)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), DiffusionError> {
+        match self {
+            Target::Folder(_) => Ok(()),
+            Target::Zip(writer) => writer
+                .finish()
+                .map(|_| ())
+                .map_err(|e| DiffusionError::FileSystem(format!("Failed to finish the collection zip: {}", e))),
+        }
+    }
+}
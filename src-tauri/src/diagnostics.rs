@@ -0,0 +1,231 @@
+// Diagnostics bundle for bug reports
+//
+// `export_diagnostics` writes one zip holding what is usually asked for when a
+// generation fails on someone else's machine:
+//
+//   system.json    app version, OS, architecture, backend script and log level
+//   settings.json  the saved settings, API token always removed
+//   models.json    imported models with their .tdict headers
+//   python.json    the probe of every candidate interpreter
+//   logs/          the most recent log files
+//
+// With `redact_paths` the path settings are blanked and the home directory is
+// replaced by `~` everywhere else, logs included.
+
+use crate::{backend_locator, logging, model_registry, python_env, AppSettings, DiffusionError};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Newest log files are included until this much has been added
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DiagnosticsOptions {
+    /// Blank path settings and replace the home directory with `~`
+    pub redact_paths: bool,
+    /// Where to write the zip, ~/.diffusionbee/diagnostics by default
+    pub output_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsBundle {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// Files in the zip
+    pub files: Vec<String>,
+}
+
+/// ~/.diffusionbee/diagnostics
+pub fn diagnostics_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".diffusionbee").join("diagnostics"))
+}
+
+/// Collects everything and writes the zip
+pub async fn export(settings: &AppSettings, options: &DiagnosticsOptions) -> Result<DiagnosticsBundle, DiffusionError> {
+    let created = OffsetDateTime::now_utc();
+    let redactor = Redactor::new(options.redact_paths);
+
+    let path = match options.output_path.as_deref().filter(|p| !p.trim().is_empty()) {
+        Some(path) => PathBuf::from(path.trim()),
+        None => diagnostics_dir()
+            .ok_or_else(|| DiffusionError::FileSystem("Failed to get home directory".to_string()))?
+            .join(format!("diffusionbee-diagnostics-{}.zip", created.unix_timestamp())),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+
+    let backend_script = backend_locator::resolve_backend_script(&settings.backend_script_path);
+    log::info!("Exporting diagnostics to {}", path.display());
+
+    let mut models = model_registry::scan_models(&settings.model_path);
+    model_registry::read_headers(&mut models);
+
+    let backends_dir = backend_script.as_deref().ok().and_then(crate::backends_dir);
+    let mut probes = Vec::new();
+    for candidate in python_env::discover_interpreters(&settings.python_path, backends_dir).await {
+        probes.push(python_env::probe_interpreter(&candidate).await);
+    }
+
+    let system = json!({
+        "created": created.format(&Rfc3339).unwrap_or_default(),
+        "app_version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "os_family": std::env::consts::FAMILY,
+        "os_version": os_version(),
+        "arch": std::env::consts::ARCH,
+        "backend_script": match &backend_script {
+            Ok(path) => json!(path),
+            Err(e) => json!({ "error": e.message() }),
+        },
+        "mock_backend": crate::mock_backend::is_enabled(settings),
+        "log_level": std::env::var(logging::LOG_LEVEL_ENV).ok(),
+    });
+
+    let mut zip = Zip::create(&path, redactor)?;
+    zip.add_json("system.json", &system)?;
+    zip.add_json("settings.json", &settings_json(settings, options.redact_paths)?)?;
+    zip.add_json("models.json", &serde_json::to_value(&models)?)?;
+    zip.add_json("python.json", &serde_json::to_value(&probes)?)?;
+    for log_file in recent_log_files() {
+        let Some(name) = log_file.file_name() else {
+            continue;
+        };
+        let contents = fs::read_to_string(&log_file)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", log_file.display(), e)))?;
+        zip.add_text(&format!("logs/{}", name.to_string_lossy()), &contents)?;
+    }
+    let files = zip.finish()?;
+
+    let size_bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    Ok(DiagnosticsBundle { path, size_bytes, files })
+}
+
+fn settings_json(settings: &AppSettings, redact_paths: bool) -> Result<Value, DiffusionError> {
+    let mut settings = settings.clone();
+    if !settings.api_server_token.is_empty() {
+        settings.api_server_token = REDACTED.to_string();
+    }
+    if redact_paths {
+        for path in [
+            &mut settings.output_directory,
+            &mut settings.model_path,
+            &mut settings.wildcards_directory,
+            &mut settings.python_path,
+            &mut settings.backend_script_path,
+        ] {
+            if !path.is_empty() {
+                *path = REDACTED.to_string();
+            }
+        }
+    }
+    Ok(serde_json::to_value(settings)?)
+}
+
+// Newest first until the size budget is used up, then back in file order
+fn recent_log_files() -> Vec<PathBuf> {
+    let mut budget = MAX_LOG_BYTES;
+    let mut files: Vec<PathBuf> = logging::log_files()
+        .into_iter()
+        .rev()
+        .take_while(|path| {
+            let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            let fits = size <= budget;
+            budget = budget.saturating_sub(size);
+            fits
+        })
+        .collect();
+    files.reverse();
+    files
+}
+
+fn os_version() -> Option<String> {
+    if cfg!(target_os = "linux") {
+        let release = fs::read_to_string("/etc/os-release").ok()?;
+        let pretty = release.lines().find_map(|line| line.strip_prefix("PRETTY_NAME="))?;
+        return Some(pretty.trim_matches('"').to_string());
+    }
+    let (program, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("sw_vers", &["-productVersion"])
+    } else if cfg!(windows) {
+        ("cmd", &["/C", "ver"])
+    } else {
+        ("uname", &["-sr"])
+    };
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!version.is_empty()).then_some(version)
+}
+
+// Replaces the home directory with `~` when paths are redacted
+struct Redactor {
+    home: Option<String>,
+}
+
+impl Redactor {
+    fn new(redact_paths: bool) -> Self {
+        let home = dirs::home_dir()
+            .filter(|_| redact_paths)
+            .map(|home| home.to_string_lossy().to_string())
+            .filter(|home| home.len() > 1);
+        Self { home }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match &self.home {
+            // JSON escapes backslashes in Windows paths, so replace both spellings
+            Some(home) => text.replace(home, "~").replace(&home.replace('\\', "\\\\"), "~"),
+            None => text.to_string(),
+        }
+    }
+}
+
+struct Zip {
+    writer: ZipWriter<File>,
+    redactor: Redactor,
+    files: Vec<String>,
+}
+
+impl Zip {
+    fn create(path: &Path, redactor: Redactor) -> Result<Self, DiffusionError> {
+        let file = File::create(path)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", path.display(), e)))?;
+        Ok(Self {
+            writer: ZipWriter::new(file),
+            redactor,
+            files: Vec::new(),
+        })
+    }
+
+    fn add_json(&mut self, name: &str, value: &Value) -> Result<(), DiffusionError> {
+        self.add_text(name, &serde_json::to_string_pretty(value)?)
+    }
+
+    fn add_text(&mut self, name: &str, text: &str) -> Result<(), DiffusionError> {
+        let zip_error = |e: zip::result::ZipError| DiffusionError::FileSystem(format!("Failed to write {}: {}", name, e));
+        self.writer
+            .start_file(name, SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated))
+            .map_err(zip_error)?;
+        self.writer.write_all(self.redactor.apply(text).as_bytes())?;
+        self.files.push(name.to_string());
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<String>, DiffusionError> {
+        self.writer
+            .finish()
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to finish the diagnostics zip: {}", e)))?;
+        Ok(self.files)
+    }
+}
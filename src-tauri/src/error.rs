@@ -0,0 +1,276 @@
+// Errors returned to the frontend
+//
+// Commands fail with a `DiffusionError`, which serializes to
+// `{ kind, message, retryable, details }` so the UI can react to the kind of
+// failure (highlight a field, offer a retry, show the backend traceback) instead
+// of displaying a raw string.
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+const TRACEBACK_HEADER: &str = "Traceback (most recent call last):";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiffusionError {
+    #[error("Python backend error: {message}")]
+    PythonBackend {
+        message: String,
+        /// Traceback the backend printed to stderr before reporting `sdbk errr`
+        traceback: Option<String>,
+    },
+    #[error("File system error: {0}")]
+    FileSystem(String),
+    #[error("JSON serialization error: {0}")]
+    JsonError(String),
+    #[error("Process error: {0}")]
+    ProcessError(String),
+    #[error("Validation error: {message}")]
+    Validation {
+        /// Request field the error refers to, when there is one
+        field: Option<String>,
+        message: String,
+    },
+    #[error("Model not found: {0}")]
+    ModelNotFound(String),
+    #[error("{}", backend_not_found_message(.0))]
+    BackendNotFound(
+        /// Every location that was checked for the backend script
+        Vec<PathBuf>,
+    ),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+impl DiffusionError {
+    pub fn validation(field: &str, message: impl Into<String>) -> Self {
+        DiffusionError::Validation {
+            field: Some(field.to_string()),
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        DiffusionError::Validation {
+            field: None,
+            message: message.into(),
+        }
+    }
+
+    /// A backend failure, with the traceback pulled out of its stderr if it printed one
+    pub fn backend(message: impl Into<String>, stderr: &str) -> Self {
+        DiffusionError::PythonBackend {
+            message: message.into(),
+            traceback: extract_traceback(stderr),
+        }
+    }
+
+    /// A poisoned global state mutex
+    pub fn lock(what: &str) -> Self {
+        DiffusionError::Internal(format!("Failed to acquire {} lock", what))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DiffusionError::PythonBackend { .. } => "python_backend",
+            DiffusionError::FileSystem(_) => "file_system",
+            DiffusionError::JsonError(_) => "json",
+            DiffusionError::ProcessError(_) => "process",
+            DiffusionError::Validation { .. } => "validation",
+            DiffusionError::ModelNotFound(_) => "model_not_found",
+            DiffusionError::BackendNotFound(_) => "backend_not_found",
+            DiffusionError::Cancelled => "cancelled",
+            DiffusionError::Internal(_) => "internal",
+        }
+    }
+
+    /// The message without the kind prefix `Display` adds
+    pub fn message(&self) -> String {
+        match self {
+            DiffusionError::PythonBackend { message, .. } | DiffusionError::Validation { message, .. } => {
+                message.clone()
+            }
+            DiffusionError::FileSystem(message)
+            | DiffusionError::JsonError(message)
+            | DiffusionError::ProcessError(message)
+            | DiffusionError::ModelNotFound(message)
+            | DiffusionError::Internal(message) => message.clone(),
+            DiffusionError::BackendNotFound(tried) => backend_not_found_message(tried),
+            DiffusionError::Cancelled => "Generation cancelled".to_string(),
+        }
+    }
+
+    /// Whether running the same request again could succeed without changing it
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            DiffusionError::PythonBackend { .. }
+                | DiffusionError::ProcessError(_)
+                | DiffusionError::FileSystem(_)
+                | DiffusionError::Cancelled
+        )
+    }
+
+    pub fn details(&self) -> Value {
+        match self {
+            DiffusionError::PythonBackend { traceback, .. } => json!({ "traceback": traceback }),
+            DiffusionError::Validation { field, .. } => json!({ "field": field }),
+            DiffusionError::ModelNotFound(path) => json!({ "model_path": path }),
+            DiffusionError::BackendNotFound(tried) => json!({ "tried": tried }),
+            _ => json!({}),
+        }
+    }
+}
+
+impl Serialize for DiffusionError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("DiffusionError", 4)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.message())?;
+        state.serialize_field("retryable", &self.retryable())?;
+        state.serialize_field("details", &self.details())?;
+        state.end()
+    }
+}
+
+impl From<serde_json::Error> for DiffusionError {
+    fn from(e: serde_json::Error) -> Self {
+        DiffusionError::JsonError(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for DiffusionError {
+    fn from(e: std::io::Error) -> Self {
+        DiffusionError::FileSystem(e.to_string())
+    }
+}
+
+fn backend_not_found_message(tried: &[PathBuf]) -> String {
+    let tried: Vec<String> = tried.iter().map(|p| p.display().to_string()).collect();
+    format!("Python backend script not found. Tried: {}", tried.join(", "))
+}
+
+/// Returns the last Python traceback in `stderr`, if any
+pub fn extract_traceback(stderr: &str) -> Option<String> {
+    let start = stderr.rfind(TRACEBACK_HEADER)?;
+    let traceback = stderr[start..].trim_end();
+    Some(traceback.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// One of each variant. The match fails to compile when a variant is added,
+    /// so the snapshots below have to be extended with it.
+    pub(crate) fn every_variant() -> Vec<DiffusionError> {
+        let errors = vec![
+            DiffusionError::backend(
+                "Out of memory",
+                "loading\nTraceback (most recent call last):\n  File \"sd.py\", line 1\nMemoryError\n",
+            ),
+            DiffusionError::FileSystem("Disk full".to_string()),
+            DiffusionError::JsonError("expected value".to_string()),
+            DiffusionError::ProcessError("Failed to start".to_string()),
+            DiffusionError::validation("img_width", "Width must be a multiple of 8"),
+            DiffusionError::ModelNotFound("/models/sd.tdict".to_string()),
+            DiffusionError::BackendNotFound(vec![PathBuf::from("/a/backend.py"), PathBuf::from("/b/backend.py")]),
+            DiffusionError::Cancelled,
+            DiffusionError::Internal("Failed to acquire progress lock".to_string()),
+        ];
+        for error in &errors {
+            match error {
+                DiffusionError::PythonBackend { .. }
+                | DiffusionError::FileSystem(_)
+                | DiffusionError::JsonError(_)
+                | DiffusionError::ProcessError(_)
+                | DiffusionError::Validation { .. }
+                | DiffusionError::ModelNotFound(_)
+                | DiffusionError::BackendNotFound(_)
+                | DiffusionError::Cancelled
+                | DiffusionError::Internal(_) => {}
+            }
+        }
+        errors
+    }
+
+    // The frontend keys on these; changing one is a breaking change for it
+    #[test]
+    fn serialized_shape() {
+        let expected = [
+            json!({
+                "kind": "python_backend",
+                "message": "Out of memory",
+                "retryable": true,
+                "details": {
+                    "traceback": "Traceback (most recent call last):\n  File \"sd.py\", line 1\nMemoryError"
+                },
+            }),
+            json!({ "kind": "file_system", "message": "Disk full", "retryable": true, "details": {} }),
+            json!({ "kind": "json", "message": "expected value", "retryable": false, "details": {} }),
+            json!({ "kind": "process", "message": "Failed to start", "retryable": true, "details": {} }),
+            json!({
+                "kind": "validation",
+                "message": "Width must be a multiple of 8",
+                "retryable": false,
+                "details": { "field": "img_width" },
+            }),
+            json!({
+                "kind": "model_not_found",
+                "message": "/models/sd.tdict",
+                "retryable": false,
+                "details": { "model_path": "/models/sd.tdict" },
+            }),
+            json!({
+                "kind": "backend_not_found",
+                "message": "Python backend script not found. Tried: /a/backend.py, /b/backend.py",
+                "retryable": false,
+                "details": { "tried": ["/a/backend.py", "/b/backend.py"] },
+            }),
+            json!({ "kind": "cancelled", "message": "Generation cancelled", "retryable": true, "details": {} }),
+            json!({
+                "kind": "internal",
+                "message": "Failed to acquire progress lock",
+                "retryable": false,
+                "details": {},
+            }),
+        ];
+        let errors = every_variant();
+        assert_eq!(errors.len(), expected.len());
+        for (error, expected) in errors.iter().zip(expected) {
+            assert_eq!(serde_json::to_value(error).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn validation_without_a_field() {
+        assert_eq!(
+            serde_json::to_value(DiffusionError::invalid("Prompt cannot be empty")).unwrap(),
+            json!({
+                "kind": "validation",
+                "message": "Prompt cannot be empty",
+                "retryable": false,
+                "details": { "field": null },
+            })
+        );
+    }
+
+    #[test]
+    fn backend_error_without_traceback() {
+        let error = DiffusionError::backend("Model load failed", "just some output\n");
+        assert_eq!(error.details(), json!({ "traceback": null }));
+        assert_eq!(error.to_string(), "Python backend error: Model load failed");
+    }
+
+    #[test]
+    fn last_traceback_is_kept() {
+        let stderr = "Traceback (most recent call last):\nfirst\nwarning\nTraceback (most recent call last):\nsecond\n\n";
+        assert_eq!(
+            extract_traceback(stderr).as_deref(),
+            Some("Traceback (most recent call last):\nsecond")
+        );
+        assert_eq!(extract_traceback("no traceback"), None);
+    }
+}
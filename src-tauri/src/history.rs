@@ -0,0 +1,287 @@
+// Generation history
+//
+// Every finished job is recorded in ~/.diffusionbee/history.json together with
+// the request and model that produced it, so an image can be traced back to its
+// prompt and settings. Images made from another entry's image (an upscale or a
+// variation, say) get an entry of their own whose `derivation` points at the
+// entry they came from. Entries can be tagged, rated, marked as favorites
+// and gathered into named collections, see `collections`.
+//
+// The file is read once and kept in memory, see `json_store`.
+
+use crate::json_store::Store;
+use crate::post_processing::UpscaleMethod;
+use crate::{model_registry, AppSettings, DiffusionError, ImageGenerationRequest, LoraWeight};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const HISTORY_FILE_NAME: &str = "history.json";
+
+static HISTORY: Store<HistoryEntry> = Store::new(HISTORY_FILE_NAME, "history");
+static ENTRY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    /// RFC 3339, UTC
+    pub created: String,
+    /// The request as it was sent to the backend, prompt already expanded
+    pub request: ImageGenerationRequest,
+    /// Model file name without extension
+    pub model: String,
+    /// Empty for entries recorded before it was stored
+    #[serde(default)]
+    pub model_path: String,
+    /// Identify the exact model and LoRA files, empty when they couldn't be read
+    #[serde(default)]
+    pub hashes: FileHashes,
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub job_id: Option<String>,
+    /// Set when the images were made from another entry's image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derivation: Option<Derivation>,
+    /// Lowercase, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub favorite: bool,
+    /// Stars, 1 to 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    /// Set for images made elsewhere and imported, see `image_import`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported: Option<Imported>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileHashes {
+    /// SHA-256 of the model file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// LoRA path -> SHA-256
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub loras: BTreeMap<String, String>,
+}
+
+impl FileHashes {
+    /// Hashes the model at `model_path`, if any, and `loras`
+    pub async fn of(model_path: &str, loras: &[LoraWeight]) -> Result<Self, DiffusionError> {
+        let model_path = model_path.to_string();
+        let loras: Vec<String> = loras.iter().map(|lora| lora.path.clone()).collect();
+        // Hashing reads every byte of the files
+        tokio::task::spawn_blocking(move || {
+            let model = match model_path.as_str() {
+                "" => None,
+                path => Some(model_registry::file_hash(Path::new(path))?),
+            };
+            let loras = loras
+                .into_iter()
+                .map(|path| model_registry::file_hash(Path::new(&path)).map(|hash| (path, hash)))
+                .collect::<Result<_, _>>()?;
+            Ok(Self { model, loras })
+        })
+        .await
+        .map_err(|e| DiffusionError::Internal(format!("Hashing failed: {}", e)))?
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Derivation {
+    pub parent_id: String,
+    /// The parent's image this one was made from
+    pub source_image: String,
+    #[serde(flatten)]
+    pub kind: DerivationKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DerivationKind {
+    Upscale { method: UpscaleMethod, scale: f32 },
+    /// Same request and seed with part of the noise from `small_mod_seed`
+    Variation { small_mod_seed: u32, strength: f32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Imported {
+    /// SHA-256 of the image file
+    pub content_hash: String,
+    /// Whether the request was read from the image; without it only the size is known
+    pub metadata: bool,
+    /// Preview made in the background after the import, may not exist yet
+    pub thumbnail: String,
+}
+
+impl HistoryEntry {
+    /// A new entry for `images` made with the model in `settings`, stamped with
+    /// the current time and job
+    pub fn new(request: ImageGenerationRequest, settings: &AppSettings, images: Vec<String>) -> Self {
+        Self {
+            id: new_entry_id(),
+            created: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            request,
+            model: settings.model_name(),
+            model_path: settings.model_path.clone(),
+            hashes: FileHashes::default(),
+            images,
+            job_id: crate::logging::current_job(),
+            derivation: None,
+            tags: Vec::new(),
+            favorite: false,
+            rating: None,
+            imported: None,
+        }
+    }
+
+    /// An entry for an image made elsewhere, dated when the file was
+    pub fn imported(
+        request: ImageGenerationRequest,
+        model: String,
+        image: String,
+        created: String,
+        imported: Imported,
+    ) -> Self {
+        Self {
+            id: new_entry_id(),
+            created,
+            request,
+            model,
+            model_path: String::new(),
+            hashes: FileHashes::default(),
+            images: vec![image],
+            job_id: None,
+            derivation: None,
+            tags: Vec::new(),
+            favorite: false,
+            rating: None,
+            imported: Some(imported),
+        }
+    }
+
+    /// A new entry for an image made from `source_image` of this one
+    pub fn derive(&self, source_image: &str, kind: DerivationKind, images: Vec<String>) -> Self {
+        Self {
+            id: new_entry_id(),
+            created: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            images,
+            job_id: crate::logging::current_job(),
+            derivation: Some(Derivation {
+                parent_id: self.id.clone(),
+                source_image: source_image.to_string(),
+                kind,
+            }),
+            tags: Vec::new(),
+            favorite: false,
+            rating: None,
+            imported: None,
+            ..self.clone()
+        }
+    }
+
+    /// `image` if it is one of this entry's images, the first image when `None`
+    pub fn image(&self, image: Option<String>) -> Result<String, DiffusionError> {
+        match image {
+            Some(image) if self.images.contains(&image) => Ok(image),
+            Some(image) => Err(DiffusionError::validation(
+                "image",
+                format!("{} is not an image of history entry {}", image, self.id),
+            )),
+            None => self
+                .images
+                .first()
+                .cloned()
+                .ok_or_else(|| DiffusionError::validation("history_id", "History entry has no images")),
+        }
+    }
+
+    /// `current` with the model this entry was generated with, for running its
+    /// request again
+    pub fn settings(&self, current: &AppSettings) -> Result<AppSettings, DiffusionError> {
+        let mut settings = current.clone();
+        if !self.model_path.is_empty() {
+            if !crate::mock_backend::is_enabled(current) && !Path::new(&self.model_path).exists() {
+                return Err(DiffusionError::ModelNotFound(format!(
+                    "Model {} of history entry {} is no longer at: {}",
+                    self.model, self.id, self.model_path
+                )));
+            }
+            settings.model_path = self.model_path.clone();
+        } else if self.model != current.model_name() {
+            // Older entries only know the model's name
+            return Err(DiffusionError::ModelNotFound(format!(
+                "History entry {} was made with {}, select that model to run it again",
+                self.id, self.model
+            )));
+        }
+        Ok(settings)
+    }
+}
+
+/// ~/.diffusionbee/history.json
+pub fn history_path() -> Option<PathBuf> {
+    HISTORY.path()
+}
+
+fn new_entry_id() -> String {
+    let millis = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+    format!("h-{}-{}", millis, ENTRY_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Adds `entry` and saves the history
+pub fn record(entry: HistoryEntry) -> Result<(), DiffusionError> {
+    HISTORY.with(|entries| {
+        entries.push(entry);
+        HISTORY.save(entries)
+    })?
+}
+
+/// Adds `entries` and saves the history once
+pub fn record_all(new_entries: Vec<HistoryEntry>) -> Result<(), DiffusionError> {
+    HISTORY.with(|entries| {
+        entries.extend(new_entries);
+        HISTORY.save(entries)
+    })?
+}
+
+pub fn get(id: &str) -> Result<HistoryEntry, DiffusionError> {
+    HISTORY.with(|entries| entries.iter().find(|e| e.id == id).cloned())?
+        .ok_or_else(|| DiffusionError::validation("history_id", format!("Unknown history entry: {}", id)))
+}
+
+/// Changes the entries `ids` with `f` and saves the history. Nothing changes
+/// when one of the ids is unknown.
+pub fn update(ids: &[String], mut f: impl FnMut(&mut HistoryEntry)) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    HISTORY.with(|entries| {
+        if let Some(unknown) = ids.iter().find(|id| !entries.iter().any(|e| e.id == **id)) {
+            return Err(DiffusionError::validation("history_id", format!("Unknown history entry: {}", unknown)));
+        }
+        let mut updated = Vec::new();
+        for entry in entries.iter_mut().filter(|e| ids.contains(&e.id)) {
+            f(entry);
+            updated.push(entry.clone());
+        }
+        HISTORY.save(entries)?;
+        Ok(updated)
+    })?
+}
+
+/// Runs `f` on every entry, oldest first, without copying them
+pub fn read<T>(f: impl FnOnce(&[HistoryEntry]) -> T) -> Result<T, DiffusionError> {
+    HISTORY.with(|entries| f(entries))
+}
+
+/// Entries made from the images of entry `id`, oldest first
+pub fn derivatives(id: &str) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    HISTORY.with(|entries| {
+        entries
+            .iter()
+            .filter(|e| e.derivation.as_ref().is_some_and(|d| d.parent_id == id))
+            .cloned()
+            .collect()
+    })
+}
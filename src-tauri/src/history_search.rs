@@ -0,0 +1,368 @@
+// Searching the generation history
+//
+// `query` finds entries by the words of their prompt and filters them on model,
+// date, seed, size and other parameters. Words are looked up in an inverted
+// index (word -> positions of the entries whose prompt has it), so text search
+// stays fast with tens of thousands of entries; the other filters then only
+// look at the entries the words matched.
+//
+// The history only ever grows, so the index is built on the first query and
+// then extended with the entries recorded since.
+//
+// Entries can also be filtered on their tags, rating and favorite mark, and on
+// being in a collection.
+//
+// Text syntax: every word must appear in the prompt, ignoring case, and the
+// last word may be unfinished (`lightho` finds `lighthouse`). Words in double
+// quotes must appear together as written.
+
+use crate::history::{self, HistoryEntry};
+use crate::{collections, DiffusionError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+pub const DEFAULT_LIMIT: usize = 100;
+
+static INDEX: Mutex<Option<SearchIndex>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistorySort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Alphabetical by prompt
+    Prompt,
+    /// By model name, newest first within a model
+    Model,
+}
+
+/// Every set field must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    /// Words to find in the prompt, see above
+    pub text: String,
+    /// Model name, ignoring case
+    pub model: Option<String>,
+    /// Created at or after, RFC 3339 or a `YYYY-MM-DD` date
+    pub since: Option<String>,
+    /// Created at or before, RFC 3339 or a `YYYY-MM-DD` date (the whole day)
+    pub until: Option<String>,
+    pub seed: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub steps: Option<u32>,
+    pub scheduler: Option<String>,
+    /// `false` for generations only, `true` for upscales and variations only
+    pub derived: Option<bool>,
+    /// `true` for images imported from elsewhere only, `false` to leave them out
+    pub imported: Option<bool>,
+    /// Tags the entries must all have
+    pub tags: Vec<String>,
+    pub favorite: Option<bool>,
+    /// At least this many stars
+    pub min_rating: Option<u8>,
+    /// Only entries in the collection of this name
+    pub collection: Option<String>,
+    pub sort: HistorySort,
+    /// At most this many entries, `DEFAULT_LIMIT` when not set
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryPage {
+    /// Matches before `offset` and `limit` were applied
+    pub total: usize,
+    pub entries: Vec<HistoryEntry>,
+}
+
+#[derive(Default)]
+struct SearchIndex {
+    /// word -> positions in the history, ascending
+    words: BTreeMap<String, Vec<u32>>,
+    /// Creation time per position, unix seconds
+    created: Vec<i64>,
+}
+
+impl SearchIndex {
+    // Indexes the entries recorded since the last query. Should the history have
+    // shrunk (e.g. an unreadable file was set aside), positions have moved, so
+    // the index starts over.
+    fn update(&mut self, entries: &[HistoryEntry]) {
+        if self.created.len() > entries.len() {
+            *self = SearchIndex::default();
+        }
+        for (position, entry) in entries.iter().enumerate().skip(self.created.len()) {
+            self.add(position as u32, entry);
+        }
+    }
+
+    fn add(&mut self, position: u32, entry: &HistoryEntry) {
+        for word in words(&entry.request.prompt) {
+            let postings = self.words.entry(word).or_default();
+            if postings.last() != Some(&position) {
+                postings.push(position);
+            }
+        }
+        let created = OffsetDateTime::parse(&entry.created, &Rfc3339).map_or(0, |time| time.unix_timestamp());
+        self.created.push(created);
+    }
+
+    // Positions of the entries having `word`, or a word starting with it
+    fn lookup(&self, word: &str, prefix: bool) -> Vec<u32> {
+        if !prefix {
+            return self.words.get(word).cloned().unwrap_or_default();
+        }
+        let mut positions: Vec<u32> = self
+            .words
+            .range(word.to_string()..)
+            .take_while(|(w, _)| w.starts_with(word))
+            .flat_map(|(_, postings)| postings.iter().copied())
+            .collect();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+}
+
+// Lowercase runs of letters and digits
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+// Both ascending
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (mut i, mut j) = (0, 0);
+    let mut both = Vec::with_capacity(a.len().min(b.len()));
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                both.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    both
+}
+
+#[derive(Default)]
+struct TextQuery {
+    /// Each word and whether it may be unfinished
+    words: Vec<(String, bool)>,
+    phrases: Vec<String>,
+}
+
+fn parse_text(text: &str) -> TextQuery {
+    let mut query = TextQuery::default();
+    for (i, part) in text.split('"').enumerate() {
+        query.words.extend(words(part).map(|word| (word, false)));
+        if i % 2 == 1 && !part.trim().is_empty() {
+            query.phrases.push(part.trim().to_lowercase());
+        }
+    }
+    // Only a word typed last, outside quotes, can be unfinished
+    if text.ends_with(char::is_alphanumeric) && text.matches('"').count().is_multiple_of(2) {
+        if let Some((_, prefix)) = query.words.last_mut() {
+            *prefix = true;
+        }
+    }
+    query
+}
+
+fn parse_time(field: &str, value: &str, end_of_day: bool) -> Result<i64, DiffusionError> {
+    if let Ok(time) = OffsetDateTime::parse(value, &Rfc3339) {
+        return Ok(time.unix_timestamp());
+    }
+    let date = Date::parse(value, format_description!("[year]-[month]-[day]")).map_err(|_| {
+        DiffusionError::validation(field, format!("Expected an RFC 3339 time or a YYYY-MM-DD date, got '{}'", value))
+    })?;
+    let start = date.midnight().assume_utc().unix_timestamp();
+    Ok(if end_of_day { start + 86_399 } else { start })
+}
+
+/// The entries matching `query`, sorted and paged
+pub fn query(query: &HistoryQuery) -> Result<HistoryPage, DiffusionError> {
+    let since = query.since.as_deref().map(|v| parse_time("since", v, false)).transpose()?;
+    let until = query.until.as_deref().map(|v| parse_time("until", v, true)).transpose()?;
+    let text = parse_text(&query.text);
+    let tags: Vec<String> = query.tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+    let collection: Option<BTreeSet<String>> = match &query.collection {
+        Some(name) => Some(collections::get(name)?.entry_ids.into_iter().collect()),
+        None => None,
+    };
+
+    history::read(|entries| {
+        let mut index = INDEX.lock().map_err(|_| DiffusionError::lock("history index"))?;
+        let index = index.get_or_insert_with(SearchIndex::default);
+        index.update(entries);
+
+        // Candidates from the index, or every entry without text
+        let mut candidates: Option<Vec<u32>> = None;
+        for (word, prefix) in &text.words {
+            let found = index.lookup(word, *prefix);
+            candidates = Some(match candidates {
+                Some(so_far) => intersect(&so_far, &found),
+                None => found,
+            });
+        }
+        let candidates = candidates.unwrap_or_else(|| (0..entries.len() as u32).collect());
+
+        let model = query.model.as_ref().map(|m| m.to_lowercase());
+        let mut matches: Vec<u32> = candidates
+            .into_iter()
+            .filter(|&position| {
+                let entry = &entries[position as usize];
+                let request = &entry.request;
+                let created = index.created[position as usize];
+                let prompt = || request.prompt.to_lowercase();
+                model.as_ref().is_none_or(|m| entry.model.to_lowercase() == *m)
+                    && since.is_none_or(|since| created >= since)
+                    && until.is_none_or(|until| created <= until)
+                    && query.seed.is_none_or(|seed| request.seed == Some(seed))
+                    && query.width.is_none_or(|width| request.img_width == width)
+                    && query.height.is_none_or(|height| request.img_height == height)
+                    && query.steps.is_none_or(|steps| request.num_inference_steps == steps)
+                    && query.scheduler.as_ref().is_none_or(|s| request.scheduler.as_ref() == Some(s))
+                    && query.derived.is_none_or(|derived| entry.derivation.is_some() == derived)
+                    && query.imported.is_none_or(|imported| entry.imported.is_some() == imported)
+                    && tags.iter().all(|tag| entry.tags.contains(tag))
+                    && query.favorite.is_none_or(|favorite| entry.favorite == favorite)
+                    && query.min_rating.is_none_or(|stars| entry.rating.is_some_and(|rating| rating >= stars))
+                    && collection.as_ref().is_none_or(|ids| ids.contains(&entry.id))
+                    && (text.phrases.is_empty() || text.phrases.iter().all(|phrase| prompt().contains(phrase)))
+            })
+            .collect();
+
+        let created = |position: &u32| index.created[*position as usize];
+        match query.sort {
+            HistorySort::Newest => matches.sort_by_key(|p| std::cmp::Reverse((created(p), *p))),
+            HistorySort::Oldest => matches.sort_by_key(|p| (created(p), *p)),
+            HistorySort::Prompt => matches.sort_by_cached_key(|p| entries[*p as usize].request.prompt.to_lowercase()),
+            HistorySort::Model => matches.sort_by_cached_key(|p| {
+                (entries[*p as usize].model.to_lowercase(), std::cmp::Reverse((created(p), *p)))
+            }),
+        }
+
+        let total = matches.len();
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        let entries = matches
+            .into_iter()
+            .skip(query.offset)
+            .take(limit)
+            .map(|position| entries[position as usize].clone())
+            .collect();
+        Ok(HistoryPage { total, entries })
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageGenerationRequest;
+
+    fn entry(prompt: &str, created: &str) -> HistoryEntry {
+        serde_json::from_value(serde_json::json!({
+            "id": format!("h-{}", prompt),
+            "created": created,
+            "request": ImageGenerationRequest::new(prompt.to_string()),
+            "model": "model",
+            "images": [],
+        }))
+        .unwrap()
+    }
+
+    fn index(prompts: &[&str]) -> SearchIndex {
+        let entries: Vec<HistoryEntry> = prompts.iter().map(|p| entry(p, "2026-01-02T03:04:05Z")).collect();
+        let mut index = SearchIndex::default();
+        index.update(&entries);
+        index
+    }
+
+    #[test]
+    fn text_words_phrases_and_prefix() {
+        let query = parse_text(r#""Red Fox" in lightho"#);
+        assert_eq!(
+            query.words,
+            [
+                ("red".to_string(), false),
+                ("fox".to_string(), false),
+                ("in".to_string(), false),
+                ("lightho".to_string(), true)
+            ]
+        );
+        assert_eq!(query.phrases, ["red fox"]);
+
+        // Finished by a space or a closing quote, so matched whole
+        assert!(parse_text("lighthouse ").words.iter().all(|(_, prefix)| !prefix));
+        assert!(parse_text(r#"a "red fox""#).words.iter().all(|(_, prefix)| !prefix));
+        // Inside an unclosed quote the words are a phrase as typed so far, not a prefix
+        let query = parse_text(r#"a "red fo"#);
+        assert!(query.words.iter().all(|(_, prefix)| !prefix));
+        assert_eq!(query.phrases, ["red fo"]);
+
+        let query = parse_text("  ");
+        assert!(query.words.is_empty() && query.phrases.is_empty());
+    }
+
+    #[test]
+    fn intersect_sorted_positions() {
+        assert_eq!(intersect(&[1, 3, 5, 7, 9], &[2, 3, 4, 9, 10]), [3, 9]);
+        assert_eq!(intersect(&[1, 2], &[3, 4]), Vec::<u32>::new());
+        assert_eq!(intersect(&[], &[1]), Vec::<u32>::new());
+        assert_eq!(intersect(&[4, 8], &[4, 8]), [4, 8]);
+    }
+
+    #[test]
+    fn lookup_whole_words_and_prefixes() {
+        let index = index(&["light house", "lighthouse at dusk", "lights", "lighu lamp", "a light light"]);
+        assert_eq!(index.lookup("light", false), [0, 4]);
+        assert_eq!(index.lookup("light", true), [0, 1, 2, 4]);
+        assert_eq!(index.lookup("ligh", true), [0, 1, 2, 3, 4]);
+        assert_eq!(index.lookup("lighthousez", true), Vec::<u32>::new());
+        assert_eq!(index.lookup("ligh", false), Vec::<u32>::new());
+        // Postings hold each entry once, however often the word appears
+        assert_eq!(index.words["light"], [0, 4]);
+    }
+
+    #[test]
+    fn index_starts_over_when_history_shrinks() {
+        let mut index = index(&["red fox", "blue bird", "green frog"]);
+        assert_eq!(index.lookup("frog", false), [2]);
+
+        let entries = vec![entry("green frog", "2026-01-02T03:04:05Z")];
+        index.update(&entries);
+        assert_eq!(index.created.len(), 1);
+        assert_eq!(index.lookup("frog", false), [0]);
+        assert!(index.lookup("fox", false).is_empty());
+
+        let mut entries = entries;
+        entries.push(entry("red fox", "not a time"));
+        index.update(&entries);
+        assert_eq!(index.lookup("fox", false), [1]);
+        assert_eq!(index.created, [1_767_323_045, 0]);
+    }
+
+    #[test]
+    fn dates_cover_the_whole_day() {
+        let midnight = 1_767_312_000; // 2026-01-02T00:00:00Z
+        assert_eq!(parse_time("since", "2026-01-02", false).unwrap(), midnight);
+        assert_eq!(parse_time("until", "2026-01-02", true).unwrap(), midnight + 86_399);
+        // Exact times are used as given, even for `until`
+        assert_eq!(parse_time("until", "2026-01-02T00:00:00Z", true).unwrap(), midnight);
+        assert_eq!(parse_time("since", "2026-01-02T01:00:00+01:00", false).unwrap(), midnight);
+
+        let err = parse_time("until", "02/01/2026", true).unwrap_err();
+        assert!(matches!(err, DiffusionError::Validation { ref field, .. } if field.as_deref() == Some("until")), "{:?}", err);
+    }
+}
@@ -0,0 +1,371 @@
+// Importing existing images into the history
+//
+// `import` scans folders for PNG, JPEG and WebP files and records a history
+// entry for each one not seen before. By default it looks where images piled up
+// before this app: the images folder of the Electron DiffusionBee
+// (~/.diffusionbee/images) and the Desktop. The request of an entry is read
+// from the `parameters` text embedded in its image (see `image_metadata`), so
+// images from this app and from AUTOMATIC1111 come back with their prompt, seed
+// and settings; any other image gets an entry with just its size. Images stay
+// where they are.
+//
+// Images are told apart by the SHA-256 of their content, so one already in the
+// history under any path, or found twice in the scan, is recorded once.
+//
+// Every imported entry gets a small JPEG preview in ~/.diffusionbee/thumbnails.
+// Making them takes a while for thousands of images, so `make_thumbnails` runs
+// in the background after the entries are recorded. Both report their progress
+// through `on_progress`.
+
+use crate::history::{self, HistoryEntry, Imported};
+use crate::image_metadata::{self, GenerationMetadata};
+use crate::{model_registry, DiffusionError, ImageGenerationRequest};
+use image::codecs::jpeg::JpegEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub const THUMBNAIL_SIZE: u32 = 256;
+const THUMBNAIL_QUALITY: u8 = 80;
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "webp"];
+// Progress is reported at most this often, apart from each stage's last step
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Held while importing; two imports at once could both record the same image
+static IMPORT_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Folders to scan, `default_folders` when empty
+    pub folders: Vec<String>,
+    /// Scan subfolders too, skipping hidden ones
+    pub recursive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStage {
+    Scanning,
+    Importing,
+    Thumbnails,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+    pub stage: ImportStage,
+    pub done: usize,
+    pub total: usize,
+    /// The file being worked on
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    /// The folders scanned
+    pub folders: Vec<String>,
+    /// Image files found
+    pub found: usize,
+    /// The new history entries, one per imported image
+    pub entry_ids: Vec<String>,
+    /// Imported images that had generation parameters
+    pub with_metadata: usize,
+    /// Images already in the history, or found more than once
+    pub duplicates: usize,
+    /// Files that couldn't be read, with the reason
+    pub failed: Vec<String>,
+}
+
+/// The Electron DiffusionBee's images folder and the Desktop, those that exist
+pub fn default_folders() -> Vec<PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    [home.join(".diffusionbee").join("images"), home.join("Desktop")]
+        .into_iter()
+        .filter(|folder| folder.is_dir())
+        .collect()
+}
+
+/// ~/.diffusionbee/thumbnails
+pub fn thumbnails_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".diffusionbee").join("thumbnails"))
+}
+
+// Calls `on_progress` no more often than PROGRESS_INTERVAL, unless forced
+struct Progress<F: FnMut(ImportProgress)> {
+    on_progress: F,
+    last: Option<Instant>,
+}
+
+impl<F: FnMut(ImportProgress)> Progress<F> {
+    fn report(&mut self, stage: ImportStage, done: usize, total: usize, path: Option<&Path>) {
+        let due = self.last.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL);
+        if due || done == total {
+            self.last = Some(Instant::now());
+            (self.on_progress)(ImportProgress {
+                stage,
+                done,
+                total,
+                path: path.map(|p| p.to_string_lossy().to_string()),
+            });
+        }
+    }
+}
+
+/// Scans the folders of `options` and records an entry for each new image.
+/// Thumbnails are left to `make_thumbnails`.
+pub fn import(
+    options: &ImportOptions,
+    on_progress: impl FnMut(ImportProgress),
+) -> Result<ImportReport, DiffusionError> {
+    let _importing = IMPORT_LOCK
+        .try_lock()
+        .map_err(|_| DiffusionError::validation("folders", "An import is already running"))?;
+    let mut folders: Vec<PathBuf> = options
+        .folders
+        .iter()
+        .map(|folder| folder.trim())
+        .filter(|folder| !folder.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    if folders.is_empty() {
+        folders = default_folders();
+    }
+    if folders.is_empty() {
+        return Err(DiffusionError::validation("folders", "No folder to import from"));
+    }
+    if let Some(missing) = folders.iter().find(|folder| !folder.is_dir()) {
+        return Err(DiffusionError::validation("folders", format!("Not a folder: {}", missing.display())));
+    }
+    let thumbnails =
+        thumbnails_dir().ok_or_else(|| DiffusionError::FileSystem("Failed to get home directory".to_string()))?;
+
+    let mut progress = Progress { on_progress, last: None };
+    let mut report = ImportReport {
+        folders: folders.iter().map(|f| f.to_string_lossy().to_string()).collect(),
+        ..ImportReport::default()
+    };
+    let mut files = Vec::new();
+    for (i, folder) in folders.iter().enumerate() {
+        progress.report(ImportStage::Scanning, i, folders.len(), Some(folder));
+        scan(folder, options.recursive, &thumbnails, &mut files);
+    }
+    files.sort();
+    files.dedup();
+    report.found = files.len();
+    log::info!("Importing from {}: {} image file(s) found", report.folders.join(", "), files.len());
+
+    let (known_paths, mut known_hashes) = known_images(&files)?;
+    let mut entries = Vec::new();
+    for (i, path) in files.iter().enumerate() {
+        progress.report(ImportStage::Importing, i, files.len(), Some(path));
+        if known_paths.contains(&path.to_string_lossy().to_string()) {
+            report.duplicates += 1;
+            continue;
+        }
+        let content_hash = match model_registry::file_hash(path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                report.failed.push(e.message());
+                continue;
+            }
+        };
+        if !known_hashes.insert(content_hash.clone()) {
+            report.duplicates += 1;
+            continue;
+        }
+        match import_image(path, content_hash, &thumbnails) {
+            Ok(entry) => {
+                report.with_metadata += entry.imported.as_ref().is_some_and(|i| i.metadata) as usize;
+                report.entry_ids.push(entry.id.clone());
+                entries.push(entry);
+            }
+            Err(e) => report.failed.push(format!("{}: {}", path.display(), e.message())),
+        }
+    }
+    progress.report(ImportStage::Importing, files.len(), files.len(), None);
+
+    history::record_all(entries)?;
+    for failure in &report.failed {
+        log::warn!("Not imported: {}", failure);
+    }
+    log::info!(
+        "Imported {} image(s), {} with parameters; {} duplicate(s), {} failed",
+        report.entry_ids.len(),
+        report.with_metadata,
+        report.duplicates,
+        report.failed.len()
+    );
+    Ok(report)
+}
+
+// Adds the image files in `folder` to `files`
+fn scan(folder: &Path, recursive: bool, thumbnails: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Can't read {}: {}", folder.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if file_type.is_dir() {
+            if recursive && !hidden && path != thumbnails {
+                scan(&path, recursive, thumbnails, files);
+            }
+        } else if file_type.is_file() && !hidden && is_image(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.as_str()))
+}
+
+// The image paths in the history, and the content hashes of the ones that
+// could be the same as one of `files`. Only history images with the size of a
+// found file are hashed, since files of another size can't be duplicates.
+fn known_images(files: &[PathBuf]) -> Result<(HashSet<String>, HashSet<String>), DiffusionError> {
+    let sizes: BTreeSet<u64> = files.iter().filter_map(|f| fs::metadata(f).ok()).map(|m| m.len()).collect();
+    let (paths, mut hashes, unhashed) = history::read(|entries| {
+        let mut paths = HashSet::new();
+        let mut hashes = HashSet::new();
+        let mut unhashed = Vec::new();
+        for entry in entries {
+            paths.extend(entry.images.iter().cloned());
+            match &entry.imported {
+                Some(imported) => {
+                    hashes.insert(imported.content_hash.clone());
+                }
+                None => unhashed.extend(entry.images.iter().cloned()),
+            }
+        }
+        (paths, hashes, unhashed)
+    })?;
+    for image in unhashed {
+        let path = Path::new(&image);
+        if fs::metadata(path).is_ok_and(|m| sizes.contains(&m.len())) {
+            if let Ok(hash) = model_registry::file_hash(path) {
+                hashes.insert(hash);
+            }
+        }
+    }
+    Ok((paths, hashes))
+}
+
+fn import_image(path: &Path, content_hash: String, thumbnails: &Path) -> Result<HistoryEntry, DiffusionError> {
+    let (width, height) = image::image_dimensions(path).map_err(|e| DiffusionError::FileSystem(e.to_string()))?;
+    let parameters = image_metadata::read_parameters(path).unwrap_or_else(|e| {
+        log::debug!("No parameters read from {}: {}", path.display(), e);
+        None
+    });
+
+    let mut request = ImageGenerationRequest::new(String::new());
+    request.img_width = width;
+    request.img_height = height;
+    let mut model = String::new();
+    if let Some(parameters) = &parameters {
+        let metadata = GenerationMetadata::from_parameters(parameters);
+        request.prompt = metadata.prompt;
+        request.negative_prompt = metadata.negative_prompt;
+        if metadata.steps > 0 {
+            request.num_inference_steps = metadata.steps;
+        }
+        if metadata.guidance_scale > 0.0 {
+            request.guidance_scale = metadata.guidance_scale;
+        }
+        request.seed = metadata.seed;
+        if let Some((seed, strength)) = metadata.variation {
+            request.small_mod_seed = Some(seed);
+            request.small_mod_strength = Some(strength);
+        }
+        // The size generated at, which an upscaled image no longer has
+        if metadata.width > 0 && metadata.height > 0 {
+            request.img_width = metadata.width;
+            request.img_height = metadata.height;
+        }
+        model = metadata.model;
+    }
+
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    let created = modified
+        .map(OffsetDateTime::from)
+        .unwrap_or_else(OffsetDateTime::now_utc)
+        .format(&Rfc3339)
+        .unwrap_or_default();
+    let thumbnail = thumbnails.join(format!("{}.jpg", content_hash));
+    Ok(HistoryEntry::imported(
+        request,
+        model,
+        path.to_string_lossy().to_string(),
+        created,
+        Imported {
+            content_hash,
+            metadata: parameters.is_some(),
+            thumbnail: thumbnail.to_string_lossy().to_string(),
+        },
+    ))
+}
+
+/// Writes the missing thumbnails of the imported entries `ids`. Failures are
+/// logged and skipped; returns how many were written.
+pub fn make_thumbnails(ids: &[String], on_progress: impl FnMut(ImportProgress)) -> Result<usize, DiffusionError> {
+    let ids: HashSet<&String> = ids.iter().collect();
+    let pending: Vec<(String, String)> = history::read(|entries| {
+        entries
+            .iter()
+            .filter(|entry| ids.contains(&entry.id))
+            .filter_map(|entry| Some((entry.images.first()?.clone(), entry.imported.as_ref()?.thumbnail.clone())))
+            .filter(|(_, thumbnail)| !Path::new(thumbnail).exists())
+            .collect()
+    })?;
+    if let Some(folder) = thumbnails_dir() {
+        fs::create_dir_all(&folder)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", folder.display(), e)))?;
+    }
+
+    let mut progress = Progress { on_progress, last: None };
+    let mut written = 0;
+    for (i, (image, thumbnail)) in pending.iter().enumerate() {
+        progress.report(ImportStage::Thumbnails, i, pending.len(), Some(Path::new(image)));
+        match write_thumbnail(Path::new(image), Path::new(thumbnail)) {
+            Ok(()) => written += 1,
+            Err(e) => log::warn!("No thumbnail for {}: {}", image, e),
+        }
+    }
+    progress.report(ImportStage::Done, pending.len(), pending.len(), None);
+    log::info!("Wrote {} of {} thumbnail(s)", written, pending.len());
+    Ok(written)
+}
+
+pub(crate) fn write_thumbnail(image: &Path, thumbnail: &Path) -> Result<(), DiffusionError> {
+    let preview = image::open(image)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", image.display(), e)))?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8();
+    // Written under another name first so a half-written file is never taken for a thumbnail
+    let temp = thumbnail.with_extension("jpg.tmp");
+    let file = File::create(&temp)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", temp.display(), e)))?;
+    JpegEncoder::new_with_quality(BufWriter::new(file), THUMBNAIL_QUALITY)
+        .encode_image(&preview)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", temp.display(), e)))?;
+    fs::rename(&temp, thumbnail)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", thumbnail.display(), e)))
+}
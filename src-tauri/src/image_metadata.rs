@@ -0,0 +1,307 @@
+// Generation parameters embedded in saved images
+//
+// Images carry the settings they were made with in the AUTOMATIC1111 text form
+// most tools understand:
+//
+//   a lighthouse at dusk
+//   Negative prompt: blurry
+//   Steps: 20, CFG scale: 7.5, Seed: 1234, Size: 512x512, Model: sd-v1-5_fp16
+//
+// PNGs store it in a `parameters` text chunk, JPEG and WebP in the EXIF
+// UserComment. Every format also gets an XMP packet with the same text as its
+// description, for photo managers that only read XMP.
+//
+// `read_parameters` finds the text again in any of these files, and in images
+// from AUTOMATIC1111 and tools copying it, which use the same `parameters`
+// chunk; `GenerationMetadata::from_parameters` parses it.
+
+use crate::{variations, DiffusionError};
+use image::ImageDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+pub const PARAMETERS_KEYWORD: &str = "parameters";
+pub const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+const SOFTWARE: &str = "DiffusionBee";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationMetadata {
+    pub prompt: String,
+    pub negative_prompt: String,
+    pub steps: u32,
+    pub guidance_scale: f32,
+    pub seed: Option<u32>,
+    /// `small_mod_seed` and `small_mod_strength` of a variation
+    pub variation: Option<(u32, f32)>,
+    pub width: u32,
+    pub height: u32,
+    pub model: String,
+}
+
+impl GenerationMetadata {
+    /// The AUTOMATIC1111 `parameters` text
+    pub fn to_parameters(&self) -> String {
+        let mut text = self.prompt.clone();
+        if !self.negative_prompt.is_empty() {
+            text.push_str("\nNegative prompt: ");
+            text.push_str(&self.negative_prompt);
+        }
+
+        let mut fields = vec![
+            format!("Steps: {}", self.steps),
+            format!("CFG scale: {}", self.guidance_scale),
+        ];
+        if let Some(seed) = self.seed {
+            fields.push(format!("Seed: {}", seed));
+        }
+        if let Some((seed, strength)) = self.variation {
+            fields.push(format!("Variation seed: {}", seed));
+            fields.push(format!("Variation seed strength: {}", strength));
+        }
+        fields.push(format!("Size: {}x{}", self.width, self.height));
+        if !self.model.is_empty() {
+            fields.push(format!("Model: {}", self.model));
+        }
+        fields.push(format!("Version: {} {}", SOFTWARE, env!("CARGO_PKG_VERSION")));
+
+        text.push('\n');
+        text.push_str(&fields.join(", "));
+        text
+    }
+
+    /// Parses `parameters` text. Fields the text doesn't have are left at zero
+    /// or empty, and ones this app doesn't use (`Sampler`, `Model hash`) are
+    /// ignored.
+    pub fn from_parameters(text: &str) -> Self {
+        let mut lines: Vec<&str> = text.trim().lines().collect();
+        // The fields are on the last line, which always starts with the steps
+        let fields = match lines.last() {
+            Some(last) if last.trim_start().starts_with("Steps: ") => lines.pop().map(split_fields).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        let mut metadata = Self::default();
+        let negative_start = lines.iter().position(|line| line.starts_with("Negative prompt:"));
+        let (prompt, negative) = lines.split_at(negative_start.unwrap_or(lines.len()));
+        metadata.prompt = prompt.join("\n").trim().to_string();
+        metadata.negative_prompt = negative
+            .join("\n")
+            .trim_start_matches("Negative prompt:")
+            .trim()
+            .to_string();
+
+        let mut variation_seed = None;
+        let mut variation_strength = None;
+        for (key, value) in fields {
+            match key {
+                "Steps" => metadata.steps = value.parse().unwrap_or_default(),
+                "CFG scale" => metadata.guidance_scale = value.parse().unwrap_or_default(),
+                "Seed" => metadata.seed = value.parse().ok(),
+                "Variation seed" => variation_seed = value.parse().ok(),
+                "Variation seed strength" => variation_strength = value.parse().ok(),
+                "Size" => {
+                    if let Some((width, height)) = value.split_once('x') {
+                        metadata.width = width.trim().parse().unwrap_or_default();
+                        metadata.height = height.trim().parse().unwrap_or_default();
+                    }
+                }
+                "Model" => metadata.model = value.to_string(),
+                _ => {}
+            }
+        }
+        let variation_strength = variation_strength.unwrap_or(variations::DEFAULT_STRENGTH);
+        metadata.variation = variation_seed.map(|seed| (seed, variation_strength));
+        metadata
+    }
+}
+
+// `Key: value` pairs of the fields line. Values may be quoted, with commas
+// inside, as A1111 does for LoRA hashes.
+fn split_fields(line: &str) -> Vec<(&str, &str)> {
+    let mut fields = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ','))) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some((key, value)) = line[start..i].split_once(':') {
+                    fields.push((key.trim(), value.trim().trim_matches('"')));
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// The `parameters` text embedded in the image at `path`: a PNG text chunk, or
+/// the EXIF UserComment of other formats. None when the image has none.
+pub fn read_parameters(path: &Path) -> Result<Option<String>, DiffusionError> {
+    let failed = |e: String| DiffusionError::FileSystem(format!("Failed to read {}: {}", path.display(), e));
+    let file = File::open(path).map_err(|e| failed(e.to_string()))?;
+    let reader = image::ImageReader::new(BufReader::new(file))
+        .with_guessed_format()
+        .map_err(|e| failed(e.to_string()))?;
+
+    if reader.format() == Some(image::ImageFormat::Png) {
+        let file = File::open(path).map_err(|e| failed(e.to_string()))?;
+        let decoder = png::Decoder::new(BufReader::new(file));
+        let reader = decoder.read_info().map_err(|e| failed(e.to_string()))?;
+        let info = reader.info();
+        let text = info
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == PARAMETERS_KEYWORD)
+            .map(|chunk| chunk.text.clone())
+            .or_else(|| {
+                info.compressed_latin1_text
+                    .iter()
+                    .find(|chunk| chunk.keyword == PARAMETERS_KEYWORD)
+                    .and_then(|chunk| chunk.get_text().ok())
+            })
+            .or_else(|| {
+                info.utf8_text
+                    .iter()
+                    .find(|chunk| chunk.keyword == PARAMETERS_KEYWORD)
+                    .and_then(|chunk| chunk.get_text().ok())
+            });
+        return Ok(text.filter(|text| !text.trim().is_empty()));
+    }
+
+    let mut decoder = reader.into_decoder().map_err(|e| failed(e.to_string()))?;
+    let exif = decoder.exif_metadata().map_err(|e| failed(e.to_string()))?;
+    Ok(exif.and_then(|exif| user_comment(&exif)).filter(|text| !text.trim().is_empty()))
+}
+
+/// The UserComment in an EXIF block like the one `exif_block` writes, in
+/// either byte order
+pub fn user_comment(exif: &[u8]) -> Option<String> {
+    let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0\x2a" => true,
+        b"II\x2a\0" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let u32_at = |offset: usize| {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+    // (count, value or offset) of `tag` in the IFD at `ifd`
+    let find = |ifd: usize, tag: u16| {
+        let entries = u16_at(ifd)? as usize;
+        (0..entries).map(|i| ifd + 2 + i * 12).find_map(|entry| {
+            (u16_at(entry)? == tag).then_some((u32_at(entry + 4)? as usize, u32_at(entry + 8)? as usize))
+        })
+    };
+
+    let (_, exif_ifd) = find(u32_at(4)? as usize, 0x8769)?;
+    let (count, offset) = find(exif_ifd, 0x9286)?;
+    let comment = tiff.get(offset..offset.checked_add(count)?)?;
+    let (code, text) = comment.split_at_checked(8)?;
+    let text = match code {
+        b"UNICODE\0" => {
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    if big_endian { u16::from_be_bytes(pair) } else { u16::from_le_bytes(pair) }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+    Some(text.trim_end_matches('\0').to_string())
+}
+
+/// A big-endian TIFF block with Software and, in the Exif sub-IFD, `text` as
+/// the UserComment. This is the EXIF payload without JPEG's `Exif\0\0` prefix.
+pub fn exif_block(text: &str) -> Vec<u8> {
+    // UserComment starts with an 8 byte character code
+    let mut comment = Vec::new();
+    if text.is_ascii() {
+        comment.extend_from_slice(b"ASCII\0\0\0");
+        comment.extend_from_slice(text.as_bytes());
+    } else {
+        comment.extend_from_slice(b"UNICODE\0");
+        comment.extend(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+    }
+    let mut software = SOFTWARE.as_bytes().to_vec();
+    software.push(0);
+    let software_count = software.len() as u32;
+    // TIFF values start on word boundaries
+    if software.len() % 2 == 1 {
+        software.push(0);
+    }
+
+    // Layout: header (8), IFD0 with 2 entries (2 + 24 + 4), Exif IFD with 1
+    // entry (2 + 12 + 4), then the software string and the comment
+    let ifd0_offset = 8u32;
+    let exif_ifd_offset = ifd0_offset + 2 + 2 * 12 + 4;
+    let software_offset = exif_ifd_offset + 2 + 12 + 4;
+    let comment_offset = software_offset + software.len() as u32;
+
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(b"MM\0\x2a");
+    tiff.extend_from_slice(&ifd0_offset.to_be_bytes());
+
+    tiff.extend_from_slice(&2u16.to_be_bytes());
+    ifd_entry(&mut tiff, 0x0131, 2, software_count, software_offset); // Software, ASCII
+    ifd_entry(&mut tiff, 0x8769, 4, 1, exif_ifd_offset); // Exif IFD pointer, LONG
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    ifd_entry(&mut tiff, 0x9286, 7, comment.len() as u32, comment_offset); // UserComment, UNDEFINED
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+
+    tiff.extend_from_slice(&software);
+    tiff.extend_from_slice(&comment);
+    tiff
+}
+
+fn ifd_entry(tiff: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32) {
+    tiff.extend_from_slice(&tag.to_be_bytes());
+    tiff.extend_from_slice(&kind.to_be_bytes());
+    tiff.extend_from_slice(&count.to_be_bytes());
+    tiff.extend_from_slice(&value.to_be_bytes());
+}
+
+/// An XMP packet with `text` as dc:description
+pub fn xmp_packet(text: &str) -> String {
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" ",
+            "xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">",
+            "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+            "<xmp:CreatorTool>{}</xmp:CreatorTool>",
+            "</rdf:Description></rdf:RDF></x:xmpmeta>",
+            "<?xpacket end=\"w\"?>"
+        ),
+        escape_xml(text),
+        SOFTWARE
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
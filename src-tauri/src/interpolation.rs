@@ -0,0 +1,331 @@
+// Prompt/seed interpolation videos through the backend's `frame_interpolate` applet
+//
+// The applet renders the frames; Rust tracks its progress, then assembles the
+// full resolution frames into a GIF or APNG itself, or an MP4 when ffmpeg is
+// available on this machine.
+
+use crate::applets;
+use crate::parameter_schema::ParameterSchema;
+use crate::{DiffusionError, ImageGenerationRequest};
+use anyhow::{Context, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const APPLET_NAME: &str = "frame_interpolate";
+const OUTPUTS_KEY: &str = "registered_ext_applets.frame_interpolate.outputs";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    Mp4,
+}
+
+impl AnimationFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+fn default_num_frames() -> u32 {
+    40
+}
+
+fn default_fps() -> u32 {
+    12
+}
+
+fn default_format() -> AnimationFormat {
+    AnimationFormat::Gif
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterpolationRequest {
+    pub prompt1: String,
+    pub prompt2: String,
+    pub seed1: u32,
+    pub seed2: u32,
+    #[serde(default = "default_num_frames")]
+    pub num_frames: u32,
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+    #[serde(default = "default_format")]
+    pub format: AnimationFormat,
+    #[serde(default)]
+    pub negative_prompt: String,
+    pub img_width: u32,
+    pub img_height: u32,
+    pub num_inference_steps: u32,
+    pub guidance_scale: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterpolationResponse {
+    pub output_path: String,
+    pub format: AnimationFormat,
+    pub num_frames: u32,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Progress reported by the applet while it renders
+#[derive(Debug, Clone, Copy)]
+pub struct FrameProgress {
+    pub rendered: u32,
+    pub total: u32,
+}
+
+impl InterpolationRequest {
+    pub fn validate(&self, schema: &ParameterSchema) -> Result<(), DiffusionError> {
+        // Same rules the applet enforces, checked before we spin up the backend
+        if self.num_frames < 3 || self.num_frames > 500 {
+            return Err(DiffusionError::validation("num_frames", "Number of frames must be between 3 and 500"));
+        }
+        if self.seed1 < 1 {
+            return Err(DiffusionError::validation("seed1", "Seeds must be at least 1"));
+        }
+        if self.seed2 < 1 {
+            return Err(DiffusionError::validation("seed2", "Seeds must be at least 1"));
+        }
+        if self.prompt1.trim().len() < 5 {
+            return Err(DiffusionError::validation("prompt1", "Start and end prompts must be at least 5 characters"));
+        }
+        if self.prompt2.trim().len() < 5 {
+            return Err(DiffusionError::validation("prompt2", "Start and end prompts must be at least 5 characters"));
+        }
+        if self.fps == 0 || self.fps > 60 {
+            return Err(DiffusionError::validation("fps", "Frame rate must be between 1 and 60"));
+        }
+
+        // Image parameters follow the normal generation rules
+        let mut image_request = ImageGenerationRequest::new(self.prompt1.clone());
+        image_request.img_width = self.img_width;
+        image_request.img_height = self.img_height;
+        image_request.num_inference_steps = self.num_inference_steps;
+        image_request.guidance_scale = self.guidance_scale;
+        image_request.validate(schema)
+    }
+
+    fn applet_params(&self, model_path: &Path, frames_dir: &Path) -> Value {
+        json!({
+            "num_frames": self.num_frames,
+            "seed1": self.seed1,
+            "seed2": self.seed2,
+            "prompt1": self.prompt1,
+            "prompt2": self.prompt2,
+            "negative_prompt": self.negative_prompt,
+            "img_width": self.img_width,
+            "img_height": self.img_height,
+            "num_steps": self.num_inference_steps,
+            "guidance_scale": self.guidance_scale,
+            "tdict_path": model_path.to_string_lossy(),
+            "frames_dir": frames_dir.to_string_lossy(),
+        })
+    }
+}
+
+/// Renders the interpolation and writes the animation into `output_dir`.
+pub async fn run_interpolation(
+    request: &InterpolationRequest,
+    python: &Path,
+    backend_script: &Path,
+    model_path: &Path,
+    output_dir: &Path,
+    mut on_progress: impl FnMut(FrameProgress),
+    is_cancelled: impl Fn() -> bool,
+) -> Result<InterpolationResponse, DiffusionError> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let frames_dir = std::env::temp_dir().join(format!("diffusionbee_interpolation_{}", timestamp));
+    fs::create_dir_all(&frames_dir)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to create frames directory: {}", e)))?;
+
+    let mut stopped = false;
+    let params = request.applet_params(model_path, &frames_dir);
+    let result = applets::run_applet(
+        python,
+        backend_script,
+        APPLET_NAME,
+        &params,
+        |key, value| {
+            if key != OUTPUTS_KEY {
+                return;
+            }
+            for text in output_texts(value) {
+                if let Some(progress) = parse_frame_progress(text) {
+                    on_progress(progress);
+                } else if text.trim() == "Stopped" {
+                    stopped = true;
+                }
+            }
+        },
+        is_cancelled,
+    )
+    .await;
+
+    let response = result.and_then(|_| {
+        if stopped {
+            return Err(DiffusionError::Cancelled);
+        }
+        let frames = collect_frames(&frames_dir)?;
+        let output_path = output_dir.join(format!("interpolation_{}.{}", timestamp, request.format.extension()));
+        assemble(&frames, request, output_path)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to assemble animation: {:#}", e)))
+    });
+
+    let _ = fs::remove_dir_all(&frames_dir);
+    response
+}
+
+fn output_texts(outputs: &Value) -> impl Iterator<Item = &str> {
+    outputs
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|el| el["component"] == "OutputText")
+        .filter_map(|el| el["text"].as_str())
+}
+
+// "Rendering... \n\n Rendered 3 out of 40 images."
+fn parse_frame_progress(text: &str) -> Option<FrameProgress> {
+    let rest = &text[text.find("Rendered ")? + "Rendered ".len()..];
+    let mut words = rest.split_whitespace();
+    let rendered = words.next()?.parse().ok()?;
+    if (words.next()?, words.next()?) != ("out", "of") {
+        return None;
+    }
+    let total = words.next()?.parse().ok()?;
+    Some(FrameProgress { rendered, total })
+}
+
+fn collect_frames(frames_dir: &Path) -> Result<Vec<PathBuf>, DiffusionError> {
+    let mut frames: Vec<PathBuf> = fs::read_dir(frames_dir)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to read rendered frames: {}", e)))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("png"))
+        .collect();
+    frames.sort();
+    if frames.is_empty() {
+        return Err(DiffusionError::PythonBackend {
+            message: "The backend did not render any frames".to_string(),
+            traceback: None,
+        });
+    }
+    Ok(frames)
+}
+
+fn assemble(
+    frames: &[PathBuf],
+    request: &InterpolationRequest,
+    output_path: PathBuf,
+) -> Result<InterpolationResponse> {
+    let mut warnings = Vec::new();
+    let mut format = request.format;
+
+    if format == AnimationFormat::Mp4 && !ffmpeg_available() {
+        warnings.push("ffmpeg was not found, so a GIF was written instead of an MP4".to_string());
+        format = AnimationFormat::Gif;
+    }
+    let output_path = output_path.with_extension(format.extension());
+
+    match format {
+        AnimationFormat::Gif => write_gif(frames, request.fps, &output_path)?,
+        AnimationFormat::Apng => write_apng(frames, request.fps, &output_path)?,
+        AnimationFormat::Mp4 => write_mp4(frames, request.fps, &output_path)?,
+    }
+
+    Ok(InterpolationResponse {
+        output_path: output_path.to_string_lossy().to_string(),
+        format,
+        num_frames: frames.len() as u32,
+        warnings,
+    })
+}
+
+pub fn write_gif(frames: &[PathBuf], fps: u32, output_path: &Path) -> Result<()> {
+    let file = fs::File::create(output_path).context("Failed to create GIF file")?;
+    let mut encoder = GifEncoder::new(BufWriter::new(file));
+    encoder.set_repeat(Repeat::Infinite)?;
+    for path in frames {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to read frame {}", path.display()))?
+            .to_rgba8();
+        let delay = Delay::from_numer_denom_ms(1000, fps);
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+    }
+    Ok(())
+}
+
+pub fn write_apng(frames: &[PathBuf], fps: u32, output_path: &Path) -> Result<()> {
+    let first = image::open(&frames[0]).context("Failed to read first frame")?.to_rgba8();
+    let (width, height) = first.dimensions();
+
+    let file = fs::File::create(output_path).context("Failed to create APNG file")?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(1, fps.min(u16::MAX as u32) as u16)?;
+
+    let mut writer = encoder.write_header()?;
+    for path in frames {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to read frame {}", path.display()))?
+            .to_rgba8();
+        anyhow::ensure!(
+            image.dimensions() == (width, height),
+            "Frame {} has a different size than the first frame",
+            path.display()
+        );
+        writer.write_image_data(image.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+fn write_mp4(frames: &[PathBuf], fps: u32, output_path: &Path) -> Result<()> {
+    // Frames are named frame_0000.png, frame_0001.png, ... by the applet
+    let pattern = frames[0]
+        .parent()
+        .context("Frame has no parent directory")?
+        .join("frame_%04d.png");
+    let output = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-framerate"])
+        .arg(fps.to_string())
+        .arg("-i")
+        .arg(&pattern)
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        .arg(output_path)
+        .output()
+        .context("Failed to run ffmpeg")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "ffmpeg failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Lists kept in a JSON file in ~/.diffusionbee
+//
+// The history, presets, prompt history and collections are each a list read
+// from one file on first use and kept in memory. Every change rewrites the
+// whole file through a temporary file, so a crash can't leave it half written.
+// A file that can't be parsed is renamed to `<name>.json.bak` and the list
+// starts empty, rather than overwriting it on the next save.
+
+use crate::DiffusionError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub struct Store<T> {
+    file_name: &'static str,
+    /// What the list holds, for messages
+    what: &'static str,
+    /// Indent the file, for lists people may edit by hand
+    pretty: bool,
+    items: Mutex<Option<Vec<T>>>,
+}
+
+impl<T: Serialize + DeserializeOwned> Store<T> {
+    pub const fn new(file_name: &'static str, what: &'static str) -> Self {
+        Self {
+            file_name,
+            what,
+            pretty: false,
+            items: Mutex::new(None),
+        }
+    }
+
+    /// A store whose file is indented
+    pub const fn pretty(file_name: &'static str, what: &'static str) -> Self {
+        Self {
+            file_name,
+            what,
+            pretty: true,
+            items: Mutex::new(None),
+        }
+    }
+
+    /// ~/.diffusionbee/<file name>
+    pub fn path(&self) -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".diffusionbee").join(self.file_name))
+    }
+
+    /// Runs `f` on the list, loading it from disk on first use
+    pub fn with<R>(&self, f: impl FnOnce(&mut Vec<T>) -> R) -> Result<R, DiffusionError> {
+        let mut items = self.items.lock().map_err(|_| DiffusionError::lock(self.what))?;
+        let items = items.get_or_insert_with(|| self.load());
+        Ok(f(items))
+    }
+
+    fn load(&self) -> Vec<T> {
+        let Some(path) = self.path() else {
+            return Vec::new();
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                let backup = path.with_extension("json.bak");
+                log::warn!(
+                    "Ignoring unreadable {} at {} (kept as {}): {}",
+                    self.what,
+                    path.display(),
+                    backup.display(),
+                    e
+                );
+                let _ = fs::rename(&path, &backup);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Writes `items` to the file; call it from `with` after changing them
+    pub fn save(&self, items: &[T]) -> Result<(), DiffusionError> {
+        let path = self
+            .path()
+            .ok_or_else(|| DiffusionError::FileSystem("Failed to get home directory".to_string()))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", parent.display(), e)))?;
+        }
+        let content = if self.pretty {
+            serde_json::to_string_pretty(items)?
+        } else {
+            serde_json::to_string(items)?
+        };
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, content)
+            .and_then(|_| fs::rename(&temp, &path))
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", path.display(), e)))
+    }
+}
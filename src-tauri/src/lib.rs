@@ -1,29 +1,71 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::fs;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::process::{Command, Stdio};
-use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use anyhow::{Result, Context};
 
-// Error handling
-#[derive(Debug, thiserror::Error)]
-pub enum DiffusionError {
-    #[error("Python backend error: {0}")]
-    PythonBackend(String),
-    #[error("File system error: {0}")]
-    FileSystem(String),
-    #[error("JSON serialization error: {0}")]
-    JsonError(String),
-    #[error("Process error: {0}")]
-    ProcessError(String),
-    #[error("Validation error: {0}")]
-    Validation(String),
-}
+pub mod api_server;
+pub mod applets;
+pub mod backend_locator;
+pub mod backend_connection;
+pub mod clip_tokenizer;
+pub mod collections;
+pub mod diagnostics;
+pub mod error;
+pub mod history;
+pub mod history_search;
+pub mod image_import;
+pub mod image_metadata;
+pub mod interpolation;
+pub mod json_store;
+pub mod logging;
+pub mod mock_backend;
+pub mod model_registry;
+pub mod output_files;
+pub mod output_format;
+pub mod parameter_schema;
+pub mod post_processing;
+pub mod presets;
+pub mod prompt_history;
+pub mod prompt_template;
+pub mod prompt_weighting;
+pub mod python_env;
+pub mod regeneration;
+pub mod validation_profile;
+pub mod variations;
+
+use clip_tokenizer::{ClipTokenizer, PromptTokenCount};
+use prompt_weighting::{parse_weighted_prompt, WeightedPrompt};
+use interpolation::{InterpolationRequest, InterpolationResponse};
+use applets::{AppletInfo, FormField};
+use backend_connection::{BackendConnection, BackendLine};
+use collections::{Collection, CollectionExport, ExportFormat, TagCount};
+use diagnostics::{DiagnosticsBundle, DiagnosticsOptions};
+use logging::{BackendStream, LogEntry, LogFilter};
+use mock_backend::MockSchedule;
+use image_import::{ImportOptions, ImportProgress, ImportReport};
+use image_metadata::GenerationMetadata;
+use output_files::{OutputContext, OutputSubfolders};
+use output_format::{EncodeOptions, OutputFormat};
+use history::{Derivation, FileHashes, HistoryEntry};
+use history_search::{HistoryPage, HistoryQuery};
+use parameter_schema::{Parameter, ParameterSchema};
+use post_processing::{PostProcessStage, UpscaleOptions};
+use presets::{ImportSummary, Preset};
+use prompt_history::{PromptRecord, PromptSuggestion};
+use python_env::PythonProbe;
+use validation_profile::{ValidationOverrides, ValidationProfile};
+use variations::VariationOptions;
+use tauri::{Emitter, Manager};
+
+pub use error::DiffusionError;
+
+// Schedulers the backend's get_scheduler knows
+pub const SCHEDULERS: [&str; 7] = ["ddim", "ddim_v", "lmsd", "pndm", "k_euler_ancestral", "k_euler", "karras"];
 
 // Data structures for image generation
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageGenerationRequest {
     pub prompt: String,
     pub img_width: u32,
@@ -31,32 +73,97 @@ pub struct ImageGenerationRequest {
     pub num_imgs: u32,
     pub num_inference_steps: u32,
     pub guidance_scale: f32,
+    #[serde(default)]
+    pub seed: Option<u32>,
+    #[serde(default)]
+    pub negative_prompt: String,
+    /// Starting image for img2img
+    #[serde(default)]
+    pub input_image: Option<String>,
+    /// How much of the input image is kept, 0.0 to 1.0
+    #[serde(default)]
+    pub input_image_strength: Option<f32>,
+    /// Seed of the noise mixed into `seed`'s for a variation
+    #[serde(default)]
+    pub small_mod_seed: Option<u32>,
+    /// Share of the noise taken from `small_mod_seed`, above 0.0 and at most 1.0
+    #[serde(default)]
+    pub small_mod_strength: Option<f32>,
+    /// One of `SCHEDULERS`; the backend picks one for the model when not set
+    #[serde(default)]
+    pub scheduler: Option<String>,
+    #[serde(default)]
+    pub loras: Vec<LoraWeight>,
+}
+
+/// A LoRA .tdict applied on top of the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoraWeight {
+    pub path: String,
+    pub weight: f32,
 }
 
 impl ImageGenerationRequest {
-    pub fn validate(&self) -> Result<(), String> {
+    /// Checks the request against the schema of the active model's family
+    pub fn validate(&self, schema: &ParameterSchema) -> Result<(), DiffusionError> {
         // Validate prompt
         if self.prompt.trim().is_empty() {
-            return Err("Prompt cannot be empty".to_string());
+            return Err(DiffusionError::validation("prompt", "Prompt cannot be empty"));
+        }
+        let weighted = self.weighted_prompt()?;
+        if weighted.plain_prompt().is_empty() {
+            return Err(DiffusionError::validation("prompt", "Prompt cannot be empty"));
         }
 
         // Validate dimensions
-        if self.img_width < 256 || self.img_width > 1024 {
-            return Err("Width must be between 256 and 1024".to_string());
-        }
-        if self.img_height < 256 || self.img_height > 1024 {
-            return Err("Height must be between 256 and 1024".to_string());
-        }
+        schema.check(Parameter::Width, self.img_width as f64)?;
+        schema.check(Parameter::Height, self.img_height as f64)?;
 
         // Validate other parameters
-        if self.num_imgs == 0 || self.num_imgs > 10 {
-            return Err("Number of images must be between 1 and 10".to_string());
+        schema.check(Parameter::Images, self.num_imgs as f64)?;
+        schema.check(Parameter::Steps, self.num_inference_steps as f64)?;
+        schema.check(Parameter::Guidance, self.guidance_scale as f64)?;
+
+        // Validate img2img input
+        if let Some(input_image) = &self.input_image {
+            if !PathBuf::from(input_image).exists() {
+                return Err(DiffusionError::validation(
+                    "input_image",
+                    format!("Input image not found at: {}", input_image),
+                ));
+            }
         }
-        if self.num_inference_steps < 10 || self.num_inference_steps > 50 {
-            return Err("Inference steps must be between 10 and 50".to_string());
+        if let Some(strength) = self.input_image_strength {
+            if !(0.0..=1.0).contains(&strength) {
+                return Err(DiffusionError::validation(
+                    "input_image_strength",
+                    "Input image strength must be between 0.0 and 1.0",
+                ));
+            }
         }
-        if self.guidance_scale < 1.0 || self.guidance_scale > 20.0 {
-            return Err("Guidance scale must be between 1.0 and 20.0".to_string());
+        if let Some(strength) = self.small_mod_strength {
+            if !(strength > 0.0 && strength <= 1.0) {
+                return Err(DiffusionError::validation(
+                    "small_mod_strength",
+                    "Variation strength must be above 0.0 and at most 1.0",
+                ));
+            }
+        }
+        if let Some(scheduler) = &self.scheduler {
+            if !SCHEDULERS.contains(&scheduler.as_str()) {
+                return Err(DiffusionError::validation(
+                    "scheduler",
+                    format!("Unknown scheduler '{}', expected one of: {}", scheduler, SCHEDULERS.join(", ")),
+                ));
+            }
+        }
+        for lora in &self.loras {
+            if !PathBuf::from(&lora.path).is_file() {
+                return Err(DiffusionError::validation("loras", format!("LoRA not found at: {}", lora.path)));
+            }
+            if !lora.weight.is_finite() {
+                return Err(DiffusionError::validation("loras", format!("Invalid weight for LoRA {}", lora.path)));
+            }
         }
 
         Ok(())
@@ -70,14 +177,67 @@ impl ImageGenerationRequest {
             num_imgs: 1,
             num_inference_steps: 20,
             guidance_scale: 7.5,
+            seed: None,
+            negative_prompt: String::new(),
+            input_image: None,
+            input_image_strength: None,
+            small_mod_seed: None,
+            small_mod_strength: None,
+            scheduler: None,
+            loras: Vec::new(),
         }
     }
+
+    /// Parses `(word:1.3)`, `[word]` and `AND` syntax in the prompt
+    pub fn weighted_prompt(&self) -> Result<WeightedPrompt, DiffusionError> {
+        parse_weighted_prompt(&self.prompt)
+            .map_err(|e| DiffusionError::validation("prompt", format!("Invalid prompt syntax: {}", e)))
+    }
+
+    /// Expands wildcards and choice groups in the prompt so the request holds
+    /// exactly what will be generated. A seed is chosen here when none was given
+    /// so both the expansion and the image can be reproduced from history.
+    pub fn expand_prompt(&mut self, wildcards_dir: &Path) -> Result<(), DiffusionError> {
+        let seed = *self.seed.get_or_insert_with(random_seed);
+        self.prompt = prompt_template::expand_prompt(&self.prompt, wildcards_dir, seed as u64)
+            .map_err(|e| DiffusionError::validation("prompt", e))?;
+        Ok(())
+    }
+}
+
+// Same range the Python backend uses when it picks a seed itself
+fn random_seed() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    (now % 1_002_486) as u32 + 1
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ImageGenerationResponse {
     pub generated_img_path: String,
     pub aux_output_image_path: Option<String>,
+    /// Every image the job produced, starting with `generated_img_path`
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// The fully expanded prompt the image was generated from
+    #[serde(default)]
+    pub prompt: String,
+    #[serde(default)]
+    pub seed: Option<u32>,
+    /// Non-fatal problems with the request, e.g. a prompt longer than CLIP's token limit
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Tags this run's entries in the log, see `get_logs`
+    #[serde(default)]
+    pub job_id: Option<String>,
+    /// The job's entry in the generation history
+    #[serde(default)]
+    pub history_id: Option<String>,
+    /// Images the post-processing stages made from `images`
+    #[serde(default)]
+    pub derived_images: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,10 +247,14 @@ pub struct GenerationProgress {
     pub status: String,
     pub is_complete: bool,
     pub is_cancelled: bool,
+    /// Job the progress belongs to, for looking up its log entries
+    #[serde(default)]
+    pub job_id: Option<String>,
 }
 
 // Settings structure for persistence
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppSettings {
     pub default_width: u32,
     pub default_height: u32,
@@ -98,6 +262,36 @@ pub struct AppSettings {
     pub default_guidance_scale: f32,
     pub output_directory: String,
     pub model_path: String,
+    pub wildcards_directory: String,
+    /// Local AUTOMATIC1111-compatible HTTP API, off unless the user opts in
+    pub api_server_enabled: bool,
+    pub api_server_bind_address: String,
+    pub api_server_token: String,
+    /// Generate with the in-process mock backend instead of Python (development and tests)
+    pub mock_backend: bool,
+    /// Interpreter for the Python backend, detected when empty
+    pub python_path: String,
+    /// diffusionbee_backend.py (or its directory) to run instead of the bundled one
+    pub backend_script_path: String,
+    /// Name for finished images, see `output_files` for the tokens
+    pub filename_template: String,
+    pub output_subfolders: OutputSubfolders,
+    /// Copy images out of the backend's folder instead of moving them
+    pub keep_backend_files: bool,
+    pub output_format: OutputFormat,
+    /// JPEG and lossy WebP quality, 1-100
+    pub output_quality: u8,
+    pub webp_lossless: bool,
+    /// Write the prompt and settings into saved images
+    pub embed_metadata: bool,
+    /// Changes to the size, steps and guidance limits of the model's family
+    pub validation_overrides: ValidationOverrides,
+    /// Run on every finished image, see `post_processing`
+    pub post_processing: Vec<PostProcessStage>,
+    /// Backend applet used for model-based upscaling
+    pub upscaler_applet: String,
+    /// Upscaler model handed to that applet
+    pub upscaler_model_path: String,
 }
 
 impl Default for AppSettings {
@@ -109,10 +303,99 @@ impl Default for AppSettings {
             default_guidance_scale: 7.5,
             output_directory: get_default_output_directory(),
             model_path: get_default_model_path(),
+            wildcards_directory: get_default_wildcards_directory(),
+            api_server_enabled: false,
+            api_server_bind_address: "127.0.0.1:7860".to_string(),
+            api_server_token: String::new(),
+            mock_backend: false,
+            python_path: String::new(),
+            backend_script_path: String::new(),
+            filename_template: output_files::DEFAULT_FILENAME_TEMPLATE.to_string(),
+            output_subfolders: OutputSubfolders::None,
+            keep_backend_files: false,
+            output_format: OutputFormat::Png,
+            output_quality: output_format::DEFAULT_QUALITY,
+            webp_lossless: false,
+            embed_metadata: true,
+            validation_overrides: ValidationOverrides::default(),
+            post_processing: Vec::new(),
+            upscaler_applet: post_processing::DEFAULT_UPSCALER_APPLET.to_string(),
+            upscaler_model_path: String::new(),
+        }
+    }
+}
+
+impl AppSettings {
+    /// How finished images are encoded
+    pub fn encode_options(&self) -> EncodeOptions {
+        EncodeOptions {
+            format: self.output_format,
+            quality: self.output_quality,
+            lossless: self.webp_lossless,
+        }
+    }
+
+    /// Request limits for the active model, with the overrides applied
+    pub fn validation_profile(&self) -> ValidationProfile {
+        ValidationProfile::resolve(&self.model_path, &self.validation_overrides)
+    }
+
+    /// The rules requests and setting defaults are checked against
+    pub fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::from_profile(&self.validation_profile())
+    }
+
+    /// The active model's file name without extension
+    pub fn model_name(&self) -> String {
+        Path::new(&self.model_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "mock".to_string())
+    }
+}
+
+fn get_settings_path() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().context("Failed to get home directory")?;
+    Ok(home_dir.join(".diffusionbee").join("app_settings.json"))
+}
+
+/// Loads the saved settings, falling back to defaults when there are none yet
+pub fn load_settings() -> AppSettings {
+    let path = match get_settings_path() {
+        Ok(path) => path,
+        Err(e) => {
+            log::warn!("Using default settings: {}", e);
+            return AppSettings::default();
         }
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable settings at {}: {}", path.display(), e);
+            AppSettings::default()
+        }),
+        Err(_) => AppSettings::default(),
     }
 }
 
+fn write_settings(settings: &AppSettings) -> Result<()> {
+    let path = get_settings_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create settings directory")?;
+    }
+    let json = serde_json::to_string_pretty(settings).context("Failed to serialize settings")?;
+    fs::write(&path, json).context("Failed to write settings file")?;
+    Ok(())
+}
+
+fn get_default_wildcards_directory() -> String {
+    dirs::home_dir()
+        .map(|home_dir| home_dir.join(".diffusionbee").join("wildcards"))
+        .unwrap_or_else(|| PathBuf::from("wildcards"))
+        .to_string_lossy()
+        .to_string()
+}
+
 fn get_default_output_directory() -> String {
     // Try to get Desktop directory, fallback to current directory
     if let Some(home_dir) = dirs::home_dir() {
@@ -158,425 +441,497 @@ fn get_default_model_path() -> String {
     String::new()
 }
 
-fn create_placeholder_image(path: &PathBuf, width: u32, height: u32) -> Result<()> {
-    // Create a simple PNG image as a placeholder
-    // This is a basic implementation - in production you'd use a proper image library
-    
-    // PNG header
-    let mut png_data = vec![
-        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // PNG signature
-    ];
-    
-    // IHDR chunk (image header)
-    let ihdr_data = create_ihdr_chunk(width, height);
-    png_data.extend(ihdr_data);
-    
-    // IDAT chunk (image data) - simple colored rectangle
-    let idat_data = create_idat_chunk(width, height);
-    png_data.extend(idat_data);
-    
-    // IEND chunk (end of file)
-    let iend_data = create_iend_chunk();
-    png_data.extend(iend_data);
-    
-    // Write to file
-    fs::write(path, png_data)
-        .context("Failed to write PNG file")?;
-    
-    Ok(())
-}
-
-fn create_ihdr_chunk(width: u32, height: u32) -> Vec<u8> {
-    let mut data = vec![];
-    
-    // Length (13 bytes)
-    data.extend_from_slice(&13u32.to_be_bytes());
-    
-    // Type "IHDR"
-    data.extend_from_slice(b"IHDR");
-    
-    // Width, height, bit depth, color type, compression, filter, interlace
-    data.extend_from_slice(&width.to_be_bytes());
-    data.extend_from_slice(&height.to_be_bytes());
-    data.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, no compression, no filter, no interlace
-    
-    // CRC (placeholder - in production you'd calculate this properly)
-    data.extend_from_slice(&[0, 0, 0, 0]);
-    
-    data
-}
-
-fn create_idat_chunk(width: u32, height: u32) -> Vec<u8> {
-    let mut data = vec![];
-    
-    // Create simple RGB data (purple gradient)
-    let mut image_data = vec![];
-    for y in 0..height {
-        // Filter byte (0 = no filter)
-        image_data.push(0);
-        
-        for x in 0..width {
-            let r = ((x as f32 / width as f32) * 255.0) as u8;
-            let g = ((y as f32 / height as f32) * 255.0) as u8;
-            let b = 128;
-            image_data.extend_from_slice(&[r, g, b]);
-        }
-    }
-    
-    // Compress data (simple implementation - in production use proper compression)
-    let compressed_data = image_data; // For now, no compression
-    
-    // Length
-    data.extend_from_slice(&(compressed_data.len() as u32).to_be_bytes());
-    
-    // Type "IDAT"
-    data.extend_from_slice(b"IDAT");
-    
-    // Data
-    data.extend(compressed_data);
-    
-    // CRC (placeholder)
-    data.extend_from_slice(&[0, 0, 0, 0]);
-    
-    data
-}
+// Global generation state
+static GENERATION_PROGRESS: Mutex<Option<GenerationProgress>> = Mutex::new(None);
+static GENERATION_CANCELLED: Mutex<bool> = Mutex::new(false);
+// The backend runs one job at a time and the state above belongs to that job, so
+// every caller (GUI, CLI and HTTP API) waits here for its turn
+static GENERATION_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
 
-fn create_iend_chunk() -> Vec<u8> {
-    let mut data = vec![];
-    
-    // Length (0 bytes)
-    data.extend_from_slice(&0u32.to_be_bytes());
-    
-    // Type "IEND"
-    data.extend_from_slice(b"IEND");
-    
-    // CRC (placeholder)
-    data.extend_from_slice(&[0, 0, 0, 0]);
-    
-    data
-}
+// The CLIP tokenizer is loaded lazily from the backend's vocab file
+static CLIP_TOKENIZER: Mutex<Option<Arc<ClipTokenizer>>> = Mutex::new(None);
 
-// Python backend manager
-#[derive(Clone)]
-struct PythonBackend {
-    backend_path: PathBuf,
-}
+fn get_clip_tokenizer() -> Result<Arc<ClipTokenizer>> {
+    let mut tokenizer = CLIP_TOKENIZER.lock()
+        .map_err(|_| anyhow::anyhow!("Failed to acquire tokenizer lock"))?;
 
-impl PythonBackend {
-    fn new() -> Result<Self> {
-        // Get the backend path relative to the project root
-        // In development, we're in the src-tauri directory, so go up to project root
-        let current_dir = std::env::current_dir()
-            .context("Failed to get current directory")?;
-        let project_root = current_dir
+    if tokenizer.is_none() {
+        let backend_script = find_backend_script(&load_settings())?;
+        let vocab_path = backend_script
             .parent()
-            .context("Failed to get project root")?
-            .parent()
-            .context("Failed to get workspace root")?;
-        
-        let backend_path = project_root
-            .join("backends")
+            .context("Backend script has no parent directory")?
             .join("stable_diffusion")
-            .join("diffusionbee_backend.py");
-
-        Ok(Self {
-            backend_path,
-        })
+            .join("clip_tokenizer")
+            .join("bpe_simple_vocab_16e6.txt.gz");
+        *tokenizer = Some(Arc::new(ClipTokenizer::load(&vocab_path)?));
     }
 
-    fn start_backend(&self) -> Result<()> {
-        // TODO: Implement proper backend startup
-        // For now, just validate the backend path exists
-        if !self.backend_path.exists() {
-            return Err(anyhow::anyhow!("Python backend not found at {:?}", self.backend_path));
-        }
-        Ok(())
-    }
+    Ok(tokenizer.as_ref().unwrap().clone())
 }
 
-// Global backend instance with proper synchronization
-static BACKEND: Mutex<Option<PythonBackend>> = Mutex::new(None);
+// How often cancellation is checked while waiting for backend output
+const BACKEND_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// A job fails when the backend prints nothing for this long. Generous, because
+// loading a large model prints nothing until it is done.
+const BACKEND_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+// How long the backend gets to finish after `__stop__` before it is killed
+const BACKEND_STOP_GRACE: Duration = Duration::from_secs(5);
 
-// Global generation state
-static GENERATION_PROGRESS: Mutex<Option<GenerationProgress>> = Mutex::new(None);
-static GENERATION_CANCELLED: Mutex<bool> = Mutex::new(false);
-
-fn get_backend() -> Result<PythonBackend> {
-    static BACKEND: Mutex<Option<PythonBackend>> = Mutex::new(None);
-    
-    let mut backend = BACKEND.lock()
-        .map_err(|_| anyhow::anyhow!("Failed to acquire backend lock"))?;
-    
-    if backend.is_none() {
-        *backend = Some(PythonBackend::new()?);
-    }
-    
-    Ok(backend.as_ref().unwrap().clone())
-}
-
-async fn call_python_backend(
+async fn call_backend(
     request: &ImageGenerationRequest,
-    model_path: &PathBuf,
-    _output_dir: &PathBuf,
-) -> Result<ImageGenerationResponse, String> {
-    println!("[RUST] Calling Python backend with model: {}", model_path.display());
-    
-    // Find the Python backend script
-    let backend_script = find_backend_script()
-        .map_err(|e| format!("Failed to find backend script: {}", e))?;
-    println!("[RUST] Backend script found at: {}", backend_script.display());
-    
+    model_path: &Path,
+    settings: &AppSettings,
+) -> Result<ImageGenerationResponse, DiffusionError> {
+    // The backend doesn't understand weighting syntax yet, so it gets the plain
+    // prompt plus the structured form for when it learns to use it
+    let weighted_prompt = request.weighted_prompt()?;
+
     // Prepare the JSON request in the format expected by the Python backend
     let json_request = serde_json::json!({
-        "prompt": request.prompt,
+        "prompt": weighted_prompt.plain_prompt(),
+        "weighted_prompt": weighted_prompt,
         "img_width": request.img_width,
         "img_height": request.img_height,
         "num_imgs": request.num_imgs,
-        "num_inference_steps": request.num_inference_steps,
+        "num_steps": request.num_inference_steps,
         "guidance_scale": request.guidance_scale,
+        "seed": request.seed,
+        "negative_prompt": request.negative_prompt,
+        "input_image_path": request.input_image,
+        // The backend compares it with numbers, so null would fail there; 0.5 is SDRun's default
+        "input_image_strength": request.input_image_strength.unwrap_or(0.5),
+        "small_mod_seed": request.small_mod_seed,
+        "small_mod_strength": request.small_mod_strength,
+        "scheduler": request.scheduler.as_deref().unwrap_or("__default_for_model__"),
+        "lora_tdict_paths": request
+            .loras
+            .iter()
+            .map(|lora| (lora.path.clone(), serde_json::Value::from(lora.weight)))
+            .collect::<serde_json::Map<_, _>>(),
         "tdict_path": model_path.to_string_lossy(),
     });
     
-    let request_str = format!("b2py t2im {}", json_request.to_string());
-    println!("[RUST] Sending request to Python backend: {}", request_str);
-    
-    // Start the Python backend process
-    let mut child = Command::new("python3")
-        .arg(backend_script)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to start Python backend: {}", e))?;
-    
-    // Send the request to the backend
-    if let Some(stdin) = child.stdin.as_mut() {
-        use std::io::Write;
-        stdin.write_all(request_str.as_bytes())
-            .map_err(|e| format!("Failed to write to Python backend: {}", e))?;
-        stdin.write_all(b"\n")
-            .map_err(|e| format!("Failed to write newline to Python backend: {}", e))?;
-    }
-    
-    // Read the response
-    let output = child.wait_with_output()
-        .map_err(|e| format!("Failed to get output from Python backend: {}", e))?;
-    
-    println!("[RUST] Python backend stdout: {}", String::from_utf8_lossy(&output.stdout));
-    println!("[RUST] Python backend stderr: {}", String::from_utf8_lossy(&output.stderr));
-    
-    if !output.status.success() {
-        return Err(format!("Python backend failed with status: {}", output.status));
-    }
-    
-    // Parse the response
-    let stdout_str = String::from_utf8_lossy(&output.stdout);
-    for line in stdout_str.lines() {
-        if line.contains("sdbk nwim") {
-            // Extract the JSON response
-            if let Some(json_start) = line.find("sdbk nwim ") {
-                let json_str = &line[json_start + 10..];
-                match serde_json::from_str::<serde_json::Value>(json_str) {
-                    Ok(response) => {
-                        if let Some(img_path) = response["generated_img_path"].as_str() {
-                            return Ok(ImageGenerationResponse {
-                                generated_img_path: img_path.to_string(),
-                                aux_output_image_path: response["aux_output_image_path"].as_str().map(|s| s.to_string()),
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        println!("[RUST] Failed to parse JSON response: {}", e);
-                    }
+    let request_str = format!("b2py t2im {}", json_request);
+    log::debug!("Sending request to backend: {}", request_str);
+
+    let mut backend = if mock_backend::is_enabled(settings) {
+        log::info!("Using the mock backend");
+        mock_backend::spawn(MockSchedule::from_env(), mock_backend::images_dir())
+    } else {
+        log::info!("Calling Python backend with model: {}", model_path.display());
+        let (python, backend_script) = find_python_backend(settings).await?;
+        log::debug!("Backend script found at: {} (Python: {})", backend_script.display(), python.display());
+        BackendConnection::spawn_python(&python, &backend_script)?
+    };
+    backend.send(&request_str)?;
+
+    let mut started = false;
+    let mut stop_sent: Option<Instant> = None;
+    let mut last_output = Instant::now();
+    let mut backend_error: Option<String> = None;
+    let mut images: Vec<serde_json::Value> = Vec::new();
+    let mut stderr = String::new();
+
+    loop {
+        match stop_sent {
+            None if GENERATION_CANCELLED.lock().map(|c| *c).unwrap_or(false) => {
+                log::info!("Asking the backend to stop");
+                let _ = backend.send("__stop__");
+                stop_sent = Some(Instant::now());
+            }
+            Some(at) if at.elapsed() > BACKEND_STOP_GRACE => {
+                // Dropping the connection below kills the process
+                log::warn!("Backend did not stop within {:?}, killing it", BACKEND_STOP_GRACE);
+                break;
+            }
+            _ => {}
+        }
+
+        let received = backend.recv(BACKEND_POLL_INTERVAL).await;
+        if received.is_ok() {
+            last_output = Instant::now();
+        }
+        let line = match received {
+            Err(_) if last_output.elapsed() > BACKEND_IDLE_TIMEOUT => {
+                return Err(DiffusionError::backend(
+                    format!(
+                        "Backend stopped responding (no output for {} seconds)",
+                        BACKEND_IDLE_TIMEOUT.as_secs()
+                    ),
+                    &stderr,
+                ));
+            }
+            Err(_) => continue,
+            Ok(Some(BackendLine::Stderr(line))) => {
+                logging::backend_output(BackendStream::Stderr, &line);
+                stderr.push_str(&line);
+                stderr.push('\n');
+                continue;
+            }
+            Ok(Some(BackendLine::Stdout(line))) => line,
+            Ok(None) => break,
+        };
+        logging::backend_output(BackendStream::Stdout, &line);
+
+        if line.starts_with("sdbk inwk") {
+            started = true;
+        } else if let Some(status) = line.strip_prefix("sdbk gnms ") {
+            update_progress(|prog| prog.status = status.trim().to_string());
+        } else if let Some(percent) = line.strip_prefix("sdbk dnpr ") {
+            if let Ok(percent) = percent.trim().parse::<f32>() {
+                if percent >= 0.0 {
+                    update_progress(|prog| {
+                        prog.current_step = (percent / 100.0 * prog.total_steps as f32).round() as u32;
+                        prog.status = "Generating".to_string();
+                    });
                 }
             }
+        } else if let Some(json_str) = line.strip_prefix("sdbk nwim ") {
+            match serde_json::from_str::<serde_json::Value>(json_str) {
+                Ok(response) => images.push(response),
+                Err(e) => log::warn!("Failed to parse JSON response: {}", e),
+            }
+        } else if let Some(message) = line.strip_prefix("sdbk errr ") {
+            backend_error = Some(message.trim().to_string());
+        } else if line.starts_with("sdbk inrd") && (started || backend_error.is_some()) {
+            // Ready for the next request, so this one is finished
+            break;
         }
     }
-    
-    Err("No valid response received from Python backend".to_string())
+    // Dropping the connection stops the backend process
+    drop(backend);
+
+    if let Some(message) = backend_error {
+        return Err(DiffusionError::backend(message, &stderr));
+    }
+
+    let image_paths: Vec<String> = images
+        .iter()
+        .filter_map(|response| response["generated_img_path"].as_str().map(|s| s.to_string()))
+        .collect();
+    let first_image = image_paths.first().map(|img_path| ImageGenerationResponse {
+        generated_img_path: img_path.clone(),
+        aux_output_image_path: images
+            .iter()
+            .find_map(|response| response["aux_output_image_path"].as_str().map(|s| s.to_string())),
+        images: image_paths.clone(),
+        prompt: request.prompt.clone(),
+        seed: request.seed,
+        warnings: Vec::new(),
+        job_id: logging::current_job(),
+        history_id: None,
+        derived_images: Vec::new(),
+    });
+
+    match first_image {
+        Some(response) => Ok(response),
+        None if stop_sent.is_some() => Err(DiffusionError::Cancelled),
+        None if !started => Err(DiffusionError::backend("Python backend exited before accepting the request", &stderr)),
+        None => Err(DiffusionError::backend("No valid response received from Python backend", &stderr)),
+    }
 }
 
-fn find_backend_script() -> Result<PathBuf> {
-    // Try to find the backend script relative to the current executable
-    let current_exe = std::env::current_exe()
-        .context("Failed to get current executable path")?;
-    
-    // Look for the backend script in the expected location
-    let possible_paths = vec![
-        // Development path
-        current_exe.parent().unwrap().parent().unwrap().parent().unwrap()
-            .join("backends").join("stable_diffusion").join("diffusionbee_backend.py"),
-        // Production path (if bundled)
-        current_exe.parent().unwrap().join("backends").join("stable_diffusion").join("diffusionbee_backend.py"),
-    ];
-    
-    for path in possible_paths {
-        if path.exists() {
-            return Ok(path);
+/// Encodes the backend's images in the output format, moves them into the
+/// output directory under their final names and points the response at them
+fn place_outputs(
+    response: &mut ImageGenerationResponse,
+    request: &ImageGenerationRequest,
+    settings: &AppSettings,
+) -> Result<(), DiffusionError> {
+    let model = settings.model_name();
+    let parameters = embedded_parameters(request, &model, settings);
+    let encode_options = settings.encode_options();
+    let mut context = OutputContext {
+        timestamp: output_files::local_now(),
+        seed: request.seed,
+        model,
+        prompt: request.prompt.clone(),
+        index: 1,
+        steps: request.num_inference_steps,
+    };
+    let output_dir = PathBuf::from(&settings.output_directory);
+    let folder = output_files::output_folder(&output_dir, settings.output_subfolders, &context);
+
+    let mut placed = Vec::new();
+    for (index, image) in response.images.iter().enumerate() {
+        context.index = index as u32 + 1;
+        let stem = output_files::render_template(&settings.filename_template, &context)?;
+        let encoded = output_format::convert_file(Path::new(image), encode_options, parameters.as_deref())?;
+        let path = output_files::place_file(&encoded, &folder, &stem, false).inspect_err(|_| {
+            let _ = fs::remove_file(&encoded);
+        })?;
+        if !settings.keep_backend_files {
+            let _ = fs::remove_file(image);
         }
+        placed.push(path.to_string_lossy().to_string());
     }
-    
-    Err(anyhow::anyhow!("Backend script not found in any expected location"))
+    if let Some(aux) = &response.aux_output_image_path {
+        context.index = 1;
+        let stem = format!("{}_aux", output_files::render_template(&settings.filename_template, &context)?);
+        let path = output_files::place_file(Path::new(aux), &folder, &stem, settings.keep_backend_files)?;
+        response.aux_output_image_path = Some(path.to_string_lossy().to_string());
+    }
+
+    log::info!("Saved {} image(s) to {}", placed.len(), folder.display());
+    response.generated_img_path = placed.first().cloned().unwrap_or_default();
+    response.images = placed;
+    Ok(())
 }
 
-fn create_fallback_image(
-    request: &ImageGenerationRequest,
-    output_dir: &PathBuf,
-) -> Result<ImageGenerationResponse, String> {
-    println!("[RUST] Creating fallback image");
-    
-    // Generate a unique filename with timestamp
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| format!("Failed to get timestamp: {}", e))?
-        .as_secs();
-    
-    let filename = format!("diffusionbee_fallback_{}_{}x{}.png", timestamp, request.img_width, request.img_height);
-    let output_path = output_dir.join(&filename);
-    
-    // Create a placeholder image
-    create_placeholder_image(&output_path, request.img_width, request.img_height)
-        .map_err(|e| format!("Failed to create fallback image: {}", e))?;
-    
-    Ok(ImageGenerationResponse {
-        generated_img_path: output_path.to_string_lossy().to_string(),
-        aux_output_image_path: None,
+/// The `parameters` text written into saved images, unless `embed_metadata` is off
+fn embedded_parameters(request: &ImageGenerationRequest, model: &str, settings: &AppSettings) -> Option<String> {
+    settings.embed_metadata.then(|| {
+        GenerationMetadata {
+            prompt: request.prompt.clone(),
+            negative_prompt: request.negative_prompt.clone(),
+            steps: request.num_inference_steps,
+            guidance_scale: request.guidance_scale,
+            seed: request.seed,
+            variation: request
+                .small_mod_seed
+                .map(|seed| (seed, request.small_mod_strength.unwrap_or(variations::DEFAULT_STRENGTH))),
+            width: request.img_width,
+            height: request.img_height,
+            model: model.to_string(),
+        }
+        .to_parameters()
     })
 }
 
+/// Records the job in the history, as derived from another entry's image when
+/// `derivation` is set, and runs the post-processing stages on its
+/// images. Neither can lose the images already saved, so problems become
+/// warnings on the response.
+async fn finish_job(
+    response: &mut ImageGenerationResponse,
+    request: &ImageGenerationRequest,
+    settings: &AppSettings,
+    derivation: Option<Derivation>,
+) {
+    let mut entry = HistoryEntry::new(request.clone(), settings, response.images.clone());
+    entry.derivation = derivation;
+    match FileHashes::of(&entry.model_path, &request.loras).await {
+        Ok(hashes) => entry.hashes = hashes,
+        Err(e) => log::warn!("Not recording model hashes: {}", e),
+    }
+    if let Err(e) = history::record(entry.clone()) {
+        log::warn!("Failed to record history: {}", e);
+        response.warnings.push(format!("Not added to history: {}", e.message()));
+        return;
+    }
+    response.history_id = Some(entry.id.clone());
+
+    if settings.post_processing.is_empty() {
+        return;
+    }
+    update_progress(|prog| prog.status = "Post-processing".to_string());
+    let (derived, errors) = post_processing::run_pipeline(&entry, settings).await;
+    response.derived_images = derived.into_iter().flat_map(|entry| entry.images).collect();
+    for e in errors {
+        response.warnings.push(format!("Post-processing failed: {}", e.message()));
+    }
+}
+
+/// Progress of the running (or last) job, `None` before the first one
+pub fn generation_progress() -> Result<Option<GenerationProgress>, DiffusionError> {
+    let progress = GENERATION_PROGRESS.lock()
+        .map_err(|_| DiffusionError::lock("progress"))?;
+    Ok(progress.clone())
+}
+
+fn update_progress(update: impl FnOnce(&mut GenerationProgress)) {
+    if let Ok(mut progress) = GENERATION_PROGRESS.lock() {
+        if let Some(ref mut prog) = *progress {
+            update(prog);
+        }
+    }
+}
+
+fn find_backend_script(settings: &AppSettings) -> Result<PathBuf, DiffusionError> {
+    backend_locator::resolve_backend_script(&settings.backend_script_path)
+}
+
+/// The interpreter and script used to start the Python backend
+async fn find_python_backend(settings: &AppSettings) -> Result<(PathBuf, PathBuf), DiffusionError> {
+    let backend_script = find_backend_script(settings)?;
+    let python = python_env::resolve_interpreter(&settings.python_path, backends_dir(&backend_script)).await;
+    Ok((python, backend_script))
+}
+
+// backends/stable_diffusion/diffusionbee_backend.py -> backends/, where Poetry and mise are set up
+fn backends_dir(backend_script: &Path) -> Option<&Path> {
+    backend_script.parent()?.parent()
+}
+
 // Tauri commands
 #[tauri::command]
-async fn generate_image(request: ImageGenerationRequest) -> Result<ImageGenerationResponse, String> {
-    println!("[RUST] Starting image generation for prompt: {}", request.prompt);
-    
-    // Validate the request first
-    request.validate()?;
-    println!("[RUST] Request validation passed");
+async fn generate_image(request: ImageGenerationRequest) -> Result<ImageGenerationResponse, DiffusionError> {
+    log::info!("Starting image generation for prompt: {}", request.prompt);
 
     // Get settings
-    let settings = get_settings().await.map_err(|e| {
-        println!("[RUST] Failed to get settings: {}", e);
-        e.to_string()
-    })?;
-    println!("[RUST] Settings loaded - Output dir: {}, Model path: {}", 
-             settings.output_directory, settings.model_path);
+    let settings = get_settings().await?;
+
+    run_generation(request, &settings).await
+}
 
-    // Check if model exists
-    if settings.model_path.is_empty() {
-        println!("[RUST] No model path configured");
-        return Err("No Stable Diffusion model found. Please download a model first.".to_string());
+/// Runs one generation end to end: prompt expansion, validation and the backend
+/// call. Shared by the `generate_image` command and the headless CLI. Everything
+/// logged on the way carries a fresh job id, returned in the response. The
+/// prompt, as given, is counted in the prompt history.
+pub async fn run_generation(
+    request: ImageGenerationRequest,
+    settings: &AppSettings,
+) -> Result<ImageGenerationResponse, DiffusionError> {
+    if let Err(e) = prompt_history::record(&request.prompt) {
+        log::warn!("Failed to record prompt: {}", e);
+    }
+    logging::with_job(logging::new_job_id("gen"), generate_in_job(request, settings, None)).await
+}
+
+async fn generate_in_job(
+    mut request: ImageGenerationRequest,
+    settings: &AppSettings,
+    derivation: Option<Derivation>,
+) -> Result<ImageGenerationResponse, DiffusionError> {
+    log::debug!("Output dir: {}, model path: {}", settings.output_directory, settings.model_path);
+
+    // Expand prompt templates before validating so the final prompt is what gets checked
+    request.expand_prompt(&PathBuf::from(&settings.wildcards_directory))?;
+    log::info!("Expanded prompt (seed {:?}): {}", request.seed, request.prompt);
+
+    // Validate the request first
+    let schema = settings.parameter_schema();
+    log::debug!("Validating against the {} profile", schema.family.display_name());
+    request.validate(&schema)?;
+
+    // Warn (but don't fail) about unmatched brackets and prompts CLIP will silently truncate
+    let weighted_prompt = request.weighted_prompt()?;
+    let mut warnings = weighted_prompt.warnings.clone();
+    for warning in &warnings {
+        log::warn!("{}", warning);
+    }
+    match get_clip_tokenizer() {
+        Ok(tokenizer) => {
+            let plain_prompt = weighted_prompt.plain_prompt();
+            if let Some(warning) = tokenizer.count_prompt_tokens(&plain_prompt).warning() {
+                log::warn!("{}", warning);
+                warnings.push(warning);
+            }
+        }
+        Err(e) => log::warn!("Skipping prompt length check: {:#}", e),
     }
 
+    // The mock needs neither a model nor the Python backend
+    let use_mock = mock_backend::is_enabled(settings);
     let model_path = PathBuf::from(&settings.model_path);
-    if !model_path.exists() {
-        println!("[RUST] Model file not found at: {}", model_path.display());
-        return Err(format!("Model file not found at: {}", model_path.display()));
+    if !use_mock {
+        // Check if model exists
+        if settings.model_path.is_empty() {
+            log::error!("No model path configured");
+            return Err(DiffusionError::ModelNotFound(
+                "No Stable Diffusion model found. Please download a model first.".to_string(),
+            ));
+        }
+
+        if !model_path.exists() {
+            log::error!("Model file not found at: {}", model_path.display());
+            return Err(DiffusionError::ModelNotFound(format!("Model file not found at: {}", model_path.display())));
+        }
+        log::debug!("Model file found at: {}", model_path.display());
+
+        // Fail before touching the progress state if the backend can't be found
+        let backend_script = find_backend_script(settings).inspect_err(|e| log::error!("{}", e))?;
+        log::debug!("Backend script found at: {}", backend_script.display());
     }
-    println!("[RUST] Model file found at: {}", model_path.display());
 
-    // Get the backend
-    let backend = get_backend().map_err(|e| {
-        println!("[RUST] Failed to get backend: {}", e);
-        e.to_string()
-    })?;
-    
-    // Validate the backend is available
-    backend.start_backend().map_err(|e| {
-        println!("[RUST] Failed to start backend: {}", e);
-        e.to_string()
-    })?;
-    println!("[RUST] Backend validation passed");
+    let _generation = GENERATION_LOCK.lock().await;
 
     // Reset progress and cancellation state
     {
         let mut progress = GENERATION_PROGRESS.lock()
-            .map_err(|_| "Failed to acquire progress lock".to_string())?;
+            .map_err(|_| DiffusionError::lock("progress"))?;
         *progress = Some(GenerationProgress {
             current_step: 0,
             total_steps: request.num_inference_steps,
             status: "Initializing...".to_string(),
             is_complete: false,
             is_cancelled: false,
+            job_id: logging::current_job(),
         });
         
         let mut cancelled = GENERATION_CANCELLED.lock()
-            .map_err(|_| "Failed to acquire cancellation lock".to_string())?;
+            .map_err(|_| DiffusionError::lock("cancellation"))?;
         *cancelled = false;
     }
-    println!("[RUST] Progress state initialized");
 
     // Prepare output directory
     let output_dir = PathBuf::from(&settings.output_directory);
     fs::create_dir_all(&output_dir)
-        .map_err(|e| format!("Failed to create output directory: {}", e))?;
-    println!("[RUST] Output directory prepared: {}", output_dir.display());
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to create output directory: {}", e)))?;
+    log::debug!("Output directory prepared: {}", output_dir.display());
 
-    // Try to call the Python backend
-    match call_python_backend(&request, &model_path, &output_dir).await {
-        Ok(response) => {
-            println!("[RUST] Python backend call successful: {:?}", response);
-            
-            // Mark as complete
-            {
-                let mut progress = GENERATION_PROGRESS.lock()
-                    .map_err(|_| "Failed to acquire progress lock".to_string())?;
-                if let Some(ref mut prog) = *progress {
-                    prog.is_complete = true;
-                    prog.status = "Complete".to_string();
-                }
-            }
-            
+    let mut result = call_backend(&request, &model_path, settings)
+        .await
+        .and_then(|mut response| {
+            place_outputs(&mut response, &request, settings)?;
+            Ok(response)
+        });
+    if let Ok(response) = &mut result {
+        finish_job(response, &request, settings, derivation).await;
+    }
+
+    // Mark as finished either way so the progress poller stops
+    {
+        let mut progress = GENERATION_PROGRESS.lock()
+            .map_err(|_| DiffusionError::lock("progress"))?;
+        if let Some(ref mut prog) = *progress {
+            prog.is_complete = true;
+            prog.is_cancelled = matches!(result, Err(DiffusionError::Cancelled));
+            prog.status = match &result {
+                Ok(_) => "Complete".to_string(),
+                Err(e) => e.message(),
+            };
+        }
+    }
+
+    match result {
+        Ok(mut response) => {
+            log::info!("Generated {}", response.generated_img_path);
+            warnings.append(&mut response.warnings);
+            response.warnings = warnings;
             Ok(response)
         }
         Err(e) => {
-            println!("[RUST] Python backend call failed: {}", e);
-            
-            // Fallback to placeholder image
-            println!("[RUST] Falling back to placeholder image");
-            let fallback_response = create_fallback_image(&request, &output_dir)
-                .map_err(|fe| format!("Failed to create fallback image: {}", fe))?;
-            
-            // Mark as complete
-            {
-                let mut progress = GENERATION_PROGRESS.lock()
-                    .map_err(|_| "Failed to acquire progress lock".to_string())?;
-                if let Some(ref mut prog) = *progress {
-                    prog.is_complete = true;
-                    prog.status = "Complete (Fallback)".to_string();
-                }
-            }
-            
-            Ok(fallback_response)
+            log::error!("Generation failed: {}", e);
+            Err(e)
         }
     }
 }
 
 #[tauri::command]
-async fn get_generation_progress() -> Result<GenerationProgress, String> {
-    let progress = GENERATION_PROGRESS.lock()
-        .map_err(|_| "Failed to acquire progress lock".to_string())?;
-    
-    match progress.as_ref() {
-        Some(prog) => Ok(prog.clone()),
+async fn get_generation_progress() -> Result<GenerationProgress, DiffusionError> {
+    match generation_progress()? {
+        Some(prog) => Ok(prog),
         None => Ok(GenerationProgress {
             current_step: 0,
             total_steps: 0,
             status: "No generation in progress".to_string(),
             is_complete: false,
             is_cancelled: false,
+            job_id: None,
         }),
     }
 }
 
 #[tauri::command]
-async fn cancel_generation() -> Result<(), String> {
+async fn cancel_generation() -> Result<(), DiffusionError> {
     let mut cancelled = GENERATION_CANCELLED.lock()
-        .map_err(|_| "Failed to acquire cancellation lock".to_string())?;
+        .map_err(|_| DiffusionError::lock("cancellation"))?;
     *cancelled = true;
     
     // Update progress to show cancellation
     if let Some(ref mut progress) = *GENERATION_PROGRESS.lock()
-        .map_err(|_| "Failed to acquire progress lock".to_string())? {
+        .map_err(|_| DiffusionError::lock("progress"))? {
         progress.is_cancelled = true;
         progress.status = "Cancelling...".to_string();
     }
@@ -585,77 +940,600 @@ async fn cancel_generation() -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn get_models() -> Result<Vec<String>, String> {
-    // Look for models in the .diffusionbee/imported_models directory
-    if let Some(home_dir) = dirs::home_dir() {
-        let diffusionbee_dir = home_dir.join(".diffusionbee");
-        let imported_models_dir = diffusionbee_dir.join("imported_models");
-        
-        if imported_models_dir.exists() {
-            let mut models = Vec::new();
-            if let Ok(entries) = fs::read_dir(imported_models_dir) {
-                for entry in entries.flatten() {
-                    if let Some(ext) = entry.path().extension() {
-                        if ext == "tdict" {
-                            if let Some(name) = entry.path().file_name() {
-                                models.push(name.to_string_lossy().to_string());
-                            }
-                        }
-                    }
+async fn interpolate_video(request: InterpolationRequest) -> Result<InterpolationResponse, DiffusionError> {
+    logging::with_job(logging::new_job_id("interp"), interpolate_in_job(request)).await
+}
+
+async fn interpolate_in_job(request: InterpolationRequest) -> Result<InterpolationResponse, DiffusionError> {
+    log::info!("Starting interpolation: {} -> {}", request.prompt1, request.prompt2);
+    let settings = load_settings();
+    request.validate(&settings.parameter_schema())?;
+    if mock_backend::is_enabled(&settings) {
+        return Err(DiffusionError::ProcessError(
+            "Interpolation needs the Python backend and is not available with the mock backend".to_string(),
+        ));
+    }
+    let model_path = PathBuf::from(&settings.model_path);
+    if settings.model_path.is_empty() || !model_path.exists() {
+        return Err(DiffusionError::ModelNotFound(
+            "No Stable Diffusion model found. Please download a model first.".to_string(),
+        ));
+    }
+
+    let (python, backend_script) = find_python_backend(&settings).await?;
+
+    let output_dir = PathBuf::from(&settings.output_directory);
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to create output directory: {}", e)))?;
+
+    // Frames are reported through the same progress state as image generation
+    let _generation = GENERATION_LOCK.lock().await;
+    {
+        let mut progress = GENERATION_PROGRESS.lock()
+            .map_err(|_| DiffusionError::lock("progress"))?;
+        *progress = Some(GenerationProgress {
+            current_step: 0,
+            total_steps: request.num_frames,
+            status: "Rendering frames...".to_string(),
+            is_complete: false,
+            is_cancelled: false,
+            job_id: logging::current_job(),
+        });
+
+        let mut cancelled = GENERATION_CANCELLED.lock()
+            .map_err(|_| DiffusionError::lock("cancellation"))?;
+        *cancelled = false;
+    }
+
+    let result = interpolation::run_interpolation(
+        &request,
+        &python,
+        &backend_script,
+        &model_path,
+        &output_dir,
+        |frame| {
+            if let Ok(mut progress) = GENERATION_PROGRESS.lock() {
+                if let Some(ref mut prog) = *progress {
+                    prog.current_step = frame.rendered;
+                    prog.total_steps = frame.total;
+                    prog.status = format!("Rendered {} of {} frames", frame.rendered, frame.total);
                 }
             }
-            return Ok(models);
+        },
+        || GENERATION_CANCELLED.lock().map(|c| *c).unwrap_or(false),
+    )
+    .await;
+
+    if let Ok(mut progress) = GENERATION_PROGRESS.lock() {
+        if let Some(ref mut prog) = *progress {
+            prog.is_complete = true;
+            prog.status = match &result {
+                Ok(_) => "Complete".to_string(),
+                Err(e) => e.message(),
+            };
         }
     }
-    
-    Ok(vec![])
+
+    log::info!("Interpolation finished: {:?}", result);
+    result
+}
+
+// Applets registered by the backend, discovered on first use
+static DISCOVERED_APPLETS: Mutex<Option<Vec<AppletInfo>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppletForm {
+    pub applet: AppletInfo,
+    pub fields: Vec<FormField>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppletUpdateEvent {
+    pub applet_id: String,
+    /// State key relative to the applet, e.g. `outputs`
+    pub key: String,
+    pub value: serde_json::Value,
+    pub is_output: bool,
+}
+
+async fn get_applets(refresh: bool) -> Result<Vec<AppletInfo>, DiffusionError> {
+    if !refresh {
+        let cached = DISCOVERED_APPLETS.lock()
+            .map_err(|_| DiffusionError::lock("applets"))?;
+        if let Some(applets) = cached.as_ref() {
+            return Ok(applets.clone());
+        }
+    }
+
+    // The mock backend registers no applets
+    let settings = load_settings();
+    let applets = if mock_backend::is_enabled(&settings) {
+        Vec::new()
+    } else {
+        let (python, backend_script) = find_python_backend(&settings).await?;
+        applets::discover_applets(&python, &backend_script).await?
+    };
+    log::info!("Discovered {} backend applets", applets.len());
+
+    let mut cached = DISCOVERED_APPLETS.lock()
+        .map_err(|_| DiffusionError::lock("applets"))?;
+    *cached = Some(applets.clone());
+    Ok(applets)
+}
+
+async fn find_applet(applet_id: &str) -> Result<AppletInfo, DiffusionError> {
+    get_applets(false)
+        .await?
+        .into_iter()
+        .find(|a| a.id == applet_id)
+        .ok_or_else(|| DiffusionError::validation("applet_id", format!("Unknown applet: {}", applet_id)))
+}
+
+#[tauri::command]
+async fn list_applets(refresh: Option<bool>) -> Result<Vec<AppletInfo>, DiffusionError> {
+    get_applets(refresh.unwrap_or(false)).await
+}
+
+#[tauri::command]
+async fn get_applet_form(applet_id: String) -> Result<AppletForm, DiffusionError> {
+    let applet = find_applet(&applet_id).await?;
+    let fields = applets::form_fields(&applet.inputs);
+    Ok(AppletForm { applet, fields })
+}
+
+/// Runs an applet, forwarding its updates as `applet-update` events. Returns the
+/// applet's final outputs. `cancel_generation` stops applets that support it.
+#[tauri::command]
+async fn run_applet(
+    app: tauri::AppHandle,
+    applet_id: String,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, DiffusionError> {
+    let applet = find_applet(&applet_id).await?;
+    let mut params = applets::validate_params(&applet.inputs, &params)?;
+
+    // Applets build their SD runs from the params, so they need the active model
+    let settings = load_settings();
+    if !settings.model_path.is_empty() && params.get("tdict_path").is_none() {
+        params["tdict_path"] = serde_json::Value::String(settings.model_path.clone());
+    }
+
+    let (python, backend_script) = find_python_backend(&settings).await?;
+
+    {
+        let mut cancelled = GENERATION_CANCELLED.lock()
+            .map_err(|_| DiffusionError::lock("cancellation"))?;
+        *cancelled = false;
+    }
+
+    let prefix = format!("{}.{}.", applets::APPLETS_STATE_KEY, applet_id);
+    let mut outputs = serde_json::Value::Null;
+    logging::with_job(logging::new_job_id("applet"), async {
+        log::info!("Running applet {}", applet_id);
+        applets::run_applet(
+            &python,
+            &backend_script,
+            &applet_id,
+            &params,
+            |key, value| {
+                let Some(key) = key.strip_prefix(&prefix) else {
+                    return;
+                };
+                let is_output = key == "outputs" || key.starts_with("outputs.");
+                if key == "outputs" {
+                    outputs = value.clone();
+                }
+                let event = AppletUpdateEvent {
+                    applet_id: applet_id.clone(),
+                    key: key.to_string(),
+                    value: value.clone(),
+                    is_output,
+                };
+                if let Err(e) = app.emit("applet-update", event) {
+                    log::warn!("Failed to emit applet update: {}", e);
+                }
+            },
+            || applet.is_stop_avail && GENERATION_CANCELLED.lock().map(|c| *c).unwrap_or(false),
+        )
+        .await
+    })
+    .await?;
+
+    Ok(outputs)
+}
+
+#[tauri::command]
+async fn count_prompt_tokens(prompt: String) -> Result<PromptTokenCount, DiffusionError> {
+    let tokenizer = get_clip_tokenizer().map_err(|e| DiffusionError::FileSystem(format!("{:#}", e)))?;
+    Ok(tokenizer.count_prompt_tokens(&prompt))
+}
+
+#[tauri::command]
+async fn get_models() -> Result<Vec<String>, DiffusionError> {
+    // Models in .diffusionbee/imported_models
+    let models = model_registry::scan_models("")
+        .into_iter()
+        .map(|model| model.name)
+        .collect();
+    Ok(models)
+}
+
+/// Size, step and guidance limits and the resolution presets for the active model
+#[tauri::command]
+async fn get_validation_profile() -> Result<ValidationProfile, DiffusionError> {
+    Ok(load_settings().validation_profile())
 }
 
+/// Min, max, step and default of every generation parameter, as enforced by
+/// `generate_image` and `save_settings`
 #[tauri::command]
-async fn set_active_model(_model_name: String) -> Result<(), String> {
+async fn get_parameter_schema() -> Result<ParameterSchema, DiffusionError> {
+    Ok(load_settings().parameter_schema())
+}
+
+#[tauri::command]
+async fn set_active_model(_model_name: String) -> Result<(), DiffusionError> {
     // Placeholder implementation
     Ok(())
 }
 
 // Settings commands
 #[tauri::command]
-async fn get_settings() -> Result<AppSettings, String> {
-    // For now, return default settings
-    // In the future, this would load from persistent storage
-    Ok(AppSettings::default())
+async fn get_settings() -> Result<AppSettings, DiffusionError> {
+    Ok(load_settings())
 }
 
 #[tauri::command]
-async fn save_settings(settings: AppSettings) -> Result<(), String> {
-    // For now, just validate the settings
-    // In the future, this would save to persistent storage
-    if settings.default_width < 256 || settings.default_width > 1024 {
-        return Err("Default width must be between 256 and 1024".to_string());
-    }
-    if settings.default_height < 256 || settings.default_height > 1024 {
-        return Err("Default height must be between 256 and 1024".to_string());
-    }
-    if settings.default_inference_steps < 10 || settings.default_inference_steps > 50 {
-        return Err("Default inference steps must be between 10 and 50".to_string());
-    }
-    if settings.default_guidance_scale < 1.0 || settings.default_guidance_scale > 20.0 {
-        return Err("Default guidance scale must be between 1.0 and 20.0".to_string());
-    }
+async fn save_settings(settings: AppSettings) -> Result<(), DiffusionError> {
+    let profile = settings.validation_profile();
+    validation_profile::validate_limits(&profile)?;
+    let schema = ParameterSchema::from_profile(&profile);
+    schema.check_default(Parameter::Width, settings.default_width as f64)?;
+    schema.check_default(Parameter::Height, settings.default_height as f64)?;
+    schema.check_default(Parameter::Steps, settings.default_inference_steps as f64)?;
+    schema.check_default(Parameter::Guidance, settings.default_guidance_scale as f64)?;
     
     // Validate model path if provided
     if !settings.model_path.is_empty() {
         let model_path = PathBuf::from(&settings.model_path);
         if !model_path.exists() {
-            return Err(format!("Model file not found at: {}", model_path.display()));
+            return Err(DiffusionError::validation(
+                "model_path",
+                format!("Model file not found at: {}", model_path.display()),
+            ));
         }
         if model_path.extension().and_then(|s| s.to_str()) != Some("tdict") {
-            return Err("Model file must have .tdict extension".to_string());
+            return Err(DiffusionError::validation("model_path", "Model file must have .tdict extension"));
         }
     }
-    
+
+    if !settings.python_path.is_empty() && !Path::new(&settings.python_path).is_file() {
+        return Err(DiffusionError::validation(
+            "python_path",
+            format!("Python interpreter not found at: {}", settings.python_path),
+        ));
+    }
+
+    if !settings.backend_script_path.is_empty() {
+        find_backend_script(&settings).map_err(|e| DiffusionError::validation("backend_script_path", e.message()))?;
+    }
+
+    output_files::validate_template(&settings.filename_template)?;
+    output_format::validate_quality(settings.output_quality)?;
+    post_processing::validate_settings(&settings)?;
+
+    // Validate API server settings
+    api_server::validate_settings(&settings)?;
+
+    write_settings(&settings).map_err(|e| DiffusionError::FileSystem(format!("{:#}", e)))?;
+
+    // Start, stop or restart the API server to match
+    api_server::apply_settings(&settings).await?;
+
     Ok(())
 }
 
+#[tauri::command]
+async fn get_api_server_status() -> Result<api_server::ApiServerStatus, DiffusionError> {
+    Ok(api_server::status())
+}
+
+// Python environment commands
+
+/// Finds the interpreters that could run the backend and checks each one's
+/// packages. Remembers the first usable one when none is configured yet.
+#[tauri::command]
+async fn probe_python_environments() -> Result<Vec<PythonProbe>, DiffusionError> {
+    let mut settings = load_settings();
+    let backend_script = find_backend_script(&settings).ok();
+    let backends_dir = backend_script.as_deref().and_then(backends_dir);
+
+    let mut probes = Vec::new();
+    for candidate in python_env::discover_interpreters(&settings.python_path, backends_dir).await {
+        let probe = python_env::probe_interpreter(&candidate).await;
+        log::info!("Probed {}: usable={} {:?}", probe.path.display(), probe.usable, probe.problems);
+        probes.push(probe);
+    }
+
+    if settings.python_path.is_empty() {
+        if let Some(probe) = probes.iter().find(|p| p.usable) {
+            settings.python_path = probe.path.to_string_lossy().to_string();
+            write_settings(&settings).map_err(|e| DiffusionError::FileSystem(format!("{:#}", e)))?;
+            log::info!("Using Python interpreter {}", settings.python_path);
+        }
+    }
+
+    Ok(probes)
+}
+
+/// Checks `python_path` and saves it as the backend interpreter if it can run the backend
+#[tauri::command]
+async fn select_python_interpreter(python_path: String) -> Result<PythonProbe, DiffusionError> {
+    let candidate = python_env::PythonCandidate {
+        path: PathBuf::from(&python_path),
+        source: python_env::InterpreterSource::Configured,
+    };
+    let probe = python_env::probe_interpreter(&candidate).await;
+    if !probe.usable {
+        return Err(DiffusionError::validation("python_path", probe.problems.join("; ")));
+    }
+
+    let mut settings = load_settings();
+    settings.python_path = python_path;
+    write_settings(&settings).map_err(|e| DiffusionError::FileSystem(format!("{:#}", e)))?;
+    Ok(probe)
+}
+
+// History and post-processing commands
+
+#[tauri::command]
+async fn get_history_entry(history_id: String) -> Result<HistoryEntry, DiffusionError> {
+    history::get(&history_id)
+}
+
+/// History entries matching `query`, e.g. `{"text": "lighthouse", "model":
+/// "sd-v1-5", "since": "2024-05-01", "sort": "newest", "limit": 50}`
+#[tauri::command]
+async fn query_history(query: HistoryQuery) -> Result<HistoryPage, DiffusionError> {
+    history_search::query(&query)
+}
+
+/// Entries made from the images of `history_id`, such as upscales
+#[tauri::command]
+async fn get_derivatives(history_id: String) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    history::derivatives(&history_id)
+}
+
+/// Upscales one image of a history entry (the first when `image` is not given)
+/// and returns the new entry, linked to the original. `cancel_generation` stops
+/// a model-based upscale.
+#[tauri::command]
+async fn upscale_image(
+    history_id: String,
+    image: Option<String>,
+    options: UpscaleOptions,
+) -> Result<HistoryEntry, DiffusionError> {
+    options.validate()?;
+    let entry = history::get(&history_id)?;
+    let source = entry.image(image)?;
+
+    {
+        let mut cancelled = GENERATION_CANCELLED.lock()
+            .map_err(|_| DiffusionError::lock("cancellation"))?;
+        *cancelled = false;
+    }
+
+    let settings = load_settings();
+    logging::with_job(
+        logging::new_job_id("upscale"),
+        post_processing::upscale_entry_image(&entry, &source, options, &settings),
+    )
+    .await
+}
+
+/// Generates variations of one image of a history entry (the first when
+/// `image` is not given) with its prompt, seed and model, each recorded as a
+/// derivative of the entry. `cancel_generation` stops the remaining ones.
+#[tauri::command]
+async fn generate_variations(
+    history_id: String,
+    image: Option<String>,
+    options: VariationOptions,
+) -> Result<Vec<ImageGenerationResponse>, DiffusionError> {
+    let entry = history::get(&history_id)?;
+    let source = entry.image(image)?;
+    let settings = get_settings().await?;
+    variations::generate_variations(&entry, &source, options, &settings).await
+}
+
+/// Runs a history entry's request again with the same model, warning when the
+/// model or LoRA files have changed since. With `compare` the new images are
+/// diffed against the old ones to catch nondeterminism.
+#[tauri::command]
+async fn regenerate_from_history(
+    history_id: String,
+    compare: Option<bool>,
+) -> Result<regeneration::Regeneration, DiffusionError> {
+    let entry = history::get(&history_id)?;
+    let settings = get_settings().await?;
+    regeneration::regenerate(&entry, &settings, compare.unwrap_or(false)).await
+}
+
+// Preset commands
+
+#[tauri::command]
+async fn list_presets() -> Result<Vec<Preset>, DiffusionError> {
+    presets::list()
+}
+
+/// Saves `preset`, replacing the one saved as `previous_name` (or under the
+/// same name), and returns it as stored
+#[tauri::command]
+async fn save_preset(preset: Preset, previous_name: Option<String>) -> Result<Preset, DiffusionError> {
+    presets::save(preset, previous_name.as_deref())
+}
+
+#[tauri::command]
+async fn delete_preset(name: String) -> Result<(), DiffusionError> {
+    presets::delete(&name)
+}
+
+/// `request` with the named presets merged in, in order, ready to generate
+#[tauri::command]
+async fn apply_presets(request: ImageGenerationRequest, names: Vec<String>) -> Result<ImageGenerationRequest, DiffusionError> {
+    presets::apply(request, &names)
+}
+
+/// Writes the named presets (all when `names` is empty) to a pack file
+#[tauri::command]
+async fn export_presets(names: Vec<String>, path: String) -> Result<usize, DiffusionError> {
+    presets::export_pack(&names, Path::new(&path))
+}
+
+#[tauri::command]
+async fn import_presets(path: String, replace: Option<bool>) -> Result<ImportSummary, DiffusionError> {
+    presets::import_pack(Path::new(&path), replace.unwrap_or(false))
+}
+
+// Prompt history commands
+
+/// Past prompts and wildcard names completing `input`, best first
+#[tauri::command]
+async fn suggest_prompts(input: String, limit: Option<usize>) -> Result<Vec<PromptSuggestion>, DiffusionError> {
+    let settings = load_settings();
+    prompt_history::suggest(
+        &input,
+        Path::new(&settings.wildcards_directory),
+        limit.unwrap_or(prompt_history::DEFAULT_SUGGESTIONS),
+    )
+}
+
+/// The most recently used prompts, with their use counts
+#[tauri::command]
+async fn get_prompt_history(limit: Option<usize>) -> Result<Vec<PromptRecord>, DiffusionError> {
+    prompt_history::recent(limit.unwrap_or(usize::MAX))
+}
+
+#[tauri::command]
+async fn forget_prompt(prompt: String) -> Result<bool, DiffusionError> {
+    prompt_history::forget(&prompt)
+}
+
+/// Imports the images in `options.folders` (the old DiffusionBee images folder
+/// and the Desktop by default) into the history and returns what was found.
+/// Thumbnails are made in the background afterwards; both stages report
+/// `import-progress` events.
+#[tauri::command]
+async fn import_images(app: tauri::AppHandle, options: Option<ImportOptions>) -> Result<ImportReport, DiffusionError> {
+    let options = options.unwrap_or_default();
+    let emitter = app.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        image_import::import(&options, |progress| emit_import_progress(&emitter, progress))
+    })
+    .await
+    .map_err(|e| DiffusionError::Internal(format!("Import failed: {}", e)))??;
+
+    let ids = report.entry_ids.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = image_import::make_thumbnails(&ids, |progress| emit_import_progress(&app, progress)) {
+            log::warn!("Failed to make thumbnails: {}", e);
+        }
+    });
+    Ok(report)
+}
+
+fn emit_import_progress(app: &tauri::AppHandle, progress: ImportProgress) {
+    if let Err(e) = app.emit("import-progress", progress) {
+        log::warn!("Failed to emit import progress: {}", e);
+    }
+}
+
+// Tag and collection commands
+
+/// Adds `tags` to the history entries `ids` and returns them updated
+#[tauri::command]
+async fn tag_entries(ids: Vec<String>, tags: Vec<String>) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    collections::add_tags(&ids, &tags)
+}
+
+#[tauri::command]
+async fn untag_entries(ids: Vec<String>, tags: Vec<String>) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    collections::remove_tags(&ids, &tags)
+}
+
+/// Every tag in use with its number of entries, the most used first
+#[tauri::command]
+async fn list_tags() -> Result<Vec<TagCount>, DiffusionError> {
+    collections::list_tags()
+}
+
+#[tauri::command]
+async fn set_favorite(ids: Vec<String>, favorite: bool) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    collections::set_favorite(&ids, favorite)
+}
+
+/// Sets 1 to 5 stars, or clears the rating when `rating` is null
+#[tauri::command]
+async fn set_rating(ids: Vec<String>, rating: Option<u8>) -> Result<Vec<HistoryEntry>, DiffusionError> {
+    collections::set_rating(&ids, rating)
+}
+
+#[tauri::command]
+async fn list_collections() -> Result<Vec<Collection>, DiffusionError> {
+    collections::list()
+}
+
+#[tauri::command]
+async fn create_collection(name: String, description: Option<String>) -> Result<Collection, DiffusionError> {
+    collections::create(&name, description.as_deref().unwrap_or_default())
+}
+
+/// Deletes the collection, leaving its entries in the history
+#[tauri::command]
+async fn delete_collection(name: String) -> Result<(), DiffusionError> {
+    collections::delete(&name)
+}
+
+#[tauri::command]
+async fn add_to_collection(name: String, ids: Vec<String>) -> Result<Collection, DiffusionError> {
+    collections::add_entries(&name, &ids)
+}
+
+#[tauri::command]
+async fn remove_from_collection(name: String, ids: Vec<String>) -> Result<Collection, DiffusionError> {
+    collections::remove_entries(&name, &ids)
+}
+
+/// Copies the collection's images and a manifest of their parameters into a
+/// new folder or zip inside `destination`
+#[tauri::command]
+async fn export_collection(
+    name: String,
+    destination: String,
+    format: Option<ExportFormat>,
+) -> Result<CollectionExport, DiffusionError> {
+    tokio::task::spawn_blocking(move || {
+        collections::export(&name, Path::new(&destination), format.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| DiffusionError::Internal(format!("Collection export failed: {}", e)))?
+}
+
+// Log and diagnostics commands
+
+/// Log entries matching `filter`, oldest first, for the log viewer and bug reports
+#[tauri::command]
+async fn get_logs(filter: Option<LogFilter>) -> Result<Vec<LogEntry>, DiffusionError> {
+    logging::read_logs(&filter.unwrap_or_default())
+}
+
+/// Zips logs, settings, models, the Python probe and system info into one file
+/// that can be attached to a bug report
+#[tauri::command]
+async fn export_diagnostics(options: Option<DiagnosticsOptions>) -> Result<DiagnosticsBundle, DiffusionError> {
+    diagnostics::export(&load_settings(), &options.unwrap_or_default()).await
+}
+
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
 fn greet(name: &str) -> String {
@@ -664,6 +1542,10 @@ fn greet(name: &str) -> String {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    logging::init();
+    // Before any threads exist, so the local time zone can be read
+    output_files::local_offset();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
@@ -671,11 +1553,67 @@ pub fn run() {
             generate_image,
             get_generation_progress,
             cancel_generation,
+            interpolate_video,
+            list_applets,
+            get_applet_form,
+            run_applet,
+            count_prompt_tokens,
             get_models,
             set_active_model,
+            get_validation_profile,
+            get_parameter_schema,
             get_settings,
-            save_settings
+            save_settings,
+            get_api_server_status,
+            probe_python_environments,
+            select_python_interpreter,
+            get_logs,
+            export_diagnostics,
+            get_history_entry,
+            get_derivatives,
+            query_history,
+            upscale_image,
+            generate_variations,
+            regenerate_from_history,
+            list_presets,
+            save_preset,
+            delete_preset,
+            apply_presets,
+            export_presets,
+            import_presets,
+            suggest_prompts,
+            get_prompt_history,
+            forget_prompt,
+            tag_entries,
+            untag_entries,
+            list_tags,
+            set_favorite,
+            set_rating,
+            list_collections,
+            create_collection,
+            delete_collection,
+            add_to_collection,
+            remove_from_collection,
+            export_collection,
+            import_images
         ])
+        .setup(|app| {
+            // Bundled builds ship the Python backend as a resource
+            match app.path().resource_dir() {
+                Ok(dir) => backend_locator::set_resource_dir(dir),
+                Err(e) => log::warn!("No resource directory: {}", e),
+            }
+
+            let settings = load_settings();
+            if settings.api_server_enabled {
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = api_server::apply_settings(&settings).await {
+                        log::error!("Failed to start API server: {}", e);
+                    }
+                });
+            }
+            Ok(())
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
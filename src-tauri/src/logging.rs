@@ -0,0 +1,350 @@
+// Application and backend logging
+//
+// Everything goes through the `log` crate into one JSON-lines file under
+// ~/.diffusionbee/logs, rotated by size, and is mirrored to stderr for terminal
+// runs. Each entry carries a UTC timestamp, its level and the id of the job it
+// belongs to, so `get_logs` can pull out everything one failed generation wrote,
+// including the backend's own stdout and stderr.
+//
+// The level for app entries comes from `DIFFUSIONBEE_LOG` (`error` to `trace`,
+// default `info`). Backend output is always written to the file, and only
+// echoed to the terminal at `debug` and above.
+
+use crate::DiffusionError;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub const LOG_LEVEL_ENV: &str = "DIFFUSIONBEE_LOG";
+pub const LOG_FILE_NAME: &str = "diffusionbee.log";
+
+// diffusionbee.log is rotated to diffusionbee.log.1 .. .5 at 5 MB
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+const KEPT_LOGS: usize = 5;
+
+// Entries returned by `get_logs` when no limit is given
+const DEFAULT_LOG_LIMIT: usize = 1000;
+
+tokio::task_local! {
+    static CURRENT_JOB: String;
+}
+
+static LOGGER: OnceLock<FileLogger> = OnceLock::new();
+static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogSource {
+    /// Logged by the Rust side
+    App,
+    /// A line the Python backend wrote
+    Backend,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// RFC 3339, UTC, millisecond precision
+    pub timestamp: String,
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+    pub source: LogSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<BackendStream>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    /// Module that logged the entry, `backend` for backend output
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogFilter {
+    /// Minimum severity, e.g. `warn` returns warnings and errors
+    pub level: Option<String>,
+    pub job_id: Option<String>,
+    pub source: Option<LogSource>,
+    /// RFC 3339 timestamps bounding the entries, inclusive
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Most recent entries to return, 1000 by default
+    pub limit: Option<usize>,
+}
+
+struct FileLogger {
+    level: LevelFilter,
+    file: Mutex<Option<LogFile>>,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+/// Installs the logger. Safe to call more than once; only the first call counts.
+pub fn init() {
+    let level = std::env::var(LOG_LEVEL_ENV)
+        .ok()
+        .and_then(|level| LevelFilter::from_str(level.trim()).ok())
+        .unwrap_or(LevelFilter::Info);
+
+    let logger = LOGGER.get_or_init(|| FileLogger {
+        level,
+        file: Mutex::new(log_dir().and_then(|dir| LogFile::open(&dir).ok())),
+    });
+    if log::set_logger(logger).is_ok() {
+        // Backend lines bypass the level, but other crates' records are only
+        // kept at warn and above (see `enabled`)
+        log::set_max_level(level.max(LevelFilter::Warn));
+    }
+}
+
+/// ~/.diffusionbee/logs
+pub fn log_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".diffusionbee").join("logs"))
+}
+
+/// The current log and its rotated predecessors that exist, oldest first
+pub fn log_files() -> Vec<PathBuf> {
+    let Some(dir) = log_dir() else {
+        return Vec::new();
+    };
+    (1..=KEPT_LOGS)
+        .rev()
+        .map(|n| dir.join(format!("{}.{}", LOG_FILE_NAME, n)))
+        .chain(std::iter::once(dir.join(LOG_FILE_NAME)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// A fresh id for a generation, interpolation or applet run, e.g. `gen-1760000000000-3`
+pub fn new_job_id(kind: &str) -> String {
+    let millis = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+    format!("{}-{}-{}", kind, millis, JOB_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Runs `future` with every entry it logs, including backend output, tagged with `job_id`
+pub async fn with_job<F: Future>(job_id: String, future: F) -> F::Output {
+    CURRENT_JOB.scope(job_id, future).await
+}
+
+/// The job the calling task is running, if any
+pub fn current_job() -> Option<String> {
+    CURRENT_JOB.try_with(|job| job.clone()).ok()
+}
+
+/// Records a line the Python backend wrote
+pub fn backend_output(stream: BackendStream, line: &str) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    let level = match stream {
+        BackendStream::Stdout => Level::Info,
+        BackendStream::Stderr => Level::Warn,
+    };
+    logger.write(LogEntry {
+        timestamp: now(),
+        level: level_name(level),
+        source: LogSource::Backend,
+        stream: Some(stream),
+        job_id: current_job(),
+        target: "backend".to_string(),
+        message: line.to_string(),
+    });
+}
+
+/// Entries from the log files matching `filter`, oldest first
+pub fn read_logs(filter: &LogFilter) -> Result<Vec<LogEntry>, DiffusionError> {
+    let min_level = match &filter.level {
+        Some(level) => Some(
+            Level::from_str(level.trim())
+                .map_err(|_| DiffusionError::validation("level", format!("Unknown log level: {}", level)))?,
+        ),
+        None => None,
+    };
+    let since = parse_bound("since", filter.since.as_deref())?;
+    let until = parse_bound("until", filter.until.as_deref())?;
+    let limit = filter.limit.unwrap_or(DEFAULT_LOG_LIMIT);
+
+    // Make sure everything logged so far is on disk
+    if let Some(logger) = LOGGER.get() {
+        logger.flush();
+    }
+
+    let mut entries = Vec::new();
+    for path in log_files() {
+        let file = File::open(&path)
+            .map_err(|e| DiffusionError::FileSystem(format!("Failed to open {}: {}", path.display(), e)))?;
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            // Lines cut short by a crash are skipped
+            let Ok(entry) = serde_json::from_str::<LogEntry>(&line) else {
+                continue;
+            };
+            if matches_filter(&entry, filter, min_level, since, until) {
+                entries.push(entry);
+            }
+        }
+    }
+
+    let skip = entries.len().saturating_sub(limit);
+    entries.drain(..skip);
+    Ok(entries)
+}
+
+fn matches_filter(
+    entry: &LogEntry,
+    filter: &LogFilter,
+    min_level: Option<Level>,
+    since: Option<OffsetDateTime>,
+    until: Option<OffsetDateTime>,
+) -> bool {
+    if let Some(min_level) = min_level {
+        // Level orders by verbosity, so Error < Warn < Info
+        match Level::from_str(&entry.level) {
+            Ok(level) if level <= min_level => {}
+            _ => return false,
+        }
+    }
+    if filter.job_id.as_ref().is_some_and(|job| entry.job_id.as_ref() != Some(job)) {
+        return false;
+    }
+    if filter.source.is_some_and(|source| source != entry.source) {
+        return false;
+    }
+    if since.is_some() || until.is_some() {
+        let Ok(timestamp) = OffsetDateTime::parse(&entry.timestamp, &Rfc3339) else {
+            return false;
+        };
+        if since.is_some_and(|since| timestamp < since) || until.is_some_and(|until| timestamp > until) {
+            return false;
+        }
+    }
+    true
+}
+
+fn parse_bound(field: &str, value: Option<&str>) -> Result<Option<OffsetDateTime>, DiffusionError> {
+    value
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| {
+            OffsetDateTime::parse(v.trim(), &Rfc3339)
+                .map_err(|e| DiffusionError::validation(field, format!("Invalid RFC 3339 timestamp '{}': {}", v, e)))
+        })
+        .transpose()
+}
+
+fn now() -> String {
+    let now = OffsetDateTime::now_utc();
+    // Truncated to milliseconds so entries line up with the frontend's Date
+    let now = now.replace_nanosecond(now.millisecond() as u32 * 1_000_000).unwrap_or(now);
+    now.format(&Rfc3339).unwrap_or_default()
+}
+
+fn level_name(level: Level) -> String {
+    level.as_str().to_ascii_lowercase()
+}
+
+impl FileLogger {
+    fn write(&self, entry: LogEntry) {
+        let to_terminal = entry.source == LogSource::App || self.level >= LevelFilter::Debug;
+        if to_terminal {
+            let job = entry.job_id.as_deref().map(|job| format!(" {}", job)).unwrap_or_default();
+            let label = match entry.stream {
+                Some(BackendStream::Stdout) => "BACKEND".to_string(),
+                Some(BackendStream::Stderr) => "BACKEND stderr".to_string(),
+                None => entry.level.to_ascii_uppercase(),
+            };
+            eprintln!("[{}{}] {}", label, job, entry.message);
+        }
+
+        let Ok(line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(log_file) = file.as_mut() {
+                // A full disk shouldn't take the app down with it
+                if log_file.append(&line).is_err() {
+                    *file = None;
+                }
+            }
+        }
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let ours = metadata.target().starts_with("ported_diffusion_app") || metadata.target().starts_with("diffusion_cli");
+        metadata.level() <= self.level && (ours || metadata.level() <= Level::Warn)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.write(LogEntry {
+            timestamp: now(),
+            level: level_name(record.level()),
+            source: LogSource::App,
+            stream: None,
+            job_id: current_job(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(log_file) = file.as_mut() {
+                let _ = log_file.file.flush();
+            }
+        }
+    }
+}
+
+impl LogFile {
+    fn open(dir: &Path) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOG_FILE_NAME);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn append(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > MAX_LOG_BYTES {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    // diffusionbee.log.4 -> .5, ..., diffusionbee.log -> .1, dropping the oldest
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let _ = fs::remove_file(numbered(KEPT_LOGS));
+        for n in (1..KEPT_LOGS).rev() {
+            let _ = fs::rename(numbered(n), numbered(n + 1));
+        }
+        fs::rename(&self.path, numbered(1))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+// In-process stand-in for the Python backend
+//
+// Speaks the same `sdbk` protocol as diffusionbee_backend.py: it announces the
+// model load, answers `b2py t2im` with working/progress/image lines, honours
+// `__stop__` and reports failures with a traceback on stderr followed by
+// `sdbk errr`. Images are gradients derived from the seed, so frontend work and
+// integration tests run without TensorFlow or a model.
+//
+// Enabled by the `mock_backend` setting or `DIFFUSIONBEE_MOCK_BACKEND=1`; the
+// timing and failure schedule come from environment variables (see
+// `MockSchedule::from_env`).
+
+use crate::backend_connection::{BackendConnection, BackendLine};
+use crate::AppSettings;
+use image::{Rgb, RgbImage};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+pub const MOCK_BACKEND_ENV: &str = "DIFFUSIONBEE_MOCK_BACKEND";
+
+// Jobs run by any mock connection, for the failure schedule
+static MOCK_JOBS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone)]
+pub struct MockSchedule {
+    /// Time spent "loading the model" before the backend reports ready
+    pub load_delay: Duration,
+    /// Time per denoising step
+    pub step_delay: Duration,
+    /// Every Nth job fails halfway through, 0 never fails
+    pub fail_every: u32,
+}
+
+impl Default for MockSchedule {
+    fn default() -> Self {
+        Self {
+            load_delay: Duration::from_millis(100),
+            step_delay: Duration::from_millis(50),
+            fail_every: 0,
+        }
+    }
+}
+
+impl MockSchedule {
+    /// Reads `DIFFUSIONBEE_MOCK_LOAD_MS`, `DIFFUSIONBEE_MOCK_STEP_MS` and
+    /// `DIFFUSIONBEE_MOCK_FAIL_EVERY`, keeping the defaults for unset values
+    pub fn from_env() -> Self {
+        let env_u64 = |name: &str| std::env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        let defaults = Self::default();
+        Self {
+            load_delay: env_u64("DIFFUSIONBEE_MOCK_LOAD_MS").map(Duration::from_millis).unwrap_or(defaults.load_delay),
+            step_delay: env_u64("DIFFUSIONBEE_MOCK_STEP_MS").map(Duration::from_millis).unwrap_or(defaults.step_delay),
+            fail_every: env_u64("DIFFUSIONBEE_MOCK_FAIL_EVERY").map(|n| n as u32).unwrap_or(defaults.fail_every),
+        }
+    }
+}
+
+/// Whether generation should use the mock. The environment variable wins over
+/// the setting so tests can force either mode.
+pub fn is_enabled(settings: &AppSettings) -> bool {
+    match std::env::var(MOCK_BACKEND_ENV) {
+        Ok(value) => matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        Err(_) => settings.mock_backend,
+    }
+}
+
+/// Where the mock saves images before they are moved to the output directory,
+/// like the real backend's own data folder
+pub fn images_dir() -> PathBuf {
+    std::env::temp_dir().join("diffusionbee_mock_images")
+}
+
+/// Starts a mock backend on its own thread. Images are written to `output_dir`.
+pub fn spawn(schedule: MockSchedule, output_dir: PathBuf) -> BackendConnection {
+    let (output, lines) = unbounded_channel();
+    let (input, requests) = mpsc::channel::<String>();
+
+    thread::spawn(move || {
+        let backend = MockBackend {
+            schedule,
+            output_dir,
+            output,
+            requests,
+        };
+        let _ = backend.serve();
+    });
+
+    BackendConnection::from_channel(lines, move |line| input.send(line.to_string()).is_ok())
+}
+
+struct MockBackend {
+    schedule: MockSchedule,
+    output_dir: PathBuf,
+    output: UnboundedSender<BackendLine>,
+    requests: Receiver<String>,
+}
+
+// Raised when the connection is dropped, which ends the mock like a killed process
+struct Disconnected;
+
+impl MockBackend {
+    fn serve(&self) -> Result<(), Disconnected> {
+        self.stdout("sdbk mltl Loading Model")?;
+        thread::sleep(self.schedule.load_delay);
+        self.stdout("sdbk mdld")?;
+
+        loop {
+            self.stdout("sdbk inrd")?;
+            let line = self.requests.recv().map_err(|_| Disconnected)?;
+            self.stdout(&format!("got {}", line))?;
+
+            if let Some(json) = line.strip_prefix("b2py t2im ") {
+                match serde_json::from_str::<Value>(json.trim()) {
+                    Ok(params) => {
+                        self.stdout("sdbk inwk")?;
+                        self.generate(&params)?;
+                    }
+                    Err(e) => self.fail(&format!("Invalid request: {}", e))?,
+                }
+            } else if let Some(rest) = line.strip_prefix("b2py rapp ") {
+                let applet = rest.split_whitespace().next().unwrap_or_default();
+                self.stdout("sdbk inwk")?;
+                self.fail(&format!("Applet '{}' is not available in the mock backend", applet))?;
+            }
+        }
+    }
+
+    fn generate(&self, params: &Value) -> Result<(), Disconnected> {
+        let job = MOCK_JOBS.fetch_add(1, Ordering::SeqCst) + 1;
+        let fails = self.schedule.fail_every > 0 && job.is_multiple_of(self.schedule.fail_every);
+
+        let num_imgs = params["num_imgs"].as_u64().unwrap_or(1).max(1);
+        // Only the keys SDRun has count, like get_sd_run_from_dict
+        let num_steps = params["num_steps"].as_u64().unwrap_or(25).max(1);
+        let width = params["img_width"].as_u64().unwrap_or(512) as u32;
+        let height = params["img_height"].as_u64().unwrap_or(512) as u32;
+        let seed = params["seed"].as_u64().unwrap_or(job as u64);
+        let variation = params["small_mod_seed"].as_u64().map(|small_mod_seed| {
+            (small_mod_seed, params["small_mod_strength"].as_f64().unwrap_or(0.01) as f32)
+        });
+        let prompt = params["prompt"].as_str().unwrap_or_default();
+
+        for img_id in 0..num_imgs {
+            self.stdout("sdbk gnms Starting")?;
+            self.stdout("sdbk dnpr -1")?;
+            for step in 0..num_steps {
+                if self.stop_requested()? {
+                    // Like the real backend: stopping abandons the job without an image
+                    return Ok(());
+                }
+                if fails && step == num_steps / 2 {
+                    self.stderr("Traceback (most recent call last):")?;
+                    self.stderr("  File \"mock_backend\", line 1, in generate")?;
+                    self.stderr(&format!("RuntimeError: Mock failure for job {}", job))?;
+                    return self.fail(&format!("Mock failure for job {}", job));
+                }
+                thread::sleep(self.schedule.step_delay);
+                self.stdout(&format!("sdbk dnpr {}", 100.0 * (step + 1) as f64 / num_steps as f64))?;
+            }
+            self.stdout("sdbk gnms Decoding")?;
+
+            let path = self.output_dir.join(format!(
+                "mock_{}_{}_{}.png",
+                seed,
+                img_id,
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0)
+            ));
+            // Like `prepare_seed`, later images offset the small_mod_seed when there is one
+            let offset = 1234 * img_id;
+            let (seed, variation) = match variation {
+                Some((small_mod_seed, strength)) => (seed, Some((small_mod_seed.wrapping_add(offset), strength))),
+                None => (seed.wrapping_add(offset), None),
+            };
+            match write_image(&path, width, height, seed, variation, prompt) {
+                Ok(()) => self.stdout(&format!("sdbk nwim {}", json!({ "generated_img_path": path })))?,
+                Err(e) => return self.fail(&format!("Failed to write mock image: {}", e)),
+            }
+        }
+        Ok(())
+    }
+
+    // Checks for `__stop__` without blocking, the way the backend's callback does
+    fn stop_requested(&self) -> Result<bool, Disconnected> {
+        loop {
+            match self.requests.try_recv() {
+                Ok(line) if line.contains("__stop__") => return Ok(true),
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(TryRecvError::Disconnected) => return Err(Disconnected),
+            }
+        }
+    }
+
+    fn fail(&self, message: &str) -> Result<(), Disconnected> {
+        self.stdout(&format!("sdbk errr {}", message))?;
+        self.stdout(&format!("py2b eror {}", message))
+    }
+
+    fn stdout(&self, line: &str) -> Result<(), Disconnected> {
+        self.output.send(BackendLine::Stdout(line.to_string())).map_err(|_| Disconnected)
+    }
+
+    fn stderr(&self, line: &str) -> Result<(), Disconnected> {
+        self.output.send(BackendLine::Stderr(line.to_string())).map_err(|_| Disconnected)
+    }
+}
+
+// A diagonal gradient whose colours depend on the seed and prompt. A variation
+// moves the colours towards those of its small_mod_seed by its strength.
+fn write_image(
+    path: &Path,
+    width: u32,
+    height: u32,
+    seed: u64,
+    variation: Option<(u64, f32)>,
+    prompt: &str,
+) -> image::ImageResult<()> {
+    let colours = |seed: u64| {
+        let hash = prompt
+            .bytes()
+            .fold(seed ^ 0x9e37_79b9_7f4a_7c15, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3));
+        [(hash & 0xff) as f32, ((hash >> 8) & 0xff) as f32, ((hash >> 16) & 0xff) as f32]
+    };
+    let mut base = colours(seed);
+    if let Some((small_mod_seed, strength)) = variation {
+        let other = colours(small_mod_seed);
+        for (c, o) in base.iter_mut().zip(other) {
+            *c += (o - *c) * strength;
+        }
+    }
+
+    let image = RgbImage::from_fn(width.max(1), height.max(1), |x, y| {
+        let t = (x + y) as f32 / (width + height).max(1) as f32;
+        let channel = |c: f32| (c * (1.0 - t) + (255.0 - c) * t) as u8;
+        Rgb([channel(base[0]), channel(base[1]), channel(base[2])])
+    });
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    image.save(path)
+}
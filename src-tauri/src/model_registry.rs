@@ -0,0 +1,241 @@
+// Imported models and their .tdict headers
+//
+// Models live in ~/.diffusionbee/imported_models as .tdict files written by
+// backends/model_converter/tdict.py. The header block at offset 64 holds the
+// format versions and the byte ranges of two JSON documents: the tensor table
+// (name -> shape, dtype, offsets) and the model metadata. Reading those is enough
+// to tell a broken or half-converted model from a good one without loading any
+// weights.
+//
+// History entries identify the model (and LoRAs) they were made with by the
+// SHA-256 of the file. Hashing a few GB takes seconds, so hashes are kept for
+// the rest of the run until the file's size or modification time changes.
+
+use crate::DiffusionError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub const MODEL_EXTENSION: &str = "tdict";
+
+// Constants from tdict.py
+const TDICT_MAGIC: u64 = 4346464;
+const BLOCK_MAGIC: u64 = 8030895855;
+const EXTRA_HEAD_POS: u64 = 64;
+const EXTRA_HEAD_LEN: usize = 20;
+// First bytes of models imported by the old protobuf-based format
+const OLD_FORMAT_PREFIX: [u8; 4] = [42, 10, 8, 42];
+// The converter reserves 10 MB for the tensor table
+const MAX_JSON_BYTES: u64 = 16 * 1024 * 1024;
+// Hex digits of the hash AUTOMATIC1111 shows as the model hash
+const SHORT_HASH_LEN: usize = 10;
+
+static FILE_HASHES: Mutex<BTreeMap<PathBuf, CachedHash>> = Mutex::new(BTreeMap::new());
+
+// A hash stays valid while the file's size and modification time do
+struct CachedHash {
+    stamp: (u64, Option<SystemTime>),
+    hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelEntry {
+    /// File name, as returned by `get_models`
+    pub name: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// Last modification time, RFC 3339
+    pub modified: Option<String>,
+    /// Whether this is the model in the `model_path` setting
+    pub active: bool,
+    /// Filled in by `read_headers`
+    #[serde(default)]
+    pub header: Option<TdictHeader>,
+    #[serde(default)]
+    pub header_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TdictHeader {
+    pub format_version: u64,
+    pub min_supported_version: u64,
+    pub ctdict_version: i64,
+    pub tensor_count: usize,
+    /// Number of tensors per dtype, e.g. `float16`
+    pub dtypes: BTreeMap<String, usize>,
+    pub parameter_count: u64,
+    /// The converter's model info, `{}` for most models
+    pub metadata: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct TensorInfo {
+    shape: Vec<u64>,
+    dtype: String,
+}
+
+/// ~/.diffusionbee/imported_models
+pub fn models_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".diffusionbee").join("imported_models"))
+}
+
+/// Every imported model, sorted by name, plus `active_model` if it lives
+/// elsewhere. Headers are not read; see `read_headers`.
+pub fn scan_models(active_model: &str) -> Vec<ModelEntry> {
+    let active_model = Path::new(active_model);
+    let mut models: Vec<ModelEntry> = models_dir()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some(MODEL_EXTENSION))
+        .filter_map(|path| model_entry(&path, active_model))
+        .collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if active_model.is_file() && !models.iter().any(|m| m.active) {
+        models.extend(model_entry(active_model, active_model));
+    }
+    models
+}
+
+/// Reads the header of each model, recording failures on the entry
+pub fn read_headers(models: &mut [ModelEntry]) {
+    for model in models {
+        match read_header(&model.path) {
+            Ok(header) => model.header = Some(header),
+            Err(e) => model.header_error = Some(e.message()),
+        }
+    }
+}
+
+fn model_entry(path: &Path, active_model: &Path) -> Option<ModelEntry> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(ModelEntry {
+        name: path.file_name()?.to_string_lossy().to_string(),
+        path: path.to_path_buf(),
+        size_bytes: metadata.len(),
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|time| OffsetDateTime::from(time).format(&Rfc3339).ok()),
+        active: same_file(path, active_model),
+        header: None,
+        header_error: None,
+    })
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Hex SHA-256 of the file at `path`. Reads the whole file the first time, so
+/// call it off the async workers.
+pub fn file_hash(path: &Path) -> Result<String, DiffusionError> {
+    let failed = |e: std::io::Error| DiffusionError::FileSystem(format!("Failed to hash {}: {}", path.display(), e));
+    let metadata = fs::metadata(path).map_err(failed)?;
+    let stamp = (metadata.len(), metadata.modified().ok());
+    if let Some(cached) = FILE_HASHES.lock().map_err(|_| DiffusionError::lock("file hashes"))?.get(path) {
+        if cached.stamp == stamp {
+            return Ok(cached.hash.clone());
+        }
+    }
+
+    let mut file = File::open(path).map_err(failed)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(failed)?;
+    let hash: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+
+    FILE_HASHES
+        .lock()
+        .map_err(|_| DiffusionError::lock("file hashes"))?
+        .insert(path.to_path_buf(), CachedHash { stamp, hash: hash.clone() });
+    Ok(hash)
+}
+
+/// The first digits of `hash`, enough to tell models apart in messages
+pub fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(SHORT_HASH_LEN)]
+}
+
+/// Parses the header and tensor table of a .tdict file
+pub fn read_header(path: &Path) -> Result<TdictHeader, DiffusionError> {
+    let invalid = |message: String| DiffusionError::FileSystem(format!("{}: {}", path.display(), message));
+
+    let mut file = File::open(path).map_err(|e| invalid(e.to_string()))?;
+    let file_len = file.metadata().map_err(|e| invalid(e.to_string()))?.len();
+
+    let mut prefix = [0u8; 4];
+    file.read_exact(&mut prefix).map_err(|e| invalid(e.to_string()))?;
+    if prefix == OLD_FORMAT_PREFIX {
+        return Err(invalid(
+            "imported with an older version of DiffusionBee, delete and re-import it".to_string(),
+        ));
+    }
+
+    // Every block starts with a 64 byte header: magic, length, data offset, ...
+    let block = read_u64s(&mut file, EXTRA_HEAD_POS, 8).map_err(|e| invalid(e.to_string()))?;
+    if block[0] != BLOCK_MAGIC || block[2] != EXTRA_HEAD_POS + 64 {
+        return Err(invalid("not a .tdict file".to_string()));
+    }
+    let head = read_u64s(&mut file, EXTRA_HEAD_POS + 64, EXTRA_HEAD_LEN).map_err(|e| invalid(e.to_string()))?;
+    if head[0] != TDICT_MAGIC {
+        return Err(invalid("bad .tdict magic number".to_string()));
+    }
+
+    let weights_json = read_json(&mut file, file_len, head[4], head[5]).map_err(invalid)?;
+    let metadata = read_json(&mut file, file_len, head[6], head[7]).map_err(invalid)?;
+    let tensors: BTreeMap<String, TensorInfo> =
+        serde_json::from_value(weights_json).map_err(|e| invalid(format!("unreadable tensor table: {}", e)))?;
+
+    let mut dtypes = BTreeMap::new();
+    for tensor in tensors.values() {
+        *dtypes.entry(tensor.dtype.clone()).or_insert(0) += 1;
+    }
+
+    Ok(TdictHeader {
+        format_version: head[1],
+        min_supported_version: head[2],
+        // -1 is written as u64::MAX
+        ctdict_version: head[3] as i64,
+        tensor_count: tensors.len(),
+        dtypes,
+        parameter_count: tensors.values().map(|t| t.shape.iter().product::<u64>()).sum(),
+        metadata,
+    })
+}
+
+fn read_u64s(file: &mut File, offset: u64, count: usize) -> std::io::Result<Vec<u64>> {
+    let mut bytes = vec![0u8; count * 8];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap_or_default()))
+        .collect())
+}
+
+fn read_json(file: &mut File, file_len: u64, start: u64, end: u64) -> Result<Value, String> {
+    if start > end || end > file_len || end - start > MAX_JSON_BYTES {
+        return Err(format!("header points outside the file ({}..{})", start, end));
+    }
+    if start == end {
+        return Ok(Value::Object(Default::default()));
+    }
+    let mut bytes = vec![0u8; (end - start) as usize];
+    file.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+    file.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&bytes).map_err(|e| format!("unreadable header JSON: {}", e))
+}
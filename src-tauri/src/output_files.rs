@@ -0,0 +1,404 @@
+// Final placement of generated images
+//
+// The backend saves into its own data folder under a name derived from the
+// prompt. Once a job finishes, each image is moved (or copied, with
+// `keep_backend_files`) into the output directory under a name built from the
+// `filename_template` setting, optionally inside a per-day and/or per-model
+// subfolder. Existing files are never overwritten: a free name is reserved by
+// appending `_1`, `_2`, ... before the file is put in place.
+//
+// Template tokens:
+//
+//   {date}       2024-05-01          {seed}    the generation seed
+//   {time}       13-45-07            {model}   model file name without extension
+//   {prompt}     the whole prompt    {index}   1 for the first image of a job
+//   {prompt:N}   its first N chars   {steps}   inference steps
+//
+// Dates use the local time zone when the platform lets us read it, UTC otherwise.
+
+use crate::DiffusionError;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use time::macros::format_description;
+use time::{OffsetDateTime, UtcOffset};
+
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{date}_{time}_{prompt:30}_{seed}";
+
+// Longest file stem a template may produce, well under common 255 byte limits
+const MAX_STEM_CHARS: usize = 150;
+// Give up after this many taken names rather than loop forever
+const MAX_COLLISIONS: u32 = 10_000;
+
+static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputSubfolders {
+    /// Everything directly in the output directory
+    #[default]
+    None,
+    /// <output>/2024-05-01/
+    Day,
+    /// <output>/<model>/
+    Model,
+    /// <output>/2024-05-01/<model>/
+    DayModel,
+}
+
+/// What a template can refer to for one image
+#[derive(Debug, Clone)]
+pub struct OutputContext {
+    pub timestamp: OffsetDateTime,
+    pub seed: Option<u32>,
+    pub model: String,
+    pub prompt: String,
+    pub index: u32,
+    pub steps: u32,
+}
+
+/// Remembers the local UTC offset. Reading it can fail once threads are
+/// running, so this is called at startup; later calls reuse the first answer.
+pub fn local_offset() -> UtcOffset {
+    *LOCAL_OFFSET.get_or_init(|| UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC))
+}
+
+pub fn local_now() -> OffsetDateTime {
+    OffsetDateTime::now_utc().to_offset(local_offset())
+}
+
+/// Checks that every token in `template` is known and that it can't escape
+/// the output folder
+pub fn validate_template(template: &str) -> Result<(), DiffusionError> {
+    let invalid = |message: String| DiffusionError::validation("filename_template", message);
+
+    if template.trim().is_empty() {
+        return Err(invalid("Filename template cannot be empty".to_string()));
+    }
+    if template.contains(['/', '\\']) {
+        return Err(invalid(
+            "Filename template cannot contain path separators, use output_subfolders instead".to_string(),
+        ));
+    }
+    for token in tokens(template).map_err(invalid)? {
+        parse_token(token).map_err(invalid)?;
+    }
+    Ok(())
+}
+
+/// The file stem for one image, without extension
+pub fn render_template(template: &str, context: &OutputContext) -> Result<String, DiffusionError> {
+    let invalid = |message: String| DiffusionError::validation("filename_template", message);
+
+    let mut stem = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        stem.push_str(&sanitize(&rest[..start]));
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| invalid(format!("Unclosed token in '{}'", template)))?;
+        let token = parse_token(&rest[start + 1..start + end]).map_err(invalid)?;
+        stem.push_str(&token.render(context));
+        rest = &rest[start + end + 1..];
+    }
+    stem.push_str(&sanitize(rest));
+
+    // A token ending in `_` next to a literal `_` would otherwise double up
+    let mut collapsed = String::with_capacity(stem.len());
+    for c in stem.chars() {
+        if !(c == '_' && collapsed.ends_with('_')) {
+            collapsed.push(c);
+        }
+    }
+    let stem: String = collapsed.trim_matches(['_', '-', '.', ' ']).chars().take(MAX_STEM_CHARS).collect();
+    Ok(if stem.is_empty() { "image".to_string() } else { stem })
+}
+
+/// The folder an image goes into
+pub fn output_folder(output_dir: &Path, subfolders: OutputSubfolders, context: &OutputContext) -> PathBuf {
+    let day = || format_date(context.timestamp);
+    let model = || {
+        let model = sanitize(&context.model);
+        if model.is_empty() { "unknown_model".to_string() } else { model }
+    };
+    match subfolders {
+        OutputSubfolders::None => output_dir.to_path_buf(),
+        OutputSubfolders::Day => output_dir.join(day()),
+        OutputSubfolders::Model => output_dir.join(model()),
+        OutputSubfolders::DayModel => output_dir.join(day()).join(model()),
+    }
+}
+
+/// Moves `source` to `<folder>/<stem>.<ext>`, or copies it when `keep_source`
+/// is set, picking a free name if that one is taken. Returns the final path.
+pub fn place_file(source: &Path, folder: &Path, stem: &str, keep_source: bool) -> Result<PathBuf, DiffusionError> {
+    let extension = source.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    fs::create_dir_all(folder)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to create {}: {}", folder.display(), e)))?;
+
+    let destination = reserve_path(folder, stem, &extension)?;
+    let placed = if keep_source {
+        fs::copy(source, &destination).map(|_| ())
+    } else {
+        // Across file systems a rename fails, so fall back to copy and delete
+        fs::rename(source, &destination).or_else(|_| {
+            fs::copy(source, &destination)?;
+            fs::remove_file(source)
+        })
+    };
+
+    placed.map(|_| destination.clone()).map_err(|e| {
+        let _ = fs::remove_file(&destination);
+        DiffusionError::FileSystem(format!(
+            "Failed to move {} to {}: {}",
+            source.display(),
+            destination.display(),
+            e
+        ))
+    })
+}
+
+// Creates an empty file at the first free `stem.ext`, `stem_1.ext`, ... so two
+// jobs finishing at once can't pick the same name
+fn reserve_path(folder: &Path, stem: &str, extension: &str) -> Result<PathBuf, DiffusionError> {
+    for attempt in 0..MAX_COLLISIONS {
+        let name = match (attempt, extension.is_empty()) {
+            (0, true) => stem.to_string(),
+            (0, false) => format!("{}.{}", stem, extension),
+            (n, true) => format!("{}_{}", stem, n),
+            (n, false) => format!("{}_{}.{}", stem, n, extension),
+        };
+        let path = folder.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(DiffusionError::FileSystem(format!("Failed to create {}: {}", path.display(), e)));
+            }
+        }
+    }
+    Err(DiffusionError::FileSystem(format!(
+        "No free file name for {} in {}",
+        stem,
+        folder.display()
+    )))
+}
+
+enum Token {
+    Date,
+    Time,
+    Seed,
+    Model,
+    Prompt(Option<usize>),
+    Index,
+    Steps,
+}
+
+impl Token {
+    fn render(&self, context: &OutputContext) -> String {
+        match self {
+            Token::Date => format_date(context.timestamp),
+            Token::Time => context
+                .timestamp
+                .format(format_description!("[hour]-[minute]-[second]"))
+                .unwrap_or_default(),
+            Token::Seed => context.seed.map(|s| s.to_string()).unwrap_or_else(|| "random".to_string()),
+            Token::Model => sanitize(&context.model),
+            Token::Prompt(limit) => {
+                let prompt = sanitize(&context.prompt);
+                match limit {
+                    Some(limit) => prompt.chars().take(*limit).collect::<String>().trim_end_matches('_').to_string(),
+                    None => prompt,
+                }
+            }
+            Token::Index => context.index.to_string(),
+            Token::Steps => context.steps.to_string(),
+        }
+    }
+}
+
+fn tokens(template: &str) -> Result<Vec<&str>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed token in '{}'", template))?;
+        tokens.push(&rest[start + 1..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    Ok(tokens)
+}
+
+fn parse_token(token: &str) -> Result<Token, String> {
+    let (name, argument) = match token.split_once(':') {
+        Some((name, argument)) => (name.trim(), Some(argument.trim())),
+        None => (token.trim(), None),
+    };
+    let token = match (name, argument) {
+        ("date", None) => Token::Date,
+        ("time", None) => Token::Time,
+        ("seed", None) => Token::Seed,
+        ("model", None) => Token::Model,
+        ("index", None) => Token::Index,
+        ("steps", None) => Token::Steps,
+        ("prompt", None) => Token::Prompt(None),
+        ("prompt", Some(limit)) => match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => Token::Prompt(Some(limit)),
+            _ => return Err(format!("Invalid prompt length in {{{}}}", token)),
+        },
+        _ => return Err(format!("Unknown token {{{}}}", token)),
+    };
+    Ok(token)
+}
+
+fn format_date(timestamp: OffsetDateTime) -> String {
+    timestamp
+        .format(format_description!("[year]-[month]-[day]"))
+        .unwrap_or_default()
+}
+
+/// Keeps letters, digits, `-` and `.`; runs of anything else become one `_`
+pub fn sanitize(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() || c == '-' || c == '.' {
+            out.push(c);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn context(prompt: &str) -> OutputContext {
+        OutputContext {
+            timestamp: datetime!(2024-05-01 13:45:07 UTC),
+            seed: Some(42),
+            model: "sd-v1.5 (pruned)".to_string(),
+            prompt: prompt.to_string(),
+            index: 1,
+            steps: 25,
+        }
+    }
+
+    fn render(template: &str, prompt: &str) -> String {
+        render_template(template, &context(prompt)).unwrap()
+    }
+
+    // A fresh folder per test, so tests running in parallel don't share files
+    fn folder(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("diffusionbee_output_files_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn renders_every_token() {
+        assert_eq!(
+            render("{date}_{time}_{seed}_{model}_{index}_{steps}_{prompt}", "a fox"),
+            "2024-05-01_13-45-07_42_sd-v1.5_pruned_1_25_a_fox"
+        );
+        assert_eq!(
+            render_template("{seed}", &OutputContext { seed: None, ..context("") }).unwrap(),
+            "random"
+        );
+    }
+
+    #[test]
+    fn prompt_length_limit() {
+        assert_eq!(render("{prompt:12}", "a lighthouse at dusk"), "a_lighthouse");
+        // Cut right after a separator, which is dropped rather than kept dangling
+        assert_eq!(render("{prompt:2}_{seed}", "a lighthouse"), "a_42");
+        assert_eq!(render("{ prompt : 100 }", "short"), "short");
+        // Counted in characters, not bytes
+        assert_eq!(render("{prompt:3}", "été à Paris"), "été");
+    }
+
+    #[test]
+    fn names_are_sanitised() {
+        assert_eq!(render("{prompt}", "a/b\\c: <fox>?*"), "a_b_c_fox");
+        assert_eq!(render("{prompt}", "../../etc/passwd"), "etc_passwd");
+        assert_eq!(render("__{prompt}__", "  !!  "), "image");
+        assert_eq!(render("img {seed}", ""), "img_42");
+        assert_eq!(render("{prompt}", &"word ".repeat(100)).chars().count(), MAX_STEM_CHARS);
+        assert_eq!(sanitize("café, 16:9 (v2)"), "café_16_9_v2_");
+    }
+
+    #[test]
+    fn templates_are_validated() {
+        assert!(validate_template(DEFAULT_FILENAME_TEMPLATE).is_ok());
+        for (template, message) in [
+            ("{date}_{colour}", "Unknown token {colour}"),
+            ("{prompt:0}", "Invalid prompt length in {prompt:0}"),
+            ("{prompt:many}", "Invalid prompt length in {prompt:many}"),
+            ("{seed:3}", "Unknown token {seed:3}"),
+            ("{date", "Unclosed token in '{date'"),
+            ("  ", "Filename template cannot be empty"),
+            ("{date}/{seed}", "Filename template cannot contain path separators, use output_subfolders instead"),
+            ("..\\{seed}", "Filename template cannot contain path separators, use output_subfolders instead"),
+        ] {
+            match validate_template(template) {
+                Err(DiffusionError::Validation { field, message: actual }) => {
+                    assert_eq!(field.as_deref(), Some("filename_template"));
+                    assert_eq!(actual, message, "{:?}", template);
+                }
+                other => panic!("{:?} gave {:?}", template, other),
+            }
+        }
+        assert!(render_template("{nope}", &context("")).is_err());
+    }
+
+    #[test]
+    fn subfolders() {
+        let out = Path::new("out");
+        let context = context("");
+        assert_eq!(output_folder(out, OutputSubfolders::None, &context), out);
+        assert_eq!(output_folder(out, OutputSubfolders::Day, &context), out.join("2024-05-01"));
+        assert_eq!(output_folder(out, OutputSubfolders::Model, &context), out.join("sd-v1.5_pruned_"));
+        assert_eq!(
+            output_folder(out, OutputSubfolders::DayModel, &OutputContext { model: "/".to_string(), ..context }),
+            out.join("2024-05-01").join("_")
+        );
+    }
+
+    #[test]
+    fn taken_names_get_a_suffix() {
+        let dir = folder("collisions");
+        let source_dir = dir.join("backend");
+        fs::create_dir_all(&source_dir).unwrap();
+
+        let mut placed = Vec::new();
+        for i in 0..3 {
+            let source = source_dir.join(format!("{}.png", i));
+            fs::write(&source, format!("image {}", i)).unwrap();
+            placed.push(place_file(&source, &dir.join("out"), "fox", false).unwrap());
+            assert!(!source.exists());
+        }
+        let names: Vec<String> = placed
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["fox.png", "fox_1.png", "fox_2.png"]);
+        // Nothing was overwritten
+        for (i, path) in placed.iter().enumerate() {
+            assert_eq!(fs::read_to_string(path).unwrap(), format!("image {}", i));
+        }
+
+        // Copies keep the source
+        let source = source_dir.join("kept.png");
+        fs::write(&source, "kept").unwrap();
+        let copy = place_file(&source, &dir.join("out"), "fox", true).unwrap();
+        assert_eq!(copy.file_name().unwrap(), "fox_3.png");
+        assert!(source.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
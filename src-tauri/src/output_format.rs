@@ -0,0 +1,344 @@
+// Encoding finished images as PNG, JPEG or WebP
+//
+// The backend always writes PNG. When an image is finalized it is re-encoded in
+// the `output_format` setting with the generation parameters embedded (see
+// `image_metadata`): PNG text chunks, or EXIF and XMP for JPEG and WebP. JPEG
+// and lossy WebP use `output_quality`; `webp_lossless` keeps WebP pixel-exact.
+
+use crate::image_metadata::{self, PARAMETERS_KEYWORD, XMP_KEYWORD};
+use crate::DiffusionError;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageEncoder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_QUALITY: u8 = 90;
+
+// JPEG APP1 XMP segment identifier
+const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    pub format: OutputFormat,
+    /// 1-100, for JPEG and lossy WebP
+    pub quality: u8,
+    pub lossless: bool,
+}
+
+pub fn validate_quality(quality: u8) -> Result<(), DiffusionError> {
+    if !(1..=100).contains(&quality) {
+        return Err(DiffusionError::validation("output_quality", "Output quality must be between 1 and 100"));
+    }
+    Ok(())
+}
+
+/// Encodes `image` with `parameters` embedded when given
+pub fn encode(image: &DynamicImage, options: EncodeOptions, parameters: Option<&str>) -> Result<Vec<u8>, DiffusionError> {
+    let encode_error = |e: String| DiffusionError::Internal(format!("Failed to encode {:?}: {}", options.format, e));
+    match options.format {
+        OutputFormat::Png => encode_png(image, parameters).map_err(|e| encode_error(e.to_string())),
+        OutputFormat::Jpeg => encode_jpeg(image, options.quality, parameters).map_err(|e| encode_error(e.to_string())),
+        OutputFormat::Webp => encode_webp(image, options, parameters).map_err(encode_error),
+    }
+}
+
+/// Re-encodes the image at `source` into a new file next to it, named
+/// `<stem>.final.<ext>`, and returns that path. `source` is left alone.
+pub fn convert_file(source: &Path, options: EncodeOptions, parameters: Option<&str>) -> Result<PathBuf, DiffusionError> {
+    let image = image::open(source)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", source.display(), e)))?;
+    let bytes = encode(&image, options, parameters)?;
+
+    let stem = source.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let destination = source.with_file_name(format!("{}.final.{}", stem, options.format.extension()));
+    fs::write(&destination, bytes)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", destination.display(), e)))?;
+    Ok(destination)
+}
+
+fn encode_png(image: &DynamicImage, parameters: Option<&str>) -> Result<Vec<u8>, png::EncodingError> {
+    let (color, pixels) = match image {
+        DynamicImage::ImageRgba8(rgba) => (png::ColorType::Rgba, rgba.as_raw().clone()),
+        image => (png::ColorType::Rgb, image.to_rgb8().into_raw()),
+    };
+
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    if let Some(parameters) = parameters {
+        // tEXt is Latin-1 only; readers of `parameters` accept iTXt too
+        if parameters.chars().all(|c| (c as u32) < 0x100) {
+            encoder.add_text_chunk(PARAMETERS_KEYWORD.to_string(), parameters.to_string())?;
+        } else {
+            encoder.add_itxt_chunk(PARAMETERS_KEYWORD.to_string(), parameters.to_string())?;
+        }
+        encoder.add_itxt_chunk(XMP_KEYWORD.to_string(), image_metadata::xmp_packet(parameters))?;
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(bytes)
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8, parameters: Option<&str>) -> image::ImageResult<Vec<u8>> {
+    let rgb = image.to_rgb8();
+    let mut bytes = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut bytes, quality);
+    if let Some(parameters) = parameters {
+        encoder
+            .set_exif_metadata(image_metadata::exif_block(parameters))
+            .map_err(image::ImageError::Unsupported)?;
+    }
+    encoder.write_image(rgb.as_raw(), rgb.width(), rgb.height(), image::ExtendedColorType::Rgb8)?;
+
+    if let Some(parameters) = parameters {
+        let mut xmp = XMP_NAMESPACE.to_vec();
+        xmp.extend_from_slice(image_metadata::xmp_packet(parameters).as_bytes());
+        bytes = insert_jpeg_segment(&bytes, 0xE1, &xmp);
+    }
+    Ok(bytes)
+}
+
+// Inserts a segment after the leading APPn segments (JFIF, EXIF) that follow SOI
+fn insert_jpeg_segment(jpeg: &[u8], marker: u8, contents: &[u8]) -> Vec<u8> {
+    let mut position = 2;
+    while position + 4 <= jpeg.len() && jpeg[position] == 0xFF && (0xE0..=0xEF).contains(&jpeg[position + 1]) {
+        let length = u16::from_be_bytes([jpeg[position + 2], jpeg[position + 3]]) as usize;
+        position += 2 + length;
+    }
+    let position = position.min(jpeg.len());
+
+    let mut out = Vec::with_capacity(jpeg.len() + contents.len() + 4);
+    out.extend_from_slice(&jpeg[..position]);
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&((contents.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(contents);
+    out.extend_from_slice(&jpeg[position..]);
+    out
+}
+
+fn encode_webp(image: &DynamicImage, options: EncodeOptions, parameters: Option<&str>) -> Result<Vec<u8>, String> {
+    let has_alpha = image.color().has_alpha();
+    let pixels = if has_alpha { image.to_rgba8().into_raw() } else { image.to_rgb8().into_raw() };
+    let encoder = if has_alpha {
+        webp::Encoder::from_rgba(&pixels, image.width(), image.height())
+    } else {
+        webp::Encoder::from_rgb(&pixels, image.width(), image.height())
+    };
+    let encoded = if options.lossless {
+        encoder.encode_lossless()
+    } else {
+        encoder.encode(options.quality as f32)
+    };
+
+    match parameters {
+        Some(parameters) => add_webp_metadata(
+            &encoded,
+            image.width(),
+            image.height(),
+            has_alpha,
+            &image_metadata::exif_block(parameters),
+            image_metadata::xmp_packet(parameters).as_bytes(),
+        ),
+        None => Ok(encoded.to_vec()),
+    }
+}
+
+// Metadata needs the extended layout: a VP8X header with the EXIF and XMP flags
+// set, the image chunks, then the EXIF and XMP chunks. libwebp writes a simple
+// file (RIFF, WEBP, one VP8/VP8L chunk) for most images, which gets a new VP8X
+// header, but lossy images with alpha come back extended already (VP8X, ALPH,
+// VP8), and then only the flags of their VP8X change.
+fn add_webp_metadata(
+    webp: &[u8],
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+    exif: &[u8],
+    xmp: &[u8],
+) -> Result<Vec<u8>, String> {
+    if webp.len() < 20 || &webp[0..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err("libwebp returned an unexpected container".to_string());
+    }
+    const EXIF_AND_XMP: u8 = 0x08 | 0x04;
+
+    let mut body = b"WEBP".to_vec();
+    if &webp[12..16] == b"VP8X" {
+        if webp.len() < 30 {
+            return Err("libwebp returned a truncated VP8X header".to_string());
+        }
+        body.extend_from_slice(&webp[12..]);
+        // Flags are the first byte of the VP8X payload, after "WEBP" and the chunk header
+        body[12] |= EXIF_AND_XMP;
+    } else {
+        let mut flags = EXIF_AND_XMP;
+        if has_alpha {
+            flags |= 0x10;
+        }
+        let mut vp8x = vec![flags, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        riff_chunk(&mut body, b"VP8X", &vp8x);
+        body.extend_from_slice(&webp[12..]);
+    }
+    riff_chunk(&mut body, b"EXIF", exif);
+    riff_chunk(&mut body, b"XMP ", xmp);
+
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn riff_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    // Chunks are padded to an even size
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_import;
+    use crate::image_metadata::GenerationMetadata;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    const PARAMETERS: &str = "a café at dusk, (warm light:1.2)\nNegative prompt: blurry, text\n\
+        Steps: 25, CFG scale: 7.5, Seed: 1234, Size: 64x48, Model: sd-v1-5, Version: DiffusionBee 0.1.0";
+
+    fn rgb() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, 128])))
+    }
+
+    fn rgba() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 48, |x, y| {
+            Rgba([(x * 4) as u8, (y * 5) as u8, 128, if x < 32 { 255 } else { 64 }])
+        }))
+    }
+
+    fn options(format: OutputFormat, lossless: bool) -> EncodeOptions {
+        EncodeOptions {
+            format,
+            quality: DEFAULT_QUALITY,
+            lossless,
+        }
+    }
+
+    // A fresh folder per test, so tests running in parallel don't share files
+    fn folder(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("diffusionbee_output_format_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn quality_must_be_1_to_100() {
+        assert!(validate_quality(1).is_ok());
+        assert!(validate_quality(100).is_ok());
+        for quality in [0, 101, 255] {
+            match validate_quality(quality) {
+                Err(DiffusionError::Validation { field, .. }) => assert_eq!(field.as_deref(), Some("output_quality")),
+                other => panic!("quality {} gave {:?}", quality, other),
+            }
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips_parameters() {
+        let dir = folder("round_trip");
+        let cases = [
+            (OutputFormat::Png, false),
+            (OutputFormat::Jpeg, false),
+            (OutputFormat::Webp, false),
+            (OutputFormat::Webp, true),
+        ];
+        for (format, lossless) in cases {
+            for (kind, image) in [("rgb", rgb()), ("rgba", rgba())] {
+                let label = format!("{:?} lossless={} {}", format, lossless, kind);
+                let source = dir.join(format!("{}_{}_{}.png", format.extension(), lossless, kind));
+                image.save(&source).unwrap();
+
+                // The way finished images are made: the backend's PNG converted next to it
+                let path = convert_file(&source, options(format, lossless), Some(PARAMETERS)).unwrap();
+                assert_eq!(path.extension().unwrap(), format.extension(), "{}", label);
+
+                let decoded = image::open(&path).unwrap_or_else(|e| panic!("{}: {}", label, e));
+                assert_eq!((decoded.width(), decoded.height()), (64, 48), "{}", label);
+                if format == OutputFormat::Png || lossless {
+                    assert_eq!(decoded.to_rgba8(), image.to_rgba8(), "{} is not pixel exact", label);
+                }
+
+                let text = image_metadata::read_parameters(&path).unwrap();
+                assert_eq!(text.as_deref(), Some(PARAMETERS), "{}", label);
+                let metadata = GenerationMetadata::from_parameters(&text.unwrap());
+                assert_eq!(metadata.prompt, "a café at dusk, (warm light:1.2)", "{}", label);
+                assert_eq!(metadata.seed, Some(1234), "{}", label);
+
+                // Thumbnails for the history are read back from the final file
+                let thumbnail = path.with_extension("thumb.jpg");
+                image_import::write_thumbnail(&path, &thumbnail).unwrap_or_else(|e| panic!("{}: {:?}", label, e));
+                assert!(image::open(&thumbnail).is_ok(), "{}", label);
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn no_parameters_means_no_metadata() {
+        let dir = folder("no_metadata");
+        for format in [OutputFormat::Png, OutputFormat::Jpeg, OutputFormat::Webp] {
+            let path = dir.join(format!("plain.{}", format.extension()));
+            fs::write(&path, encode(&rgb(), options(format, false), None).unwrap()).unwrap();
+            assert_eq!(image_metadata::read_parameters(&path).unwrap(), None, "{:?}", format);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn webp_with_metadata_has_one_vp8x_header() {
+        for (image, lossless) in [(rgb(), false), (rgba(), false), (rgba(), true)] {
+            let bytes = encode(&image, options(OutputFormat::Webp, lossless), Some(PARAMETERS)).unwrap();
+            let mut chunks = Vec::new();
+            let mut position = 12;
+            while position + 8 <= bytes.len() {
+                let size = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap()) as usize;
+                chunks.push(String::from_utf8_lossy(&bytes[position..position + 4]).to_string());
+                position += 8 + size + size % 2;
+            }
+            assert_eq!(position, bytes.len());
+            assert_eq!(chunks.first().map(String::as_str), Some("VP8X"), "{:?}", chunks);
+            assert_eq!(chunks.iter().filter(|c| *c == "VP8X").count(), 1, "{:?}", chunks);
+            assert_eq!(chunks[chunks.len() - 2..], ["EXIF", "XMP "]);
+            // EXIF and XMP flags set, alpha flag matching the image
+            let flags = bytes[20];
+            assert_eq!(flags & 0x0C, 0x0C);
+            assert_eq!(flags & 0x10 != 0, image.color().has_alpha());
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// The rules for generation parameters, in one place
+//
+// `ImageGenerationRequest::validate` and `save_settings` both check their
+// values against a `ParameterSchema` built from the active validation profile,
+// and `get_parameter_schema` hands the same schema to the frontend so its
+// sliders and inputs use the exact min, max, step and default the backend
+// enforces. For widths and heights the step is a rule (the UNet's multiple);
+// for the other parameters it is only the slider increment.
+
+use crate::validation_profile::{AspectPreset, ModelFamily, ValidationProfile};
+use crate::DiffusionError;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Width,
+    Height,
+    Images,
+    Steps,
+    Guidance,
+}
+
+impl Parameter {
+    /// Field name in `ImageGenerationRequest`
+    pub fn request_field(&self) -> &'static str {
+        match self {
+            Parameter::Width => "img_width",
+            Parameter::Height => "img_height",
+            Parameter::Images => "num_imgs",
+            Parameter::Steps => "num_inference_steps",
+            Parameter::Guidance => "guidance_scale",
+        }
+    }
+
+    /// Field name of the default in `AppSettings`, if there is one
+    pub fn settings_field(&self) -> Option<&'static str> {
+        match self {
+            Parameter::Width => Some("default_width"),
+            Parameter::Height => Some("default_height"),
+            Parameter::Images => None,
+            Parameter::Steps => Some("default_inference_steps"),
+            Parameter::Guidance => Some("default_guidance_scale"),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Parameter::Width => "Width",
+            Parameter::Height => "Height",
+            Parameter::Images => "Number of images",
+            Parameter::Steps => "Inference steps",
+            Parameter::Guidance => "Guidance scale",
+        }
+    }
+
+    fn is_size(&self) -> bool {
+        matches!(self, Parameter::Width | Parameter::Height)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ParameterSpec {
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    pub default: f64,
+    /// Whole numbers only
+    pub integer: bool,
+    /// Values must be multiples of `step`, not just within range
+    pub step_enforced: bool,
+}
+
+impl ParameterSpec {
+    fn integer(min: u32, max: u32, step: u32, default: u32, step_enforced: bool) -> Self {
+        Self {
+            min: min as f64,
+            max: max as f64,
+            step: step as f64,
+            default: default.clamp(min, max.max(min)) as f64,
+            integer: true,
+            step_enforced,
+        }
+    }
+
+    fn format(&self, value: f64) -> String {
+        if self.integer {
+            format!("{}", value as i64)
+        } else {
+            format!("{:.1}", value)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParameterSchema {
+    pub family: ModelFamily,
+    pub img_width: ParameterSpec,
+    pub img_height: ParameterSpec,
+    pub num_imgs: ParameterSpec,
+    pub num_inference_steps: ParameterSpec,
+    pub guidance_scale: ParameterSpec,
+    pub aspect_presets: Vec<AspectPreset>,
+}
+
+impl ParameterSchema {
+    pub fn from_profile(profile: &ValidationProfile) -> Self {
+        let size = |default: u32| {
+            ParameterSpec::integer(profile.min_size, profile.max_size, profile.size_multiple, default, true)
+        };
+        Self {
+            family: profile.family,
+            img_width: size(profile.default_width),
+            img_height: size(profile.default_height),
+            num_imgs: ParameterSpec::integer(1, profile.max_images, 1, 1, false),
+            num_inference_steps: ParameterSpec::integer(
+                profile.min_steps,
+                profile.max_steps,
+                1,
+                profile.default_steps,
+                false,
+            ),
+            guidance_scale: ParameterSpec {
+                min: profile.min_guidance as f64,
+                max: profile.max_guidance as f64,
+                step: 0.5,
+                default: profile.default_guidance.clamp(profile.min_guidance, profile.max_guidance) as f64,
+                integer: false,
+                step_enforced: false,
+            },
+            aspect_presets: profile.aspect_presets.clone(),
+        }
+    }
+
+    pub fn spec(&self, parameter: Parameter) -> &ParameterSpec {
+        match parameter {
+            Parameter::Width => &self.img_width,
+            Parameter::Height => &self.img_height,
+            Parameter::Images => &self.num_imgs,
+            Parameter::Steps => &self.num_inference_steps,
+            Parameter::Guidance => &self.guidance_scale,
+        }
+    }
+
+    /// Checks a request value
+    pub fn check(&self, parameter: Parameter, value: f64) -> Result<(), DiffusionError> {
+        self.check_field(parameter, parameter.request_field(), parameter.label(), value)
+    }
+
+    /// Checks the settings default for `parameter`
+    pub fn check_default(&self, parameter: Parameter, value: f64) -> Result<(), DiffusionError> {
+        let field = parameter.settings_field().unwrap_or(parameter.request_field());
+        let label = format!("Default {}", parameter.label().to_lowercase());
+        self.check_field(parameter, field, &label, value)
+    }
+
+    fn check_field(&self, parameter: Parameter, field: &str, label: &str, value: f64) -> Result<(), DiffusionError> {
+        let spec = self.spec(parameter);
+        // Sizes depend on the family, so say which one the limits are for
+        let family = if parameter.is_size() {
+            format!(" for {} models", self.family.display_name())
+        } else {
+            String::new()
+        };
+
+        if !(spec.min..=spec.max).contains(&value) {
+            return Err(DiffusionError::validation(
+                field,
+                format!("{} must be between {} and {}{}", label, spec.format(spec.min), spec.format(spec.max), family),
+            ));
+        }
+        if spec.step_enforced && (value / spec.step).fract() != 0.0 {
+            return Err(DiffusionError::validation(
+                field,
+                format!("{} must be a multiple of {}{}", label, spec.format(spec.step), family),
+            ));
+        }
+        Ok(())
+    }
+}
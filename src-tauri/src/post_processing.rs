@@ -0,0 +1,227 @@
+// Post-processing of finished images
+//
+// After a job's images are saved, the stages in the `post_processing` setting
+// run on each of them in order, each stage working on the previous one's
+// result. Every result is saved next to its source and recorded in the history
+// as a derivative of the entry it came from. `upscale_image` runs a single
+// stage on demand.
+//
+// The only stage so far is upscaling, by one of:
+//
+//   lanczos  Lanczos resampling in Rust, always available
+//   model    a model-based upscaler applet in the Python backend
+//            (`upscaler_applet`, given `upscaler_model_path`)
+//
+// Upscaler models work at a fixed factor, usually 4x, so their output is
+// resampled to the requested scale afterwards.
+
+use crate::history::{self, DerivationKind, HistoryEntry};
+use crate::{applets, logging, mock_backend, output_files, output_format, AppSettings, DiffusionError};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+pub const MAX_UPSCALE: f32 = 4.0;
+pub const DEFAULT_UPSCALER_APPLET: &str = "model_upscale";
+
+// Largest side an upscaled image may have
+const MAX_OUTPUT_SIDE: u32 = 8192;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpscaleMethod {
+    #[default]
+    Lanczos,
+    Model,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct UpscaleOptions {
+    /// Factor for both sides, above 1 and at most `MAX_UPSCALE`
+    pub scale: f32,
+    #[serde(default)]
+    pub method: UpscaleMethod,
+}
+
+/// One step of the pipeline, e.g. `{"stage": "upscale", "scale": 2}`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum PostProcessStage {
+    Upscale(UpscaleOptions),
+}
+
+impl UpscaleOptions {
+    pub fn validate(&self) -> Result<(), DiffusionError> {
+        if !(self.scale > 1.0 && self.scale <= MAX_UPSCALE) {
+            return Err(DiffusionError::validation(
+                "scale",
+                format!("Upscale factor must be above 1 and at most {}", MAX_UPSCALE),
+            ));
+        }
+        Ok(())
+    }
+
+    fn target_size(&self, width: u32, height: u32) -> Result<(u32, u32), DiffusionError> {
+        let target = |side: u32| (side as f32 * self.scale).round() as u32;
+        let (width, height) = (target(width), target(height));
+        if width > MAX_OUTPUT_SIDE || height > MAX_OUTPUT_SIDE {
+            return Err(DiffusionError::validation(
+                "scale",
+                format!("Upscaled image would be {}x{}, more than {} pixels on a side", width, height, MAX_OUTPUT_SIDE),
+            ));
+        }
+        Ok((width, height))
+    }
+
+    // `_2x`, `_1.5x`
+    fn suffix(&self) -> String {
+        format!("_{}x", self.scale)
+    }
+}
+
+/// Checks the `post_processing` and upscaler settings
+pub fn validate_settings(settings: &AppSettings) -> Result<(), DiffusionError> {
+    for stage in &settings.post_processing {
+        match stage {
+            PostProcessStage::Upscale(options) => options
+                .validate()
+                .map_err(|e| DiffusionError::validation("post_processing", e.message()))?,
+        }
+    }
+    if settings.upscaler_applet.trim().is_empty() {
+        return Err(DiffusionError::validation("upscaler_applet", "Upscaler applet cannot be empty"));
+    }
+    if !settings.upscaler_model_path.is_empty() && !Path::new(&settings.upscaler_model_path).exists() {
+        return Err(DiffusionError::validation(
+            "upscaler_model_path",
+            format!("Upscaler model not found at: {}", settings.upscaler_model_path),
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the `post_processing` stages on every image of `entry` and returns the
+/// derivative entries, in order. An image whose stages fail is skipped and the
+/// failure returned alongside, so one bad image doesn't lose the others.
+pub async fn run_pipeline(entry: &HistoryEntry, settings: &AppSettings) -> (Vec<HistoryEntry>, Vec<DiffusionError>) {
+    let mut derived = Vec::new();
+    let mut errors = Vec::new();
+    for image in &entry.images {
+        let mut parent = entry.clone();
+        let mut source = image.clone();
+        for stage in &settings.post_processing {
+            let result = match stage {
+                PostProcessStage::Upscale(options) => upscale_entry_image(&parent, &source, *options, settings).await,
+            };
+            match result {
+                Ok(entry) => {
+                    source = entry.images.first().cloned().unwrap_or_default();
+                    parent = entry.clone();
+                    derived.push(entry);
+                }
+                Err(e) => {
+                    log::warn!("Post-processing {} failed: {}", source, e);
+                    errors.push(e);
+                    break;
+                }
+            }
+        }
+    }
+    (derived, errors)
+}
+
+/// Upscales `source`, one of `entry`'s images, saves the result next to it in
+/// the output format and records it as a derivative of `entry`
+pub async fn upscale_entry_image(
+    entry: &HistoryEntry,
+    source: &str,
+    options: UpscaleOptions,
+    settings: &AppSettings,
+) -> Result<HistoryEntry, DiffusionError> {
+    let source_path = Path::new(source);
+    log::info!("Upscaling {} {}x with {:?}", source_path.display(), options.scale, options.method);
+    let image = upscale(source_path, options, settings).await?;
+
+    let parameters = crate::embedded_parameters(&entry.request, &entry.model, settings);
+    let encode_options = settings.encode_options();
+    let bytes = output_format::encode(&image, encode_options, parameters.as_deref())?;
+
+    let folder = source_path.parent().unwrap_or(Path::new("."));
+    let stem = source_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let stem = format!("{}{}", stem, options.suffix());
+    let temp = folder.join(format!("{}.upscaling.{}", stem, encode_options.format.extension()));
+    fs::write(&temp, bytes)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", temp.display(), e)))?;
+    let path = output_files::place_file(&temp, folder, &stem, false).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })?;
+
+    let derived = entry.derive(
+        source,
+        DerivationKind::Upscale {
+            method: options.method,
+            scale: options.scale,
+        },
+        vec![path.to_string_lossy().to_string()],
+    );
+    history::record(derived.clone())?;
+    log::info!("Saved upscaled image {}", path.display());
+    Ok(derived)
+}
+
+/// The image at `source` scaled by `options.scale`, not yet saved
+pub async fn upscale(source: &Path, options: UpscaleOptions, settings: &AppSettings) -> Result<DynamicImage, DiffusionError> {
+    options.validate()?;
+    let image = image::open(source)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", source.display(), e)))?;
+    let (width, height) = options.target_size(image.width(), image.height())?;
+
+    let image = match options.method {
+        UpscaleMethod::Lanczos => image,
+        UpscaleMethod::Model => run_upscaler_applet(source, settings).await?,
+    };
+    if image.dimensions() == (width, height) {
+        return Ok(image);
+    }
+    // Resampling a large image takes a while, keep it off the async workers
+    tokio::task::spawn_blocking(move || image.resize_exact(width, height, FilterType::Lanczos3))
+        .await
+        .map_err(|e| DiffusionError::Internal(format!("Upscaling failed: {}", e)))
+}
+
+// The applet gets `input_image_path`, `output_path` and `model_path` and writes
+// its result to `output_path`
+async fn run_upscaler_applet(source: &Path, settings: &AppSettings) -> Result<DynamicImage, DiffusionError> {
+    if mock_backend::is_enabled(settings) {
+        return Err(DiffusionError::ProcessError(
+            "Model upscaling needs the Python backend and is not available with the mock backend".to_string(),
+        ));
+    }
+    let (python, backend_script) = crate::find_python_backend(settings).await?;
+
+    let output = std::env::temp_dir().join(format!("diffusionbee_{}.png", logging::new_job_id("upscale")));
+    let params = json!({
+        "input_image_path": source,
+        "output_path": output,
+        "model_path": settings.upscaler_model_path,
+    });
+    let applet = settings.upscaler_applet.trim();
+    applets::run_applet(
+        &python,
+        &backend_script,
+        applet,
+        &params,
+        |key, value| log::debug!("Upscaler update {}: {}", key, value),
+        || crate::GENERATION_CANCELLED.lock().map(|c| *c).unwrap_or(false),
+    )
+    .await?;
+
+    let image = image::open(&output).map_err(|e| {
+        DiffusionError::ProcessError(format!("Upscaler applet '{}' did not produce an image: {}", applet, e))
+    });
+    let _ = fs::remove_file(&output);
+    image
+}
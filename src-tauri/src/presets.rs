@@ -0,0 +1,436 @@
+// Saved prompt presets
+//
+// A preset is a named style: a prompt template, a negative prompt and the
+// parameters that go with them. They are kept in ~/.diffusionbee/presets.json
+// and can be shared as preset packs, JSON files holding any number of them.
+//
+// Applying presets to a request merges them in a fixed order, one preset at a
+// time in the order they are given:
+//
+//   prompt           `{prompt}` in the preset's template is replaced by the
+//                    prompt so far; a template without it is appended after a
+//                    comma, the way style suffixes are written
+//   negative prompt  the request's first, then each preset's, skipping the
+//                    comma-separated terms already there
+//   parameters       each value the preset sets replaces the request's, so a
+//                    later preset wins; LoRAs are added, and one already in the
+//                    request takes the preset's weight
+//
+// Preset parameters are defaults, so values the user gives explicitly win.
+// From lowest to highest precedence:
+//
+//   1. the request defaults (or the form, for `apply_presets` in the app)
+//   2. preset parameters, a later preset over an earlier one
+//   3. values set for the job: CLI flags such as `--width`, then the fields of
+//      a JSONL job line
+//
+// `apply_presets` merges into the request the app sends, which is the form the
+// user picks presets for. The CLI applies `PresetParameters::apply_to` to its
+// base request before the flags and job lines, and `apply_prompts_to` to each
+// job afterwards.
+//
+// Presets are applied before the prompt's wildcards and choice groups are
+// expanded, so templates may use both.
+
+use crate::json_store::Store;
+use crate::{DiffusionError, ImageGenerationRequest, LoraWeight, SCHEDULERS};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PRESETS_FILE_NAME: &str = "presets.json";
+const PACK_FORMAT: &str = "diffusionbee-presets";
+const PACK_VERSION: u32 = 1;
+const MAX_NAME_LEN: usize = 64;
+/// Where the prompt goes in a preset's template
+pub const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+static PRESETS: Store<Preset> = Store::pretty(PRESETS_FILE_NAME, "presets");
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    /// Unique, ignoring case
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// e.g. `{prompt}, oil painting, dramatic light`
+    #[serde(default)]
+    pub prompt_template: String,
+    #[serde(default)]
+    pub negative_prompt: String,
+    #[serde(default)]
+    pub parameters: PresetParameters,
+}
+
+/// Request values a preset sets; unset ones are left alone
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetParameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub img_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub img_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_inference_steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guidance_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub loras: Vec<LoraWeight>,
+}
+
+/// The file `export_pack` writes and `import_pack` reads
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresetPack {
+    pub format: String,
+    pub version: u32,
+    pub presets: Vec<Preset>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    /// Names that already existed, when not replacing
+    pub skipped: Vec<String>,
+}
+
+impl Preset {
+    pub fn validate(&self) -> Result<(), DiffusionError> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err(DiffusionError::validation("name", "Preset name cannot be empty"));
+        }
+        if name.chars().count() > MAX_NAME_LEN {
+            return Err(DiffusionError::validation(
+                "name",
+                format!("Preset name must be at most {} characters", MAX_NAME_LEN),
+            ));
+        }
+        if let Some(scheduler) = &self.parameters.scheduler {
+            if !SCHEDULERS.contains(&scheduler.as_str()) {
+                return Err(DiffusionError::validation(
+                    "scheduler",
+                    format!("Unknown scheduler '{}', expected one of: {}", scheduler, SCHEDULERS.join(", ")),
+                ));
+            }
+        }
+        if self.parameters.loras.iter().any(|lora| !lora.weight.is_finite()) {
+            return Err(DiffusionError::validation("loras", "LoRA weights must be numbers"));
+        }
+        Ok(())
+    }
+
+    /// Merges this preset into `request`, see the order above
+    pub fn apply_to(&self, request: &mut ImageGenerationRequest) {
+        self.apply_prompts_to(request);
+        self.parameters.apply_to(request);
+    }
+
+    /// Merges only the prompt template and negative prompt into `request`
+    pub fn apply_prompts_to(&self, request: &mut ImageGenerationRequest) {
+        let template = self.prompt_template.trim();
+        if template.contains(PROMPT_PLACEHOLDER) {
+            request.prompt = template.replace(PROMPT_PLACEHOLDER, request.prompt.trim());
+        } else if !template.is_empty() {
+            request.prompt = join_prompts(&request.prompt, template);
+        }
+
+        for term in self.negative_prompt.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if !request.negative_prompt.split(',').any(|t| t.trim() == term) {
+                request.negative_prompt = join_prompts(&request.negative_prompt, term);
+            }
+        }
+    }
+}
+
+impl PresetParameters {
+    /// Sets the values this preset has on `request`
+    pub fn apply_to(&self, request: &mut ImageGenerationRequest) {
+        request.img_width = self.img_width.unwrap_or(request.img_width);
+        request.img_height = self.img_height.unwrap_or(request.img_height);
+        request.num_inference_steps = self.num_inference_steps.unwrap_or(request.num_inference_steps);
+        request.guidance_scale = self.guidance_scale.unwrap_or(request.guidance_scale);
+        if self.scheduler.is_some() {
+            request.scheduler = self.scheduler.clone();
+        }
+        for lora in &self.loras {
+            match request.loras.iter_mut().find(|l| l.path == lora.path) {
+                Some(existing) => existing.weight = lora.weight,
+                None => request.loras.push(lora.clone()),
+            }
+        }
+    }
+}
+
+fn join_prompts(first: &str, second: &str) -> String {
+    match (first.trim(), second.trim()) {
+        ("", second) => second.to_string(),
+        (first, "") => first.to_string(),
+        (first, second) => format!("{}, {}", first, second),
+    }
+}
+
+/// ~/.diffusionbee/presets.json
+pub fn presets_path() -> Option<PathBuf> {
+    PRESETS.path()
+}
+
+/// Every preset, sorted by name
+pub fn list() -> Result<Vec<Preset>, DiffusionError> {
+    PRESETS.with(|presets| presets.clone())
+}
+
+pub fn get(name: &str) -> Result<Preset, DiffusionError> {
+    PRESETS.with(|presets| presets.iter().find(|p| same_name(&p.name, name)).cloned())?
+        .ok_or_else(|| DiffusionError::validation("name", format!("Unknown preset: {}", name)))
+}
+
+/// Adds `preset`, replacing the one with the same name. With `previous_name`
+/// the preset saved under that name is replaced instead, which renames it.
+pub fn save(mut preset: Preset, previous_name: Option<&str>) -> Result<Preset, DiffusionError> {
+    preset.validate()?;
+    preset.name = preset.name.trim().to_string();
+    let previous = previous_name.unwrap_or(&preset.name).to_string();
+    PRESETS.with(|presets| {
+        if !same_name(&previous, &preset.name) && presets.iter().any(|p| same_name(&p.name, &preset.name)) {
+            return Err(DiffusionError::validation(
+                "name",
+                format!("A preset named {} already exists", preset.name),
+            ));
+        }
+        presets.retain(|p| !same_name(&p.name, &previous));
+        insert_sorted(presets, preset.clone());
+        PRESETS.save(presets)?;
+        Ok(preset)
+    })?
+}
+
+pub fn delete(name: &str) -> Result<(), DiffusionError> {
+    PRESETS.with(|presets| {
+        let before = presets.len();
+        presets.retain(|p| !same_name(&p.name, name));
+        if presets.len() == before {
+            return Err(DiffusionError::validation("name", format!("Unknown preset: {}", name)));
+        }
+        PRESETS.save(presets)
+    })?
+}
+
+/// `request` with the named presets applied in order
+pub fn apply(mut request: ImageGenerationRequest, names: &[String]) -> Result<ImageGenerationRequest, DiffusionError> {
+    for name in names {
+        get(name)?.apply_to(&mut request);
+    }
+    Ok(request)
+}
+
+/// Writes the named presets, or all of them when `names` is empty, to a pack
+/// at `path` and returns how many were written
+pub fn export_pack(names: &[String], path: &Path) -> Result<usize, DiffusionError> {
+    let presets = if names.is_empty() {
+        list()?
+    } else {
+        names.iter().map(|name| get(name)).collect::<Result<_, _>>()?
+    };
+    let pack = PresetPack {
+        format: PACK_FORMAT.to_string(),
+        version: PACK_VERSION,
+        presets,
+    };
+    fs::write(path, serde_json::to_string_pretty(&pack)?)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(pack.presets.len())
+}
+
+/// Adds the presets of the pack at `path`. Presets whose name is taken replace
+/// the existing one with `replace`, and are skipped otherwise.
+pub fn import_pack(path: &Path, replace: bool) -> Result<ImportSummary, DiffusionError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", path.display(), e)))?;
+    let pack: PresetPack = serde_json::from_str(&content)?;
+    if pack.format != PACK_FORMAT {
+        return Err(DiffusionError::validation("path", format!("{} is not a preset pack", path.display())));
+    }
+    if pack.version > PACK_VERSION {
+        return Err(DiffusionError::validation(
+            "path",
+            format!("Preset pack version {} is newer than this app supports", pack.version),
+        ));
+    }
+    for preset in &pack.presets {
+        preset
+            .validate()
+            .map_err(|e| DiffusionError::validation("path", format!("Preset '{}': {}", preset.name, e.message())))?;
+    }
+
+    PRESETS.with(|presets| {
+        let mut summary = ImportSummary::default();
+        for mut preset in pack.presets {
+            preset.name = preset.name.trim().to_string();
+            let exists = presets.iter().any(|p| same_name(&p.name, &preset.name));
+            if exists && !replace {
+                summary.skipped.push(preset.name);
+                continue;
+            }
+            presets.retain(|p| !same_name(&p.name, &preset.name));
+            if exists {
+                summary.replaced.push(preset.name.clone());
+            } else {
+                summary.added.push(preset.name.clone());
+            }
+            insert_sorted(presets, preset);
+        }
+        PRESETS.save(presets)?;
+        log::info!(
+            "Imported presets from {}: {} added, {} replaced, {} skipped",
+            path.display(),
+            summary.added.len(),
+            summary.replaced.len(),
+            summary.skipped.len()
+        );
+        Ok(summary)
+    })?
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+fn insert_sorted(presets: &mut Vec<Preset>, preset: Preset) {
+    let index = presets.partition_point(|p| p.name.to_lowercase() < preset.name.to_lowercase());
+    presets.insert(index, preset);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(prompt_template: &str, negative_prompt: &str, parameters: PresetParameters) -> Preset {
+        Preset {
+            name: "style".to_string(),
+            description: String::new(),
+            prompt_template: prompt_template.to_string(),
+            negative_prompt: negative_prompt.to_string(),
+            parameters,
+        }
+    }
+
+    fn lora(path: &str, weight: f32) -> LoraWeight {
+        LoraWeight {
+            path: path.to_string(),
+            weight,
+        }
+    }
+
+    fn applied(request: ImageGenerationRequest, presets: &[Preset]) -> ImageGenerationRequest {
+        let mut request = request;
+        for preset in presets {
+            preset.apply_to(&mut request);
+        }
+        request
+    }
+
+    #[test]
+    fn template_with_placeholder_wraps_the_prompt() {
+        let request = ImageGenerationRequest::new("  a red fox ".to_string());
+        let styled = applied(request, &[preset("portrait of {prompt}, oil painting", "", Default::default())]);
+        assert_eq!(styled.prompt, "portrait of a red fox, oil painting");
+    }
+
+    #[test]
+    fn template_without_placeholder_is_appended() {
+        let request = ImageGenerationRequest::new("a red fox".to_string());
+        let styled = applied(
+            request,
+            &[
+                preset("oil painting", "", Default::default()),
+                preset("{prompt}, dramatic light", "", Default::default()),
+                preset("   ", "", Default::default()),
+            ],
+        );
+        assert_eq!(styled.prompt, "a red fox, oil painting, dramatic light");
+
+        let empty = applied(ImageGenerationRequest::new(String::new()), &[preset("oil painting", "", Default::default())]);
+        assert_eq!(empty.prompt, "oil painting");
+    }
+
+    #[test]
+    fn negative_terms_are_not_repeated() {
+        let request = ImageGenerationRequest {
+            negative_prompt: "blurry, text".to_string(),
+            ..ImageGenerationRequest::new("a fox".to_string())
+        };
+        let styled = applied(
+            request,
+            &[
+                preset("", " text ,watermark,, blurry", Default::default()),
+                preset("", "watermark, low quality", Default::default()),
+            ],
+        );
+        assert_eq!(styled.negative_prompt, "blurry, text, watermark, low quality");
+    }
+
+    #[test]
+    fn loras_are_added_or_reweighted() {
+        let request = ImageGenerationRequest {
+            loras: vec![lora("/loras/ink.safetensors", 0.5), lora("/loras/film.safetensors", 1.0)],
+            ..ImageGenerationRequest::new("a fox".to_string())
+        };
+        let parameters = PresetParameters {
+            loras: vec![lora("/loras/film.safetensors", 0.3), lora("/loras/grain.safetensors", 0.8)],
+            ..Default::default()
+        };
+        let styled = applied(request, &[preset("", "", parameters)]);
+        assert_eq!(
+            styled.loras,
+            [
+                lora("/loras/ink.safetensors", 0.5),
+                lora("/loras/film.safetensors", 0.3),
+                lora("/loras/grain.safetensors", 0.8)
+            ]
+        );
+    }
+
+    #[test]
+    fn later_presets_win_and_unset_values_are_kept() {
+        let request = ImageGenerationRequest {
+            scheduler: Some("ddim".to_string()),
+            ..ImageGenerationRequest::new("a fox".to_string())
+        };
+        let first = PresetParameters {
+            img_width: Some(768),
+            img_height: Some(768),
+            num_inference_steps: Some(30),
+            scheduler: Some("k_euler".to_string()),
+            ..Default::default()
+        };
+        let second = PresetParameters {
+            img_width: Some(1024),
+            guidance_scale: Some(4.0),
+            ..Default::default()
+        };
+        let styled = applied(request, &[preset("", "", first), preset("", "", second)]);
+        assert_eq!((styled.img_width, styled.img_height), (1024, 768));
+        assert_eq!(styled.num_inference_steps, 30);
+        assert_eq!(styled.guidance_scale, 4.0);
+        assert_eq!(styled.scheduler.as_deref(), Some("k_euler"));
+        assert_eq!(styled.num_imgs, 1);
+    }
+
+    #[test]
+    fn prompts_only_leaves_parameters_alone() {
+        let parameters = PresetParameters {
+            img_width: Some(1024),
+            loras: vec![lora("/loras/ink.safetensors", 0.5)],
+            ..Default::default()
+        };
+        let mut request = ImageGenerationRequest::new("a fox".to_string());
+        preset("{prompt}, ink", "blurry", parameters).apply_prompts_to(&mut request);
+        assert_eq!(request.prompt, "a fox, ink");
+        assert_eq!(request.negative_prompt, "blurry");
+        assert_eq!(request.img_width, 512);
+        assert!(request.loras.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+// Prompt history and autocomplete
+//
+// Every prompt submitted for generation is remembered in
+// ~/.diffusionbee/prompt_history.json as it was typed, before its wildcards and
+// choice groups are expanded, with how often and when it was last used. Unlike
+// the generation history there is one record per distinct prompt, not per job.
+//
+// `suggest` completes what the user is typing. While the last word is an
+// unfinished `__wildcard`, it offers wildcard names; otherwise past prompts.
+// Candidates match by prefix, by substring or fuzzily (the typed characters
+// appear in order), in that order of preference, and are ranked by the match
+// times a usage weight that grows with the use count and halves for every two
+// weeks since the last use. Wildcards count as used whenever a prompt using
+// them was.
+
+use crate::json_store::Store;
+use crate::{prompt_template, DiffusionError};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const PROMPT_HISTORY_FILE_NAME: &str = "prompt_history.json";
+// The least recently used prompts are dropped beyond this
+const MAX_PROMPTS: usize = 5000;
+const RECENCY_HALF_LIFE_DAYS: f64 = 14.0;
+pub const DEFAULT_SUGGESTIONS: usize = 10;
+
+static PROMPTS: Store<PromptRecord> = Store::new(PROMPT_HISTORY_FILE_NAME, "prompt history");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptRecord {
+    pub prompt: String,
+    pub use_count: u32,
+    /// RFC 3339, UTC
+    pub last_used: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    Prompt,
+    Wildcard,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptSuggestion {
+    pub kind: SuggestionKind,
+    /// The whole input with the suggestion applied
+    pub text: String,
+    /// What to show: the prompt, or the wildcard as `__name__`
+    pub label: String,
+    pub use_count: u32,
+    pub last_used: Option<String>,
+    pub score: f64,
+}
+
+/// ~/.diffusionbee/prompt_history.json
+pub fn prompt_history_path() -> Option<PathBuf> {
+    PROMPTS.path()
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+/// Counts a use of `prompt`
+pub fn record(prompt: &str) -> Result<(), DiffusionError> {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return Ok(());
+    }
+    PROMPTS.with(|records| {
+        match records.iter_mut().find(|r| r.prompt == prompt) {
+            Some(record) => {
+                record.use_count += 1;
+                record.last_used = now();
+            }
+            None => records.push(PromptRecord {
+                prompt: prompt.to_string(),
+                use_count: 1,
+                last_used: now(),
+            }),
+        }
+        if records.len() > MAX_PROMPTS {
+            // RFC 3339 in UTC sorts by time
+            records.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+            records.truncate(MAX_PROMPTS);
+        }
+        PROMPTS.save(records)
+    })?
+}
+
+/// The most recently used prompts first, at most `limit`
+pub fn recent(limit: usize) -> Result<Vec<PromptRecord>, DiffusionError> {
+    PROMPTS.with(|records| {
+        let mut records = records.clone();
+        records.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+        records.truncate(limit);
+        records
+    })
+}
+
+/// Forgets `prompt`, returning whether it was remembered
+pub fn forget(prompt: &str) -> Result<bool, DiffusionError> {
+    PROMPTS.with(|records| {
+        let before = records.len();
+        records.retain(|r| r.prompt != prompt.trim());
+        if records.len() == before {
+            return Ok(false);
+        }
+        PROMPTS.save(records).map(|_| true)
+    })?
+}
+
+/// Completions for `input`, best first, at most `limit`
+pub fn suggest(input: &str, wildcards_dir: &Path, limit: usize) -> Result<Vec<PromptSuggestion>, DiffusionError> {
+    let records = PROMPTS.with(|records| records.clone())?;
+    let now = OffsetDateTime::now_utc();
+
+    let mut suggestions = match partial_wildcard(input) {
+        Some((start, query)) => prompt_template::list_wildcards(wildcards_dir)
+            .into_iter()
+            .filter_map(|name| {
+                let quality = match_quality(query, &name)?;
+                let token = format!("__{}__", name);
+                let uses = records.iter().filter(|r| r.prompt.contains(&token));
+                let use_count = uses.clone().map(|r| r.use_count).sum();
+                let last_used = uses.map(|r| r.last_used.clone()).max();
+                Some(PromptSuggestion {
+                    kind: SuggestionKind::Wildcard,
+                    text: format!("{}{}", &input[..start], token),
+                    label: token,
+                    use_count,
+                    score: quality * usage_weight(use_count, last_used.as_deref(), now),
+                    last_used,
+                })
+            })
+            .collect(),
+        None => {
+            let query = input.trim();
+            records
+                .iter()
+                .filter(|r| !r.prompt.eq_ignore_ascii_case(query))
+                .filter_map(|r| {
+                    let quality = match_quality(query, &r.prompt)?;
+                    Some(PromptSuggestion {
+                        kind: SuggestionKind::Prompt,
+                        text: r.prompt.clone(),
+                        label: r.prompt.clone(),
+                        use_count: r.use_count,
+                        last_used: Some(r.last_used.clone()),
+                        score: quality * usage_weight(r.use_count, Some(&r.last_used), now),
+                    })
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.label.cmp(&b.label)));
+    suggestions.truncate(limit);
+    Ok(suggestions)
+}
+
+// The byte offset and name typed so far when the input ends in an unfinished
+// `__wildcard`, e.g. `a cat, __sty` -> (7, "sty")
+fn partial_wildcard(input: &str) -> Option<(usize, &str)> {
+    let start = input
+        .rfind(|c: char| c.is_whitespace() || matches!(c, ',' | '{' | '}' | '|'))
+        .map(|i| i + input[i..].chars().next().map_or(1, char::len_utf8))
+        .unwrap_or(0);
+    let token = &input[start..];
+    let name = token.strip_prefix("__")?;
+    // A closed `__name__` is finished
+    (!name.contains("__")).then_some((start, name))
+}
+
+// 1 for a prefix match, 0.75 for a substring, up to 0.5 for the characters in
+// order (more for closer together), None otherwise. Everything matches an
+// empty query, so usage alone ranks it.
+fn match_quality(query: &str, candidate: &str) -> Option<f64> {
+    let query = query.to_lowercase();
+    let candidate = candidate.to_lowercase();
+    if query.is_empty() || candidate.starts_with(&query) {
+        return Some(1.0);
+    }
+    if candidate.contains(&query) {
+        return Some(0.75);
+    }
+
+    let mut wanted = query.chars().filter(|c| !c.is_whitespace()).peekable();
+    let (mut first, mut last) = (None, 0);
+    for (i, c) in candidate.chars().enumerate() {
+        if wanted.peek() == Some(&c) {
+            wanted.next();
+            first.get_or_insert(i);
+            last = i;
+        }
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+    let matched = query.chars().filter(|c| !c.is_whitespace()).count();
+    let span = last + 1 - first.unwrap_or(0);
+    Some(0.25 + 0.25 * matched as f64 / span.max(1) as f64)
+}
+
+fn usage_weight(use_count: u32, last_used: Option<&str>, now: OffsetDateTime) -> f64 {
+    let days = last_used
+        .and_then(|time| OffsetDateTime::parse(time, &Rfc3339).ok())
+        .map(|time| (now - time).as_seconds_f64().max(0.0) / 86_400.0);
+    let recency = days.map_or(0.5, |days| 0.5f64.powf(days / RECENCY_HALF_LIFE_DAYS));
+    (1.0 + (use_count as f64).ln_1p()) * recency
+}
@@ -0,0 +1,338 @@
+// Prompt templating: wildcard files and random choice groups
+//
+// Syntax supported:
+//   __name__          a random line from `<wildcards_dir>/name.txt` (sub folders allowed: `__styles/anime__`)
+//   {red|green|blue}  a random choice between the options
+//
+// Both forms can be nested inside each other and inside wildcard files. Expansion
+// is driven by a seeded generator so the same prompt, seed and wildcard files always
+// produce the same result.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Guards against runaway templates that never hit the same wildcard twice
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// Small deterministic generator (SplitMix64). We don't use an external RNG
+/// because its output must stay stable across dependency upgrades for old
+/// seeds to keep reproducing the same prompts.
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn pick(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
+
+pub struct PromptExpander {
+    wildcards_dir: PathBuf,
+    rng: SeededRng,
+    cache: HashMap<String, Vec<String>>,
+}
+
+impl PromptExpander {
+    pub fn new(wildcards_dir: impl Into<PathBuf>, seed: u64) -> Self {
+        Self {
+            wildcards_dir: wildcards_dir.into(),
+            rng: SeededRng::new(seed),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Expands every wildcard and choice group in `template`.
+    pub fn expand(&mut self, template: &str) -> Result<String, String> {
+        let mut stack = Vec::new();
+        self.expand_inner(template, &mut stack)
+    }
+
+    fn expand_inner(&mut self, template: &str, stack: &mut Vec<String>) -> Result<String, String> {
+        if stack.len() > MAX_EXPANSION_DEPTH {
+            return Err(format!(
+                "Prompt expansion exceeded the maximum nesting depth of {}",
+                MAX_EXPANSION_DEPTH
+            ));
+        }
+
+        let chars: Vec<char> = template.chars().collect();
+        let mut output = String::with_capacity(template.len());
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '{' => {
+                    let end = find_closing_brace(&chars, i).ok_or_else(|| {
+                        format!("Unmatched '{{' at position {} in prompt template", i)
+                    })?;
+                    let options = split_options(&chars[i + 1..end]);
+                    let choice = options[self.rng.pick(options.len())].clone();
+                    output.push_str(&self.expand_inner(&choice, stack)?);
+                    i = end + 1;
+                }
+                '}' => {
+                    return Err(format!("Unmatched '}}' at position {} in prompt template", i));
+                }
+                '_' if is_wildcard_start(&chars, i) => {
+                    match find_wildcard_end(&chars, i + 2) {
+                        Some(end) => {
+                            let name: String = chars[i + 2..end].iter().collect();
+                            output.push_str(&self.expand_wildcard(&name, stack)?);
+                            i = end + 2;
+                        }
+                        None => {
+                            // Not a wildcard after all, keep the underscores as typed
+                            output.push_str("__");
+                            i += 2;
+                        }
+                    }
+                }
+                c => {
+                    output.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn expand_wildcard(&mut self, name: &str, stack: &mut Vec<String>) -> Result<String, String> {
+        if stack.iter().any(|n| n == name) {
+            let mut cycle = stack.clone();
+            cycle.push(name.to_string());
+            return Err(format!("Wildcard cycle detected: {}", cycle.join(" -> ")));
+        }
+
+        let lines = self.load_wildcard(name)?;
+        let choice = lines[self.rng.pick(lines.len())].clone();
+
+        stack.push(name.to_string());
+        let expanded = self.expand_inner(&choice, stack);
+        stack.pop();
+        expanded
+    }
+
+    fn load_wildcard(&mut self, name: &str) -> Result<Vec<String>, String> {
+        if let Some(lines) = self.cache.get(name) {
+            return Ok(lines.clone());
+        }
+
+        let path = wildcard_path(&self.wildcards_dir, name)?;
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read wildcard '__{}__' at {}: {}", name, path.display(), e))?;
+
+        let lines: Vec<String> = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+
+        if lines.is_empty() {
+            return Err(format!("Wildcard '__{}__' has no entries", name));
+        }
+
+        self.cache.insert(name.to_string(), lines.clone());
+        Ok(lines)
+    }
+}
+
+/// Expands `template` against the wildcard files in `wildcards_dir` using `seed`.
+pub fn expand_prompt(template: &str, wildcards_dir: &Path, seed: u64) -> Result<String, String> {
+    PromptExpander::new(wildcards_dir, seed).expand(template)
+}
+
+/// Lists the wildcard names (without the surrounding underscores) available in `wildcards_dir`.
+pub fn list_wildcards(wildcards_dir: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    collect_wildcards(wildcards_dir, wildcards_dir, &mut names);
+    names.sort();
+    names
+}
+
+fn collect_wildcards(root: &Path, dir: &Path, names: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_wildcards(root, &path, names);
+        } else if path.extension().and_then(|s| s.to_str()) == Some("txt") {
+            if let Ok(relative) = path.with_extension("").strip_prefix(root) {
+                let name = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                names.push(name);
+            }
+        }
+    }
+}
+
+fn wildcard_path(wildcards_dir: &Path, name: &str) -> Result<PathBuf, String> {
+    // Only plain relative names are allowed so a template can't read arbitrary files
+    let valid = !name.is_empty()
+        && name.split('/').all(|part| {
+            !part.is_empty()
+                && part != "."
+                && part != ".."
+                && part.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ' '))
+        });
+    if !valid {
+        return Err(format!("Invalid wildcard name '__{}__'", name));
+    }
+    Ok(wildcards_dir.join(format!("{}.txt", name)))
+}
+
+fn is_wildcard_start(chars: &[char], i: usize) -> bool {
+    i + 1 < chars.len() && chars[i + 1] == '_'
+}
+
+// Wildcard names end at the next `__` and may not span whitespace or groups
+fn find_wildcard_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut j = start;
+    while j + 1 < chars.len() {
+        let c = chars[j];
+        if c == '_' && chars[j + 1] == '_' {
+            return if j > start { Some(j) } else { None };
+        }
+        if c.is_whitespace() || matches!(c, '{' | '}' | '|' | ',') {
+            return None;
+        }
+        j += 1;
+    }
+    None
+}
+
+fn find_closing_brace(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (j, &c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(j);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits the inside of a choice group on top-level `|` only
+fn split_options(chars: &[char]) -> Vec<String> {
+    let mut options = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for &c in chars {
+        match c {
+            '{' => {
+                depth += 1;
+                current.push(c);
+            }
+            '}' => {
+                depth -= 1;
+                current.push(c);
+            }
+            '|' if depth == 0 => options.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    options.push(current);
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh wildcards folder per test, so tests running in parallel don't share files
+    fn wildcards_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("diffusionbee_wildcards_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, content) in files {
+            let path = dir.join(format!("{}.txt", name));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn nested_groups_pick_one_leaf() {
+        let dir = wildcards_dir("nested", &[]);
+        for seed in 0..50 {
+            let prompt = expand_prompt("a {red|{green|{blue|cyan}}} cat", &dir, seed).unwrap();
+            assert!(
+                ["a red cat", "a green cat", "a blue cat", "a cyan cat"].contains(&prompt.as_str()),
+                "unexpected expansion {:?}",
+                prompt
+            );
+        }
+    }
+
+    #[test]
+    fn nested_wildcards_and_groups() {
+        let dir = wildcards_dir(
+            "nested_wildcards",
+            &[("animal", "# comment\n\n{big|small} __styles/color__ dog\n"), ("styles/color", "brown")],
+        );
+        let prompt = expand_prompt("__animal__", &dir, 7).unwrap();
+        assert!(prompt == "big brown dog" || prompt == "small brown dog", "{:?}", prompt);
+    }
+
+    #[test]
+    fn reference_cycle_is_an_error() {
+        let dir = wildcards_dir("cycle", &[("a", "x __b__"), ("b", "y __a__")]);
+        let err = expand_prompt("__a__", &dir, 1).unwrap_err();
+        assert_eq!(err, "Wildcard cycle detected: a -> b -> a");
+    }
+
+    #[test]
+    fn parent_directory_names_are_rejected() {
+        let dir = wildcards_dir("parent", &[]);
+        let err = expand_prompt("__../secret__", &dir, 1).unwrap_err();
+        assert_eq!(err, "Invalid wildcard name '__../secret__'");
+        assert!(wildcard_path(&dir, "styles/../../secret").is_err());
+        assert!(wildcard_path(&dir, "/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn same_seed_gives_same_prompt() {
+        let dir = wildcards_dir("seed", &[("color", "red\ngreen\nblue\nyellow\npurple\norange")]);
+        let template = "__color__ {cat|dog|fox|owl} in {rain|snow|fog}, __color__ sky";
+        let first = expand_prompt(template, &dir, 1234).unwrap();
+        for _ in 0..5 {
+            assert_eq!(expand_prompt(template, &dir, 1234).unwrap(), first);
+        }
+        let others: Vec<String> = (0..20).map(|seed| expand_prompt(template, &dir, seed).unwrap()).collect();
+        assert!(others.iter().any(|p| *p != first), "every seed gave {:?}", first);
+    }
+
+    #[test]
+    fn unmatched_braces_and_plain_underscores() {
+        let dir = wildcards_dir("plain", &[]);
+        assert!(expand_prompt("{red|green", &dir, 1).is_err());
+        assert!(expand_prompt("red}", &dir, 1).is_err());
+        assert_eq!(expand_prompt("snake_case __ words", &dir, 1).unwrap(), "snake_case __ words");
+    }
+}
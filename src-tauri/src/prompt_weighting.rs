@@ -0,0 +1,382 @@
+// Parser for the weighted prompt syntax power users know from other SD front-ends
+//
+//   (word)        weight x1.1, nesting multiplies: ((word)) is x1.21
+//   (word:1.3)    explicit weight
+//   [word]        weight /1.1
+//   a AND b       separate sub-prompts, each optionally ending in `:weight`
+//   \( \) \[ \]   literal brackets
+//
+// A bracket without a partner, like the one in `smile :(`, is kept as literal
+// text and reported in `warnings` instead of rejecting the prompt.
+//
+// The backend doesn't understand weights yet, so the structured form is sent
+// alongside a plain prompt with all the syntax stripped out.
+
+use serde::{Deserialize, Serialize};
+
+const ATTENTION_MULTIPLIER: f32 = 1.1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptSegment {
+    pub text: String,
+    pub weight: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubPrompt {
+    pub segments: Vec<PromptSegment>,
+    pub weight: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedPrompt {
+    pub sub_prompts: Vec<SubPrompt>,
+    /// Problems that were worked around, e.g. unmatched brackets
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, thiserror::Error)]
+#[error("{message} (at position {position})")]
+pub struct PromptSyntaxError {
+    /// Character offset into the prompt
+    pub position: usize,
+    pub message: String,
+}
+
+impl PromptSyntaxError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl SubPrompt {
+    fn plain_text(&self) -> String {
+        let text: String = self.segments.iter().map(|s| s.text.as_str()).collect();
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl WeightedPrompt {
+    /// The prompt with all weighting syntax removed
+    pub fn plain_prompt(&self) -> String {
+        self.sub_prompts
+            .iter()
+            .map(SubPrompt::plain_text)
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// True when the prompt uses any syntax beyond plain text
+    pub fn has_weights(&self) -> bool {
+        self.sub_prompts.len() > 1
+            || self.sub_prompts.iter().any(|p| {
+                p.weight != 1.0 || p.segments.iter().any(|s| s.weight != 1.0)
+            })
+    }
+}
+
+pub fn parse_weighted_prompt(prompt: &str) -> Result<WeightedPrompt, PromptSyntaxError> {
+    let chars: Vec<char> = prompt.chars().collect();
+    let matched = match_brackets(&chars);
+    let warnings = (0..chars.len())
+        .filter(|&i| is_bracket(&chars, i) && !matched[i])
+        .map(|i| format!("Unmatched '{}' at position {} was kept as text", chars[i], i))
+        .collect();
+
+    let ranges = split_on_and(&chars, &matched)?;
+    // Sub-prompt weights only make sense with AND, and `16:9` shouldn't become a weight
+    let allow_weights = ranges.len() > 1;
+    let sub_prompts = ranges
+        .into_iter()
+        .map(|(start, end)| parse_sub_prompt(&chars, &matched, start, end, allow_weights))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(WeightedPrompt { sub_prompts, warnings })
+}
+
+// True for an unescaped bracket at `i`
+fn is_bracket(chars: &[char], i: usize) -> bool {
+    matches!(chars[i], '(' | ')' | '[' | ']') && !is_escaped(chars, i)
+}
+
+fn is_escaped(chars: &[char], i: usize) -> bool {
+    chars[..i].iter().rev().take_while(|&&c| c == '\\').count() % 2 == 1
+}
+
+// Marks the brackets that have a partner; the others are plain text
+fn match_brackets(chars: &[char]) -> Vec<bool> {
+    let mut matched = vec![false; chars.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    for i in 0..chars.len() {
+        if !is_bracket(chars, i) {
+            continue;
+        }
+        match chars[i] {
+            '(' | '[' => stack.push(i),
+            close => {
+                let open = if close == ')' { '(' } else { '[' };
+                if let Some(&j) = stack.last().filter(|&&j| chars[j] == open) {
+                    stack.pop();
+                    matched[i] = true;
+                    matched[j] = true;
+                }
+            }
+        }
+    }
+    matched
+}
+
+// Returns the char ranges of each sub-prompt, splitting on top-level `AND`
+fn split_on_and(chars: &[char], matched: &[bool]) -> Result<Vec<(usize, usize)>, PromptSyntaxError> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut depth = 0i32;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '(' | '[' if matched[i] => depth += 1,
+            ')' | ']' if matched[i] => depth -= 1,
+            'A' if is_and_keyword(chars, i) => {
+                if depth > 0 {
+                    return Err(PromptSyntaxError::new(i, "AND cannot be used inside brackets"));
+                }
+                if chars[start..i].iter().all(|c| c.is_whitespace()) {
+                    return Err(PromptSyntaxError::new(i, "AND must be between two prompts"));
+                }
+                ranges.push((start, i));
+                start = i + 3;
+                i += 2;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if !ranges.is_empty() && chars[start..].iter().all(|c| c.is_whitespace()) {
+        return Err(PromptSyntaxError::new(chars.len(), "AND must be between two prompts"));
+    }
+    ranges.push((start, chars.len()));
+    Ok(ranges)
+}
+
+fn is_and_keyword(chars: &[char], i: usize) -> bool {
+    chars.get(i..i + 3) == Some(&['A', 'N', 'D'][..])
+        && (i == 0 || chars[i - 1].is_whitespace())
+        && chars.get(i + 3).is_none_or(|c| c.is_whitespace())
+}
+
+fn parse_sub_prompt(
+    chars: &[char],
+    matched: &[bool],
+    start: usize,
+    end: usize,
+    allow_weight: bool,
+) -> Result<SubPrompt, PromptSyntaxError> {
+    let trailing = if allow_weight { trailing_weight(chars, start, end)? } else { None };
+    let (end, weight) = trailing.unwrap_or((end, 1.0));
+
+    // Each open bracket remembers where it started and the first segment it owns
+    let mut stack: Vec<(char, usize, usize)> = Vec::new();
+    let mut segments: Vec<PromptSegment> = Vec::new();
+    let mut text = String::new();
+    let mut i = start;
+
+    let flush = |text: &mut String, segments: &mut Vec<PromptSegment>| {
+        if !text.is_empty() {
+            segments.push(PromptSegment {
+                text: std::mem::take(text),
+                weight: 1.0,
+            });
+        }
+    };
+
+    while i < end {
+        let c = chars[i];
+        match c {
+            '\\' if i + 1 < end && matches!(chars[i + 1], '(' | ')' | '[' | ']' | '\\') => {
+                text.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '(' | '[' if matched[i] => {
+                flush(&mut text, &mut segments);
+                stack.push((c, i, segments.len()));
+            }
+            ':' if matches!(stack.last(), Some(('(', _, _))) => {
+                match explicit_weight(chars, i, end) {
+                    Some((weight, close)) if matched[close] => {
+                        flush(&mut text, &mut segments);
+                        let (_, open_pos, first) = stack.pop().unwrap();
+                        if first == segments.len() {
+                            return Err(PromptSyntaxError::new(open_pos, "Weighted group is empty"));
+                        }
+                        apply_weight(&mut segments[first..], weight);
+                        i = close + 1;
+                        continue;
+                    }
+                    _ => text.push(c),
+                }
+            }
+            ')' | ']' if matched[i] => {
+                // Matched brackets nest, so this closes the innermost open group
+                let (_, _, first) = stack.pop().unwrap();
+                flush(&mut text, &mut segments);
+                let multiplier = if c == ')' {
+                    ATTENTION_MULTIPLIER
+                } else {
+                    1.0 / ATTENTION_MULTIPLIER
+                };
+                apply_weight(&mut segments[first..], multiplier);
+            }
+            _ => text.push(c),
+        }
+        i += 1;
+    }
+
+    flush(&mut text, &mut segments);
+
+    Ok(SubPrompt {
+        segments: merge_segments(segments),
+        weight,
+    })
+}
+
+// Parses `:<number>)` starting at the colon; returns the weight and the `)` index
+fn explicit_weight(chars: &[char], colon: usize, end: usize) -> Option<(f32, usize)> {
+    let close = (colon + 1..end).find(|&j| chars[j] == ')')?;
+    let number: String = chars[colon + 1..close].iter().collect();
+    let weight = number.trim().parse::<f32>().ok()?;
+    weight.is_finite().then_some((weight, close))
+}
+
+// A sub-prompt may end in `:<number>` to weight the whole sub-prompt
+fn trailing_weight(
+    chars: &[char],
+    start: usize,
+    end: usize,
+) -> Result<Option<(usize, f32)>, PromptSyntaxError> {
+    let Some(colon) = (start..end).rev().find(|&j| chars[j] == ':') else {
+        return Ok(None);
+    };
+    // A colon inside a group belongs to that group
+    if chars[colon + 1..end].iter().any(|c| matches!(c, '(' | ')' | '[' | ']')) {
+        return Ok(None);
+    }
+    let number: String = chars[colon + 1..end].iter().collect();
+    let number = number.trim();
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) {
+        return Ok(None);
+    }
+    match number.parse::<f32>() {
+        Ok(weight) if weight.is_finite() => Ok(Some((colon, weight))),
+        _ => Err(PromptSyntaxError::new(colon + 1, format!("Invalid weight '{}'", number))),
+    }
+}
+
+fn apply_weight(segments: &mut [PromptSegment], multiplier: f32) {
+    for segment in segments {
+        segment.weight *= multiplier;
+    }
+}
+
+fn merge_segments(segments: Vec<PromptSegment>) -> Vec<PromptSegment> {
+    let mut merged: Vec<PromptSegment> = Vec::with_capacity(segments.len());
+    for segment in segments {
+        match merged.last_mut() {
+            Some(last) if (last.weight - segment.weight).abs() < f32::EPSILON => {
+                last.text.push_str(&segment.text)
+            }
+            _ => merged.push(segment),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(prompt: &str) -> Vec<(String, f32)> {
+        let parsed = parse_weighted_prompt(prompt).unwrap();
+        assert_eq!(parsed.sub_prompts.len(), 1);
+        parsed.sub_prompts[0]
+            .segments
+            .iter()
+            .map(|s| (s.text.clone(), s.weight))
+            .collect()
+    }
+
+    fn assert_segments(prompt: &str, expected: &[(&str, f32)]) {
+        let actual = segments(prompt);
+        assert_eq!(actual.len(), expected.len(), "{:?} parsed as {:?}", prompt, actual);
+        for ((text, weight), (expected_text, expected_weight)) in actual.iter().zip(expected) {
+            assert_eq!(text, expected_text, "{:?}", prompt);
+            assert!((weight - expected_weight).abs() < 1e-5, "{:?} parsed as {:?}", prompt, actual);
+        }
+    }
+
+    #[test]
+    fn explicit_weight() {
+        assert_segments("a (red:1.3) car", &[("a ", 1.0), ("red", 1.3), (" car", 1.0)]);
+        assert_segments("(red car: 0.5)", &[("red car", 0.5)]);
+        // Not a number, so the colon is just text inside a x1.1 group
+        assert_segments("(ratio:wide)", &[("ratio:wide", 1.1)]);
+    }
+
+    #[test]
+    fn square_brackets_lower_the_weight() {
+        assert_segments("a [blurry] photo", &[("a ", 1.0), ("blurry", 1.0 / 1.1), (" photo", 1.0)]);
+        assert_segments("[[blurry]]", &[("blurry", 1.0 / 1.21)]);
+    }
+
+    #[test]
+    fn nested_emphasis_multiplies() {
+        assert_segments("((cat))", &[("cat", 1.21)]);
+        assert_segments("(a (cat:1.5) b)", &[("a ", 1.1), ("cat", 1.65), (" b", 1.1)]);
+        assert_segments("([cat])", &[("cat", 1.0)]);
+        assert_segments("\\(cat\\)", &[("(cat)", 1.0)]);
+    }
+
+    #[test]
+    fn and_splits_sub_prompts() {
+        let parsed = parse_weighted_prompt("a cat:1.2 AND a (dog) AND snow:0.5").unwrap();
+        let weights: Vec<f32> = parsed.sub_prompts.iter().map(|p| p.weight).collect();
+        assert_eq!(weights, [1.2, 1.0, 0.5]);
+        assert_eq!(parsed.plain_prompt(), "a cat, a dog, snow");
+        assert!(parsed.has_weights());
+
+        // Without AND a trailing number is part of the prompt
+        let parsed = parse_weighted_prompt("poster 16:9").unwrap();
+        assert_eq!(parsed.plain_prompt(), "poster 16:9");
+        assert!(!parsed.has_weights());
+
+        assert!(parse_weighted_prompt("AND cat").is_err());
+        assert!(parse_weighted_prompt("cat AND").is_err());
+        assert!(parse_weighted_prompt("(cat AND dog)").is_err());
+        // Only the upper case keyword splits
+        assert_eq!(segments("salt and pepper, ANDROID"), [("salt and pepper, ANDROID".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn unbalanced_brackets_are_kept_as_text() {
+        let parsed = parse_weighted_prompt("smile :(").unwrap();
+        assert_eq!(parsed.plain_prompt(), "smile :(");
+        assert_eq!(parsed.warnings, ["Unmatched '(' at position 7 was kept as text"]);
+
+        let parsed = parse_weighted_prompt("wink ;) (cat)").unwrap();
+        assert_eq!(parsed.plain_prompt(), "wink ;) cat");
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_segments("wink ;) (cat)", &[("wink ;) ", 1.0), ("cat", 1.1)]);
+
+        assert_segments("(a (b) c", &[("(a ", 1.0), ("b", 1.1), (" c", 1.0)]);
+        assert_segments("(a] b)", &[("a] b", 1.1)]);
+        assert_eq!(parse_weighted_prompt(":( AND dog").unwrap().sub_prompts.len(), 2);
+        assert!(parse_weighted_prompt("(a) [b]").unwrap().warnings.is_empty());
+    }
+}
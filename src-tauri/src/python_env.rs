@@ -0,0 +1,331 @@
+// Python environment discovery and validation
+//
+// The backend needs an interpreter with TensorFlow and friends, usually the
+// Poetry venv in `backends/` or the mise-managed Python. Candidates are collected
+// from the settings, Poetry, mise and PATH; probing one runs a short import check
+// in it and reports missing or broken packages, including the tf-nightly /
+// keras-nightly mismatch where `keras.__version__` is missing.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::process::Command;
+
+// Importing TensorFlow for the first time can take a while
+const PROBE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Python version pinned by backends/mise.toml and pyproject.toml
+const EXPECTED_PYTHON: &str = "3.11";
+
+// Modules the backend imports, in import order (module name, package to install)
+const REQUIRED_MODULES: [(&str, &str); 9] = [
+    ("numpy", "numpy"),
+    ("PIL", "pillow"),
+    ("cv2", "opencv-python"),
+    ("scipy", "scipy"),
+    ("tqdm", "tqdm"),
+    ("regex", "regex"),
+    ("ftfy", "ftfy"),
+    ("keras", "keras-nightly"),
+    ("tensorflow", "tf-nightly"),
+];
+
+// Interpreter discovery picked, per backends folder, for the rest of the
+// session: discovery runs Poetry and mise, too slow to repeat for every job
+static DISCOVERED: Mutex<BTreeMap<Option<PathBuf>, PathBuf>> = Mutex::new(BTreeMap::new());
+
+const PROBE_MARKER: &str = "DIFFUSIONBEE_PROBE ";
+
+const PROBE_SCRIPT: &str = r#"
+import importlib, json, sys
+report = {"python_version": "%d.%d.%d" % sys.version_info[:3], "executable": sys.executable, "modules": {}}
+for name in sys.argv[1:]:
+    try:
+        module = importlib.import_module(name)
+        report["modules"][name] = {"version": getattr(module, "__version__", None), "error": None}
+    except BaseException as e:
+        report["modules"][name] = {"version": None, "error": "%s: %s" % (type(e).__name__, e)}
+print("DIFFUSIONBEE_PROBE " + json.dumps(report))
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpreterSource {
+    Configured,
+    PoetryVenv,
+    Mise,
+    Path,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PythonCandidate {
+    pub path: PathBuf,
+    pub source: InterpreterSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleStatus {
+    pub module: String,
+    pub package: String,
+    pub version: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PythonProbe {
+    pub path: PathBuf,
+    pub source: InterpreterSource,
+    pub python_version: Option<String>,
+    pub modules: Vec<ModuleStatus>,
+    /// Problems that stop the backend from running
+    pub problems: Vec<String>,
+    /// Things that may work but are not the supported setup
+    pub warnings: Vec<String>,
+    pub usable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeReport {
+    python_version: String,
+    modules: BTreeMap<String, ProbeModule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeModule {
+    version: Option<String>,
+    error: Option<String>,
+}
+
+/// Candidate interpreters in order of preference, without duplicates.
+/// `backends_dir` is the `backends/` folder holding the Poetry and mise config.
+pub async fn discover_interpreters(configured: &str, backends_dir: Option<&Path>) -> Vec<PythonCandidate> {
+    let mut candidates = Vec::new();
+
+    if !configured.trim().is_empty() {
+        candidates.push(PythonCandidate {
+            path: PathBuf::from(configured.trim()),
+            source: InterpreterSource::Configured,
+        });
+    }
+
+    if let Some(backends_dir) = backends_dir {
+        // In-project venv first (`virtualenvs.in-project`), then Poetry's cache
+        let mut venvs = vec![backends_dir.join(".venv")];
+        if let Some(venv) = command_output("poetry", &["env", "info", "--path"], backends_dir).await {
+            venvs.push(PathBuf::from(venv));
+        }
+        for venv in venvs {
+            candidates.push(PythonCandidate {
+                path: venv_python(&venv),
+                source: InterpreterSource::PoetryVenv,
+            });
+        }
+
+        if let Some(python) = command_output("mise", &["which", "python3"], backends_dir).await {
+            candidates.push(PythonCandidate {
+                path: PathBuf::from(python),
+                source: InterpreterSource::Mise,
+            });
+        }
+    }
+    if let Some(shims) = mise_shims_dir() {
+        candidates.push(PythonCandidate {
+            path: shims.join(python_file_name("python3")),
+            source: InterpreterSource::Mise,
+        });
+    }
+
+    for name in ["python3", "python"] {
+        if let Some(path) = find_on_path(name) {
+            candidates.push(PythonCandidate {
+                path,
+                source: InterpreterSource::Path,
+            });
+        }
+    }
+
+    // Configured paths are kept even if missing so the probe can say so
+    let mut seen = HashSet::new();
+    candidates.retain(|c| {
+        (c.source == InterpreterSource::Configured || c.path.is_file())
+            && seen.insert(c.path.canonicalize().unwrap_or_else(|_| c.path.clone()))
+    });
+    candidates
+}
+
+/// The interpreter to launch the backend with: the configured one, otherwise the
+/// first candidate found, which is remembered until it disappears. Falls back to
+/// `python3` from PATH.
+pub async fn resolve_interpreter(configured: &str, backends_dir: Option<&Path>) -> PathBuf {
+    if !configured.trim().is_empty() {
+        return PathBuf::from(configured.trim());
+    }
+    let key = backends_dir.map(Path::to_path_buf);
+    let cached = DISCOVERED.lock().ok().and_then(|cache| cache.get(&key).cloned());
+    if let Some(path) = cached.filter(|path| path.is_file()) {
+        return path;
+    }
+
+    match discover_interpreters(configured, backends_dir).await.into_iter().next() {
+        Some(candidate) => {
+            log::info!("Using Python interpreter {} for this session", candidate.path.display());
+            if let Ok(mut cache) = DISCOVERED.lock() {
+                cache.insert(key, candidate.path.clone());
+            }
+            candidate.path
+        }
+        // Not remembered, so an interpreter installed later is found
+        None => PathBuf::from("python3"),
+    }
+}
+
+/// Runs the import check in `candidate` and interprets the result
+pub async fn probe_interpreter(candidate: &PythonCandidate) -> PythonProbe {
+    let mut probe = PythonProbe {
+        path: candidate.path.clone(),
+        source: candidate.source,
+        python_version: None,
+        modules: Vec::new(),
+        problems: Vec::new(),
+        warnings: Vec::new(),
+        usable: false,
+    };
+
+    if !candidate.path.is_file() {
+        probe.problems.push(format!("Interpreter not found at {}", candidate.path.display()));
+        return probe;
+    }
+
+    let report = match run_probe(&candidate.path).await {
+        Ok(report) => report,
+        Err(e) => {
+            probe.problems.push(e);
+            return probe;
+        }
+    };
+
+    if !report.python_version.starts_with(&format!("{}.", EXPECTED_PYTHON)) {
+        probe.warnings.push(format!(
+            "Python {} found, the backend is set up for Python {}",
+            report.python_version, EXPECTED_PYTHON
+        ));
+    }
+    probe.python_version = Some(report.python_version);
+
+    for (module, package) in REQUIRED_MODULES {
+        let status = report.modules.get(module);
+        let error = status.and_then(|s| s.error.clone());
+        let version = status.and_then(|s| s.version.clone());
+
+        match &error {
+            Some(e) if e.starts_with("ModuleNotFoundError") => {
+                probe.problems.push(format!("Missing package {} (import {})", package, module));
+            }
+            Some(e) if e.contains("__version__") => {
+                probe.problems.push(format!(
+                    "{} fails to import because keras has no __version__ (tf-nightly / keras-nightly mismatch): {}",
+                    module, e
+                ));
+            }
+            Some(e) => probe.problems.push(format!("Importing {} failed: {}", module, e)),
+            None if module == "keras" && version.is_none() => {
+                probe.problems.push(
+                    "keras has no __version__, which TensorFlow requires (tf-nightly / keras-nightly mismatch)"
+                        .to_string(),
+                );
+            }
+            None => {}
+        }
+
+        probe.modules.push(ModuleStatus {
+            module: module.to_string(),
+            package: package.to_string(),
+            version,
+            error,
+        });
+    }
+
+    probe.usable = probe.problems.is_empty();
+    probe
+}
+
+async fn run_probe(python: &Path) -> Result<ProbeReport, String> {
+    let mut command = Command::new(python);
+    command
+        .arg("-c")
+        .arg(PROBE_SCRIPT)
+        .args(REQUIRED_MODULES.iter().map(|(module, _)| module))
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let output = tokio::time::timeout(PROBE_TIMEOUT, command.output())
+        .await
+        .map_err(|_| format!("{} did not finish the import check in time", python.display()))?
+        .map_err(|e| format!("Failed to run {}: {}", python.display(), e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let json = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix(PROBE_MARKER))
+        .ok_or_else(|| {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            format!("{} could not run the import check: {}", python.display(), stderr.trim())
+        })?;
+    serde_json::from_str(json).map_err(|e| format!("Unreadable import check output: {}", e))
+}
+
+// First line of a tool's stdout, if the tool exists and succeeds
+async fn command_output(program: &str, args: &[&str], dir: &Path) -> Option<String> {
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        Command::new(program)
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let line = String::from_utf8_lossy(&output.stdout).lines().next()?.trim().to_string();
+    (!line.is_empty()).then_some(line)
+}
+
+fn venv_python(venv: &Path) -> PathBuf {
+    if cfg!(windows) {
+        venv.join("Scripts").join("python.exe")
+    } else {
+        venv.join("bin").join("python")
+    }
+}
+
+fn python_file_name(name: &str) -> String {
+    if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// mise uses ~/.local/share on every platform unless told otherwise
+fn mise_shims_dir() -> Option<PathBuf> {
+    if let Some(data_dir) = std::env::var_os("MISE_DATA_DIR") {
+        return Some(PathBuf::from(data_dir).join("shims"));
+    }
+    dirs::home_dir().map(|home| home.join(".local").join("share").join("mise").join("shims"))
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(python_file_name(name)))
+        .find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,195 @@
+// Running a history entry's request again
+//
+// `regenerate_from_history` takes the request an entry was made with, down to
+// the seed, scheduler and LoRAs, and runs it again with the entry's model. The
+// model and LoRA files are hashed first and compared with the hashes recorded
+// with the entry: a file that changed since is reported as a warning, because
+// the new images can't be expected to match then.
+//
+// With `compare` each new image is diffed pixel by pixel against the one it
+// reproduces, which tells whether the backend is deterministic on this machine.
+// Both are compared as decoded RGB, so identical lossy encodings of the same
+// pixels still count as identical.
+
+use crate::history::{DerivationKind, FileHashes, HistoryEntry};
+use crate::model_registry::short_hash;
+use crate::{logging, AppSettings, DiffusionError, ImageGenerationRequest, ImageGenerationResponse};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+pub struct Regeneration {
+    /// The entry that was reproduced
+    pub original_id: String,
+    /// The request as it was run again
+    pub request: ImageGenerationRequest,
+    /// The new job, with its own history entry and any warnings
+    pub response: ImageGenerationResponse,
+    /// Original and new image side by side, only when comparing
+    pub diffs: Vec<ImageDiff>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageDiff {
+    pub original: String,
+    pub regenerated: String,
+    pub identical: bool,
+    /// Pixels with at least one channel different; all of them when the sizes differ
+    pub differing_pixels: u64,
+    pub total_pixels: u64,
+    /// Largest difference in one channel, 0 to 255
+    pub max_difference: u8,
+    /// Mean absolute difference over every channel
+    pub mean_difference: f64,
+}
+
+/// Runs the request of `entry` again with its model and, with `compare`, diffs
+/// the new images against the old ones
+pub async fn regenerate(
+    entry: &HistoryEntry,
+    current: &AppSettings,
+    compare: bool,
+) -> Result<Regeneration, DiffusionError> {
+    if let Some(derivation) = &entry.derivation {
+        if let DerivationKind::Upscale { .. } = derivation.kind {
+            return Err(DiffusionError::validation(
+                "history_id",
+                format!("{} is an upscale, regenerate {} instead", entry.id, derivation.parent_id),
+            ));
+        }
+    }
+    let settings = entry.settings(current)?;
+    let mut warnings = check_files(entry, &settings).await;
+
+    let request = entry.request.clone();
+    log::info!("Regenerating {} with seed {:?}", entry.id, request.seed);
+    let job = crate::generate_in_job(request.clone(), &settings, None);
+    let mut response = logging::with_job(logging::new_job_id("regen"), job).await?;
+
+    let mut diffs = Vec::new();
+    if compare {
+        for (original, regenerated) in entry.images.iter().zip(&response.images) {
+            if !Path::new(original).is_file() {
+                warnings.push(format!("Can't compare with {}, the file is gone", original));
+                continue;
+            }
+            match diff_images(original, regenerated).await {
+                Ok(diff) => {
+                    if !diff.identical {
+                        log::warn!(
+                            "{} differs from {}: {} of {} pixels, up to {} levels",
+                            regenerated,
+                            original,
+                            diff.differing_pixels,
+                            diff.total_pixels,
+                            diff.max_difference
+                        );
+                    }
+                    diffs.push(diff);
+                }
+                Err(e) => warnings.push(format!("Failed to compare with {}: {}", original, e.message())),
+            }
+        }
+        if !diffs.is_empty() && diffs.iter().all(|diff| diff.identical) {
+            log::info!("Regenerated images are identical to {}", entry.id);
+        }
+    }
+
+    warnings.append(&mut response.warnings);
+    response.warnings = warnings;
+    Ok(Regeneration {
+        original_id: entry.id.clone(),
+        request,
+        response,
+        diffs,
+    })
+}
+
+// Warnings for model and LoRA files that aren't the ones the entry was made with
+async fn check_files(entry: &HistoryEntry, settings: &AppSettings) -> Vec<String> {
+    let recorded = &entry.hashes;
+    let current = match FileHashes::of(&settings.model_path, &entry.request.loras).await {
+        Ok(hashes) => hashes,
+        Err(e) => return vec![format!("Can't check the model files: {}", e.message())],
+    };
+
+    let mut warnings = Vec::new();
+    match (&recorded.model, &current.model) {
+        (Some(before), Some(now)) if before != now => warnings.push(format!(
+            "Model {} has changed since {} was made (hash {} is now {})",
+            settings.model_path,
+            entry.id,
+            short_hash(before),
+            short_hash(now)
+        )),
+        (None, Some(_)) => warnings.push(format!("{} has no model hash to check the model against", entry.id)),
+        _ => {}
+    }
+    for (path, before) in &recorded.loras {
+        match current.loras.get(path) {
+            Some(now) if now != before => warnings.push(format!(
+                "LoRA {} has changed since {} was made (hash {} is now {})",
+                path,
+                entry.id,
+                short_hash(before),
+                short_hash(now)
+            )),
+            _ => {}
+        }
+    }
+    for warning in &warnings {
+        log::warn!("{}", warning);
+    }
+    warnings
+}
+
+/// Compares two images pixel by pixel
+pub async fn diff_images(original: &str, regenerated: &str) -> Result<ImageDiff, DiffusionError> {
+    let (original, regenerated) = (original.to_string(), regenerated.to_string());
+    tokio::task::spawn_blocking(move || {
+        let open = |path: &str| {
+            image::open(path)
+                .map(|image| image.to_rgb8())
+                .map_err(|e| DiffusionError::FileSystem(format!("Failed to read {}: {}", path, e)))
+        };
+        let (a, b) = (open(&original)?, open(&regenerated)?);
+        let total_pixels = a.width() as u64 * a.height() as u64;
+
+        if a.dimensions() != b.dimensions() {
+            return Ok(ImageDiff {
+                original,
+                regenerated,
+                identical: false,
+                differing_pixels: total_pixels,
+                total_pixels,
+                max_difference: u8::MAX,
+                mean_difference: u8::MAX as f64,
+            });
+        }
+
+        let mut differing_pixels = 0;
+        let mut max_difference = 0;
+        let mut sum = 0u64;
+        for (pa, pb) in a.pixels().zip(b.pixels()) {
+            let mut differs = false;
+            for (ca, cb) in pa.0.iter().zip(pb.0) {
+                let difference = ca.abs_diff(cb);
+                differs |= difference > 0;
+                max_difference = max_difference.max(difference);
+                sum += difference as u64;
+            }
+            differing_pixels += differs as u64;
+        }
+        Ok(ImageDiff {
+            original,
+            regenerated,
+            identical: differing_pixels == 0,
+            differing_pixels,
+            total_pixels,
+            max_difference,
+            mean_difference: sum as f64 / (total_pixels * 3).max(1) as f64,
+        })
+    })
+    .await
+    .map_err(|e| DiffusionError::Internal(format!("Comparing images failed: {}", e)))?
+}
@@ -0,0 +1,282 @@
+// Request limits per model family
+//
+// What a request may ask for depends on the model: SD 1.x is trained at 512px,
+// SD 2.x at 768px and SDXL at 1024px with sizes in steps of 64. The active
+// model's family is read from its .tdict header (the converter's ctdict id),
+// falling back to its file name, and picks the profile that `parameter_schema`
+// turns into the rules for requests and settings.
+//
+// Every UNet downsamples the latent, which is 1/8 of the image, so sizes must
+// be multiples of 8 at least; SDXL's extra downsampling stage needs 64. The
+// `validation_overrides` setting changes any limit, or the family itself, for
+// models the detection gets wrong.
+
+use crate::model_registry::{self, TdictHeader};
+use crate::DiffusionError;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// ctdict ids written by backends/model_converter/sd_shapes.py
+const SD1_CTDICT_IDS: [i64; 5] = [12, 13, 1012, 1013, 1014];
+const SD2_CTDICT_IDS: [i64; 2] = [15, 1015];
+const SDXL_CTDICT_IDS: [i64; 1] = [3031];
+
+// Smallest size step any UNet accepts
+const LATENT_FACTOR: u32 = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelFamily {
+    #[default]
+    Sd1,
+    Sd2,
+    Sdxl,
+}
+
+impl ModelFamily {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            ModelFamily::Sd1 => "SD 1.x",
+            ModelFamily::Sd2 => "SD 2.x",
+            ModelFamily::Sdxl => "SDXL",
+        }
+    }
+
+    fn from_header(header: &TdictHeader) -> Option<Self> {
+        let id = header.ctdict_version;
+        if SD1_CTDICT_IDS.contains(&id) {
+            return Some(ModelFamily::Sd1);
+        }
+        if SD2_CTDICT_IDS.contains(&id) {
+            return Some(ModelFamily::Sd2);
+        }
+        if SDXL_CTDICT_IDS.contains(&id) {
+            return Some(ModelFamily::Sdxl);
+        }
+        // Older converters only wrote `sd_type` into the metadata
+        match header.metadata["sd_type"].as_str()? {
+            sd_type if sd_type.starts_with("SD_1x") => Some(ModelFamily::Sd1),
+            sd_type if sd_type.starts_with("SD_2x") => Some(ModelFamily::Sd2),
+            sd_type if sd_type.to_lowercase().starts_with("sdxl") => Some(ModelFamily::Sdxl),
+            _ => None,
+        }
+    }
+
+    fn from_file_name(model_path: &Path) -> Option<Self> {
+        let name = model_path.file_stem()?.to_string_lossy().to_lowercase();
+        if name.contains("sdxl") || name.contains("sd_xl") || name.contains("-xl") || name.contains("_xl") {
+            Some(ModelFamily::Sdxl)
+        } else if name.contains("v2") || name.contains("sd2") || name.contains("2-1") || name.contains("2_1") {
+            Some(ModelFamily::Sd2)
+        } else {
+            None
+        }
+    }
+
+    /// The family of the model at `model_path`, SD 1.x when it can't be told
+    pub fn detect(model_path: &str) -> Self {
+        if model_path.is_empty() {
+            return ModelFamily::default();
+        }
+        let path = Path::new(model_path);
+        model_registry::read_header(path)
+            .ok()
+            .and_then(|header| Self::from_header(&header))
+            .or_else(|| Self::from_file_name(path))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AspectPreset {
+    /// e.g. `3:2`
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationProfile {
+    pub family: ModelFamily,
+    pub min_size: u32,
+    pub max_size: u32,
+    /// Width and height must be multiples of this
+    pub size_multiple: u32,
+    /// The resolution the family was trained at
+    pub default_width: u32,
+    pub default_height: u32,
+    pub default_steps: u32,
+    pub default_guidance: f32,
+    pub min_steps: u32,
+    pub max_steps: u32,
+    pub min_guidance: f32,
+    pub max_guidance: f32,
+    pub max_images: u32,
+    pub aspect_presets: Vec<AspectPreset>,
+}
+
+/// Limits from the `validation_overrides` setting; unset fields keep the profile's
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidationOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family: Option<ModelFamily>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_multiple: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_guidance: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_guidance: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_images: Option<u32>,
+}
+
+fn presets(sizes: &[(&str, u32, u32)]) -> Vec<AspectPreset> {
+    sizes
+        .iter()
+        .map(|&(name, width, height)| AspectPreset {
+            name: name.to_string(),
+            width,
+            height,
+        })
+        .collect()
+}
+
+impl ValidationProfile {
+    /// The built-in limits for `family`
+    pub fn for_family(family: ModelFamily) -> Self {
+        match family {
+            ModelFamily::Sd1 => Self {
+                family,
+                min_size: 256,
+                max_size: 1024,
+                size_multiple: 8,
+                default_width: 512,
+                default_height: 512,
+                default_steps: 20,
+                default_guidance: 7.5,
+                min_steps: 10,
+                max_steps: 50,
+                min_guidance: 1.0,
+                max_guidance: 20.0,
+                max_images: 10,
+                aspect_presets: presets(&[
+                    ("1:1", 512, 512),
+                    ("3:2", 768, 512),
+                    ("2:3", 512, 768),
+                    ("4:3", 640, 480),
+                    ("3:4", 480, 640),
+                    ("16:9", 768, 432),
+                    ("9:16", 432, 768),
+                ]),
+            },
+            ModelFamily::Sd2 => Self {
+                family,
+                min_size: 256,
+                max_size: 1024,
+                size_multiple: 8,
+                default_width: 768,
+                default_height: 768,
+                default_steps: 20,
+                default_guidance: 7.5,
+                min_steps: 10,
+                max_steps: 50,
+                min_guidance: 1.0,
+                max_guidance: 20.0,
+                max_images: 10,
+                aspect_presets: presets(&[
+                    ("1:1", 768, 768),
+                    ("3:2", 768, 512),
+                    ("2:3", 512, 768),
+                    ("4:3", 768, 576),
+                    ("3:4", 576, 768),
+                    ("16:9", 1024, 576),
+                    ("9:16", 576, 1024),
+                ]),
+            },
+            ModelFamily::Sdxl => Self {
+                family,
+                min_size: 640,
+                max_size: 2048,
+                size_multiple: 64,
+                default_width: 1024,
+                default_height: 1024,
+                default_steps: 30,
+                default_guidance: 7.0,
+                min_steps: 10,
+                max_steps: 50,
+                min_guidance: 1.0,
+                max_guidance: 20.0,
+                max_images: 4,
+                aspect_presets: presets(&[
+                    ("1:1", 1024, 1024),
+                    ("3:2", 1216, 832),
+                    ("2:3", 832, 1216),
+                    ("4:3", 1152, 896),
+                    ("3:4", 896, 1152),
+                    ("16:9", 1344, 768),
+                    ("9:16", 768, 1344),
+                    ("21:9", 1536, 640),
+                ]),
+            },
+        }
+    }
+
+    /// The profile for the model at `model_path` with `overrides` applied
+    pub fn resolve(model_path: &str, overrides: &ValidationOverrides) -> Self {
+        let family = overrides.family.unwrap_or_else(|| ModelFamily::detect(model_path));
+        Self::for_family(family).with_overrides(overrides)
+    }
+
+    pub fn with_overrides(mut self, overrides: &ValidationOverrides) -> Self {
+        self.min_size = overrides.min_size.unwrap_or(self.min_size);
+        self.max_size = overrides.max_size.unwrap_or(self.max_size);
+        self.size_multiple = overrides.size_multiple.unwrap_or(self.size_multiple);
+        self.min_steps = overrides.min_steps.unwrap_or(self.min_steps);
+        self.max_steps = overrides.max_steps.unwrap_or(self.max_steps);
+        self.min_guidance = overrides.min_guidance.unwrap_or(self.min_guidance);
+        self.max_guidance = overrides.max_guidance.unwrap_or(self.max_guidance);
+        self.max_images = overrides.max_images.unwrap_or(self.max_images);
+        // Presets that no longer fit are of no use
+        let (min, max, multiple) = (self.min_size, self.max_size, self.size_multiple);
+        self.aspect_presets.retain(|preset| {
+            [preset.width, preset.height]
+                .iter()
+                .all(|&side| (min..=max).contains(&side) && side.is_multiple_of(multiple))
+        });
+        self
+    }
+}
+
+/// Checks that the limits, after overrides, still make sense together
+pub fn validate_limits(profile: &ValidationProfile) -> Result<(), DiffusionError> {
+    let invalid = |message: &str| DiffusionError::validation("validation_overrides", message);
+
+    if profile.size_multiple == 0 || !profile.size_multiple.is_multiple_of(LATENT_FACTOR) {
+        return Err(invalid("size_multiple must be a multiple of 8"));
+    }
+    if !profile.min_size.is_multiple_of(profile.size_multiple) || profile.min_size == 0 {
+        return Err(invalid("min_size must be a positive multiple of size_multiple"));
+    }
+    if profile.min_size > profile.max_size {
+        return Err(invalid("min_size must be at most max_size"));
+    }
+    if profile.min_steps == 0 || profile.min_steps > profile.max_steps {
+        return Err(invalid("min_steps must be at least 1 and at most max_steps"));
+    }
+    if !(profile.min_guidance > 0.0 && profile.min_guidance <= profile.max_guidance) {
+        return Err(invalid("min_guidance must be above 0 and at most max_guidance"));
+    }
+    if profile.max_images == 0 {
+        return Err(invalid("max_images must be at least 1"));
+    }
+    Ok(())
+}
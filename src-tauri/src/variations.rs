@@ -0,0 +1,133 @@
+// Variations of a history entry's image
+//
+// A variation runs the request behind an image again with the same prompt,
+// seed and model, and replaces part of the seed's starting noise with noise
+// from a `small_mod_seed`. `strength` is the share replaced: small values give
+// close siblings of the original, 1.0 an unrelated image. Every variation is a
+// job and history entry of its own, derived from the entry it varies.
+//
+// The backend offsets the seed of each image in a job by 1234 times its index
+// (`prepare_seed`), so the variation of a job's third image runs as a job of
+// one image with that offset seed. Varying a variation keeps its seed and picks
+// new noise to mix in, so the result is a sibling rather than a child.
+
+use crate::history::{self, Derivation, DerivationKind, HistoryEntry};
+use crate::{logging, AppSettings, DiffusionError, ImageGenerationRequest, ImageGenerationResponse};
+use serde::Deserialize;
+
+pub const MAX_VARIATIONS: u32 = 16;
+/// What the backend mixes in when given a `small_mod_seed` without a strength
+pub const DEFAULT_STRENGTH: f32 = 0.01;
+
+// Seed offset between consecutive images of a job in the backend
+const BACKEND_SEED_STRIDE: u32 = 1234;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct VariationOptions {
+    /// Share of the noise that changes, above 0 and at most 1
+    pub strength: f32,
+    /// Number of variations, each a job of its own
+    #[serde(default = "default_count")]
+    pub count: u32,
+}
+
+fn default_count() -> u32 {
+    4
+}
+
+impl VariationOptions {
+    pub fn validate(&self) -> Result<(), DiffusionError> {
+        if !(self.strength > 0.0 && self.strength <= 1.0) {
+            return Err(DiffusionError::validation(
+                "strength",
+                "Variation strength must be above 0.0 and at most 1.0",
+            ));
+        }
+        if !(1..=MAX_VARIATIONS).contains(&self.count) {
+            return Err(DiffusionError::validation(
+                "count",
+                format!("Number of variations must be between 1 and {}", MAX_VARIATIONS),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Generates `options.count` variations of `source`, one of `entry`'s images,
+/// one after the other. Each is recorded as a derivative of `entry`. When the
+/// jobs are cancelled the variations finished so far are returned.
+pub async fn generate_variations(
+    entry: &HistoryEntry,
+    source: &str,
+    options: VariationOptions,
+    settings: &AppSettings,
+) -> Result<Vec<ImageGenerationResponse>, DiffusionError> {
+    options.validate()?;
+    let settings = entry.settings(settings)?;
+    let request = source_request(entry, source)?;
+
+    let first_seed = crate::random_seed();
+    let mut responses = Vec::new();
+    for i in 0..options.count {
+        let small_mod_seed = first_seed.wrapping_add(i);
+        let request = ImageGenerationRequest {
+            small_mod_seed: Some(small_mod_seed),
+            small_mod_strength: Some(options.strength),
+            ..request.clone()
+        };
+        let derivation = Derivation {
+            parent_id: entry.id.clone(),
+            source_image: source.to_string(),
+            kind: DerivationKind::Variation {
+                small_mod_seed,
+                strength: options.strength,
+            },
+        };
+        log::info!("Variation {} of {} for {} (small_mod_seed {})", i + 1, options.count, source, small_mod_seed);
+
+        let job = crate::generate_in_job(request, &settings, Some(derivation));
+        match logging::with_job(logging::new_job_id("var"), job).await {
+            Ok(response) => responses.push(response),
+            Err(DiffusionError::Cancelled) if !responses.is_empty() => {
+                log::info!("Variations cancelled after {} of {}", responses.len(), options.count);
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(responses)
+}
+
+/// The request that reproduces the noise of `source` on its own
+fn source_request(entry: &HistoryEntry, source: &str) -> Result<ImageGenerationRequest, DiffusionError> {
+    // Upscales keep their parent's request, the image they came from says which seed
+    let mut generation = entry.clone();
+    let mut image = source.to_string();
+    while let Some(Derivation {
+        parent_id,
+        source_image,
+        kind: DerivationKind::Upscale { .. },
+    }) = &generation.derivation
+    {
+        image = source_image.clone();
+        generation = history::get(parent_id)?;
+    }
+
+    let seed = generation
+        .request
+        .seed
+        .ok_or_else(|| DiffusionError::validation("history_id", "History entry has no seed to vary"))?;
+    let index = generation.images.iter().position(|i| *i == image).unwrap_or(0) as u32;
+    // A variation's own offset went into its small_mod_seed, which is replaced
+    let seed = if generation.request.small_mod_seed.is_some() {
+        seed
+    } else {
+        seed.wrapping_add(BACKEND_SEED_STRIDE.wrapping_mul(index))
+    };
+
+    Ok(ImageGenerationRequest {
+        num_imgs: 1,
+        seed: Some(seed),
+        ..generation.request
+    })
+}
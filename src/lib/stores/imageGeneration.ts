@@ -9,17 +9,385 @@ export interface GenerationParams {
     num_imgs: number;
     num_inference_steps: number;
     guidance_scale: number;
+    seed?: number;
+    small_mod_seed?: number;
+    small_mod_strength?: number;
+    scheduler?: Scheduler | null;
+    loras?: LoraWeight[];
+}
+
+export type Scheduler = 'ddim' | 'ddim_v' | 'lmsd' | 'pndm' | 'k_euler_ancestral' | 'k_euler' | 'karras';
+
+export interface LoraWeight {
+    path: string;
+    weight: number;
 }
 
 export interface GenerationProgress {
     current_step: number;
     total_steps: number;
     status: string;
+    job_id?: string | null;
 }
 
 export interface GenerationResult {
     generated_img_path: string;
     aux_output_image_path?: string;
+    prompt: string;
+    seed?: number;
+    images?: string[];
+    job_id?: string | null;
+    history_id?: string | null;
+    derived_images?: string[];
+}
+
+// Structured error returned by every Tauri command
+export interface CommandError {
+    kind: 'python_backend' | 'file_system' | 'json' | 'process' | 'validation' | 'model_not_found' | 'cancelled' | 'internal';
+    message: string;
+    retryable: boolean;
+    details: {
+        field?: string | null;
+        traceback?: string | null;
+        model_path?: string;
+    };
+}
+
+export function isCommandError(err: unknown): err is CommandError {
+    return typeof err === 'object' && err !== null && 'kind' in err && 'message' in err;
+}
+
+export function errorMessage(err: unknown): string {
+    if (isCommandError(err)) return err.message;
+    return String(err);
+}
+
+// One entry from `get_logs`
+export interface LogEntry {
+    timestamp: string;
+    level: 'error' | 'warn' | 'info' | 'debug' | 'trace';
+    source: 'app' | 'backend';
+    stream?: 'stdout' | 'stderr';
+    job_id?: string;
+    target: string;
+    message: string;
+}
+
+export interface LogFilter {
+    level?: LogEntry['level'];
+    job_id?: string;
+    source?: LogEntry['source'];
+    since?: string;
+    until?: string;
+    limit?: number;
+}
+
+export function getLogs(filter: LogFilter = {}): Promise<LogEntry[]> {
+    return invoke<LogEntry[]>('get_logs', { filter });
+}
+
+export interface DiagnosticsBundle {
+    path: string;
+    size_bytes: number;
+    files: string[];
+}
+
+export function exportDiagnostics(options: { redact_paths?: boolean; output_path?: string } = {}): Promise<DiagnosticsBundle> {
+    return invoke<DiagnosticsBundle>('export_diagnostics', { options });
+}
+
+export type ModelFamily = 'sd1' | 'sd2' | 'sdxl';
+
+// Limits of the active model's family, see `get_validation_profile`
+export interface ValidationProfile {
+    family: ModelFamily;
+    min_size: number;
+    max_size: number;
+    size_multiple: number;
+    default_width: number;
+    default_height: number;
+    min_steps: number;
+    max_steps: number;
+    min_guidance: number;
+    max_guidance: number;
+    max_images: number;
+    aspect_presets: AspectPreset[];
+}
+
+export interface AspectPreset {
+    name: string;
+    width: number;
+    height: number;
+}
+
+export interface ParameterSpec {
+    min: number;
+    max: number;
+    step: number;
+    default: number;
+    integer: boolean;
+    // Values must be multiples of `step` (image sizes), not just in range
+    step_enforced: boolean;
+}
+
+// The rules `generate_image` and `save_settings` enforce, see `get_parameter_schema`
+export interface ParameterSchema {
+    family: ModelFamily;
+    img_width: ParameterSpec;
+    img_height: ParameterSpec;
+    num_imgs: ParameterSpec;
+    num_inference_steps: ParameterSpec;
+    guidance_scale: ParameterSpec;
+    aspect_presets: AspectPreset[];
+}
+
+export function getParameterSchema(): Promise<ParameterSchema> {
+    return invoke<ParameterSchema>('get_parameter_schema');
+}
+
+export type ValidationOverrides = Partial<Omit<ValidationProfile, 'default_width' | 'default_height' | 'aspect_presets'>>;
+
+export function getValidationProfile(): Promise<ValidationProfile> {
+    return invoke<ValidationProfile>('get_validation_profile');
+}
+
+export type UpscaleMethod = 'lanczos' | 'model';
+
+export interface UpscaleOptions {
+    scale: number;
+    method?: UpscaleMethod;
+}
+
+export type PostProcessStage = { stage: 'upscale' } & UpscaleOptions;
+
+// One generation, or an image derived from another entry's image
+export interface HistoryEntry {
+    id: string;
+    created: string;
+    request: GenerationParams & { negative_prompt?: string };
+    model: string;
+    model_path?: string;
+    // SHA-256 of the model and of each LoRA by path
+    hashes?: { model?: string; loras?: Record<string, string> };
+    images: string[];
+    job_id?: string | null;
+    derivation?: {
+        parent_id: string;
+        source_image: string;
+    } & (
+        | { kind: 'upscale'; method: UpscaleMethod; scale: number }
+        | { kind: 'variation'; small_mod_seed: number; strength: number }
+    );
+    // Lowercase, sorted
+    tags?: string[];
+    favorite?: boolean;
+    // 1 to 5 stars
+    rating?: number | null;
+    // Set for images imported from elsewhere
+    imported?: { content_hash: string; metadata: boolean; thumbnail: string } | null;
+}
+
+export function getHistoryEntry(historyId: string): Promise<HistoryEntry> {
+    return invoke<HistoryEntry>('get_history_entry', { historyId });
+}
+
+export type HistorySort = 'newest' | 'oldest' | 'prompt' | 'model';
+
+// Every set field must match. `text` words must all be in the prompt, the last
+// may be unfinished; "quoted phrases" match as written. Dates are RFC 3339 or YYYY-MM-DD.
+export interface HistoryQuery {
+    text?: string;
+    model?: string;
+    since?: string;
+    until?: string;
+    seed?: number;
+    width?: number;
+    height?: number;
+    steps?: number;
+    scheduler?: Scheduler;
+    derived?: boolean;
+    imported?: boolean;
+    // Entries must have all of these
+    tags?: string[];
+    favorite?: boolean;
+    min_rating?: number;
+    collection?: string;
+    sort?: HistorySort;
+    limit?: number;
+    offset?: number;
+}
+
+export interface HistoryPage {
+    total: number;
+    entries: HistoryEntry[];
+}
+
+export function queryHistory(query: HistoryQuery): Promise<HistoryPage> {
+    return invoke<HistoryPage>('query_history', { query });
+}
+
+export function getDerivatives(historyId: string): Promise<HistoryEntry[]> {
+    return invoke<HistoryEntry[]>('get_derivatives', { historyId });
+}
+
+export function upscaleImage(historyId: string, options: UpscaleOptions, image?: string): Promise<HistoryEntry> {
+    return invoke<HistoryEntry>('upscale_image', { historyId, image, options });
+}
+
+export interface VariationOptions {
+    // Share of the starting noise that changes, above 0 and at most 1
+    strength: number;
+    count?: number;
+}
+
+export function generateVariations(
+    historyId: string,
+    options: VariationOptions,
+    image?: string,
+): Promise<GenerationResult[]> {
+    return invoke<GenerationResult[]>('generate_variations', { historyId, image, options });
+}
+
+export interface ImageDiff {
+    original: string;
+    regenerated: string;
+    identical: boolean;
+    differing_pixels: number;
+    total_pixels: number;
+    max_difference: number;
+    mean_difference: number;
+}
+
+export interface Regeneration {
+    original_id: string;
+    request: GenerationParams & { negative_prompt?: string };
+    response: GenerationResult & { warnings?: string[] };
+    diffs: ImageDiff[];
+}
+
+// A named style; `{prompt}` in the template marks where the user's prompt goes
+export interface Preset {
+    name: string;
+    description?: string;
+    prompt_template?: string;
+    negative_prompt?: string;
+    parameters?: Partial<Pick<GenerationParams, 'img_width' | 'img_height' | 'num_inference_steps' | 'guidance_scale' | 'scheduler' | 'loras'>>;
+}
+
+export interface PresetImportSummary {
+    added: string[];
+    replaced: string[];
+    skipped: string[];
+}
+
+export const presetActions = {
+    list: () => invoke<Preset[]>('list_presets'),
+    save: (preset: Preset, previousName?: string) => invoke<Preset>('save_preset', { preset, previousName }),
+    delete: (name: string) => invoke<void>('delete_preset', { name }),
+    // Merges the presets into the request in the given order
+    apply: (request: GenerationParams, names: string[]) =>
+        invoke<GenerationParams>('apply_presets', { request, names }),
+    exportPack: (path: string, names: string[] = []) => invoke<number>('export_presets', { names, path }),
+    importPack: (path: string, replace = false) => invoke<PresetImportSummary>('import_presets', { path, replace }),
+};
+
+export interface PromptRecord {
+    prompt: string;
+    use_count: number;
+    last_used: string;
+}
+
+export interface PromptSuggestion {
+    kind: 'prompt' | 'wildcard';
+    // The whole input with the suggestion applied
+    text: string;
+    label: string;
+    use_count: number;
+    last_used?: string | null;
+    score: number;
+}
+
+export function suggestPrompts(input: string, limit?: number): Promise<PromptSuggestion[]> {
+    return invoke<PromptSuggestion[]>('suggest_prompts', { input, limit });
+}
+
+export function getPromptHistory(limit?: number): Promise<PromptRecord[]> {
+    return invoke<PromptRecord[]>('get_prompt_history', { limit });
+}
+
+export function forgetPrompt(prompt: string): Promise<boolean> {
+    return invoke<boolean>('forget_prompt', { prompt });
+}
+
+export interface TagCount {
+    tag: string;
+    count: number;
+}
+
+// A named album of history entries, in the order they were added
+export interface Collection {
+    name: string;
+    description: string;
+    created: string;
+    entry_ids: string[];
+}
+
+export interface CollectionExport {
+    path: string;
+    entries: number;
+    files: number;
+    // Images and entries that no longer exist
+    missing: string[];
+}
+
+export const libraryActions = {
+    tag: (ids: string[], tags: string[]) => invoke<HistoryEntry[]>('tag_entries', { ids, tags }),
+    untag: (ids: string[], tags: string[]) => invoke<HistoryEntry[]>('untag_entries', { ids, tags }),
+    listTags: () => invoke<TagCount[]>('list_tags'),
+    setFavorite: (ids: string[], favorite: boolean) => invoke<HistoryEntry[]>('set_favorite', { ids, favorite }),
+    // null clears the rating
+    setRating: (ids: string[], rating: number | null) => invoke<HistoryEntry[]>('set_rating', { ids, rating }),
+    listCollections: () => invoke<Collection[]>('list_collections'),
+    createCollection: (name: string, description?: string) =>
+        invoke<Collection>('create_collection', { name, description }),
+    deleteCollection: (name: string) => invoke<void>('delete_collection', { name }),
+    addToCollection: (name: string, ids: string[]) => invoke<Collection>('add_to_collection', { name, ids }),
+    removeFromCollection: (name: string, ids: string[]) =>
+        invoke<Collection>('remove_from_collection', { name, ids }),
+    exportCollection: (name: string, destination: string, format: 'folder' | 'zip' = 'folder') =>
+        invoke<CollectionExport>('export_collection', { name, destination, format }),
+};
+
+export interface ImportOptions {
+    // The old DiffusionBee images folder and the Desktop when empty
+    folders?: string[];
+    recursive?: boolean;
+}
+
+export interface ImportReport {
+    folders: string[];
+    found: number;
+    entry_ids: string[];
+    with_metadata: number;
+    duplicates: number;
+    failed: string[];
+}
+
+// Payload of `import-progress` events, sent while importing and while the
+// thumbnails are made afterwards
+export interface ImportProgress {
+    stage: 'scanning' | 'importing' | 'thumbnails' | 'done';
+    done: number;
+    total: number;
+    path?: string | null;
+}
+
+export function importImages(options: ImportOptions = {}): Promise<ImportReport> {
+    return invoke<ImportReport>('import_images', { options });
+}
+
+export function regenerateFromHistory(historyId: string, compare = false): Promise<Regeneration> {
+    return invoke<Regeneration>('regenerate_from_history', { historyId, compare });
 }
 
 export interface GenerationState {
@@ -28,6 +396,7 @@ export interface GenerationState {
     progress: GenerationProgress;
     currentResult: GenerationResult | null;
     error: string | null;
+    errorDetails: CommandError | null;
     history: GenerationResult[];
 }
 
@@ -38,6 +407,24 @@ export interface AppSettings {
   default_guidance_scale: number;
   output_directory: string;
   model_path: string;
+  wildcards_directory: string;
+  api_server_enabled: boolean;
+  api_server_bind_address: string;
+  api_server_token: string;
+  mock_backend: boolean;
+  python_path: string;
+  backend_script_path: string;
+  filename_template: string;
+  output_subfolders: 'none' | 'day' | 'model' | 'day_model';
+  keep_backend_files: boolean;
+  output_format: 'png' | 'jpeg' | 'webp';
+  output_quality: number;
+  webp_lossless: boolean;
+  embed_metadata: boolean;
+  validation_overrides: ValidationOverrides;
+  post_processing: PostProcessStage[];
+  upscaler_applet: string;
+  upscaler_model_path: string;
 }
 
 // Default generation parameters
@@ -68,6 +455,7 @@ export const generationStore: Writable<GenerationState> = writable({
     // Results
     currentResult: null,
     error: null,
+    errorDetails: null,
     
     // History
     history: []
@@ -80,9 +468,30 @@ export const settingsStore: Writable<AppSettings> = writable({
     default_inference_steps: 20,
     default_guidance_scale: 7.5,
     output_directory: '',
-    model_path: ''
+    model_path: '',
+    wildcards_directory: '',
+    api_server_enabled: false,
+    api_server_bind_address: '127.0.0.1:7860',
+    api_server_token: '',
+    mock_backend: false,
+    python_path: '',
+    backend_script_path: '',
+    filename_template: '{date}_{time}_{prompt:30}_{seed}',
+    output_subfolders: 'none',
+    keep_backend_files: false,
+    output_format: 'png',
+    output_quality: 90,
+    webp_lossless: false,
+    embed_metadata: true,
+    validation_overrides: {},
+    post_processing: [],
+    upscaler_applet: 'model_upscale',
+    upscaler_model_path: ''
 });
 
+// Parameter limits of the active model, null until loaded with the settings
+export const parameterSchemaStore: Writable<ParameterSchema | null> = writable(null);
+
 // Actions
 export const generationActions = {
     // Update generation parameters
@@ -110,6 +519,7 @@ export const generationActions = {
             ...store,
             isGenerating: true,
             error: null,
+            errorDetails: null,
             progress: {
                 current_step: 0,
                 total_steps: store.params.num_inference_steps,
@@ -137,11 +547,12 @@ export const generationActions = {
     },
     
     // Set error
-    setError: (error: string) => {
+    setError: (error: string | CommandError) => {
         generationStore.update((store: GenerationState) => ({
             ...store,
             isGenerating: false,
-            error: error
+            error: errorMessage(error),
+            errorDetails: isCommandError(error) ? error : null
         }));
     },
     
@@ -152,6 +563,7 @@ export const generationActions = {
             params: { ...defaultParams },
             isGenerating: false,
             error: null,
+            errorDetails: null,
             progress: {
                 current_step: 0,
                 total_steps: 20,
@@ -168,6 +580,7 @@ export const settingsActions = {
         try {
             const settings = await invoke<AppSettings>('get_settings');
             settingsStore.set(settings);
+            await settingsActions.loadParameterSchema();
             
             // Also update the generation params with the loaded settings
             generationStore.update((store: GenerationState) => ({
@@ -190,12 +603,23 @@ export const settingsActions = {
         try {
             await invoke('save_settings', { settings });
             settingsStore.set(settings);
+            // A different model or overrides change the limits
+            await settingsActions.loadParameterSchema();
         } catch (error) {
             console.error('Failed to save settings:', error);
             throw error;
         }
     },
     
+    // Load the parameter limits for the active model
+    loadParameterSchema: async () => {
+        try {
+            parameterSchemaStore.set(await getParameterSchema());
+        } catch (error) {
+            console.error('Failed to load parameter schema:', error);
+        }
+    },
+    
     // Update a specific setting
     updateSetting: async (key: keyof AppSettings, value: number) => {
         settingsStore.update((settings: AppSettings) => {
@@ -217,7 +641,25 @@ export const settingsActions = {
                 default_inference_steps: 20,
                 default_guidance_scale: 7.5,
                 output_directory: '',
-                model_path: ''
+                model_path: '',
+                wildcards_directory: '',
+                api_server_enabled: false,
+                api_server_bind_address: '127.0.0.1:7860',
+                api_server_token: '',
+                mock_backend: false,
+                python_path: '',
+                backend_script_path: '',
+                filename_template: '{date}_{time}_{prompt:30}_{seed}',
+                output_subfolders: 'none',
+                keep_backend_files: false,
+                output_format: 'png',
+                output_quality: 90,
+                webp_lossless: false,
+                embed_metadata: true,
+                validation_overrides: {},
+                post_processing: [],
+                upscaler_applet: 'model_upscale',
+                upscaler_model_path: ''
             };
             
             await invoke('save_settings', { settings: defaultSettings });